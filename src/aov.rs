@@ -0,0 +1,158 @@
+use crate::hitable::HitRecord;
+use crate::vec3::Vec3;
+
+// Arbitrary output variables (AOVs) describe the surface seen by each camera ray. They are
+// accumulated alongside the rendered image so that they can be written out for compositing, and
+// are used as feature buffers to guide the denoiser.
+
+// Data about the first surface hit by a single camera ray.
+#[derive(Debug, Copy, Clone)]
+pub struct AovSample {
+    pub depth: f64,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+impl AovSample {
+    // depth is the distance along the ray to the hit, which differs from the hit's t value if the
+    // ray direction isn't a unit vector.
+    pub fn from_hit(hit: &HitRecord, depth: f64) -> AovSample {
+        AovSample {
+            depth,
+            position: hit.p,
+            normal: hit.shading_normal,
+            albedo: hit.material.albedo(hit),
+            object_id: hit.object_id,
+            material_id: hit.material_id,
+        }
+    }
+}
+
+// Accumulated AOVs for a pixel. Depth, position and normal are averaged over the samples that
+// hit a surface, while the albedo also includes the background colour seen by samples that
+// missed. Object and material IDs can't be averaged, so the first ID seen is kept.
+#[derive(Debug, Copy, Clone)]
+pub struct AovPixel {
+    pub samples: u64,
+    pub hits: u64,
+    depth_sum: f64,
+    position_sum: Vec3,
+    normal_sum: Vec3,
+    albedo_sum: Vec3,
+    pub object_id: Option<u32>,
+    pub material_id: Option<u32>,
+}
+
+impl AovPixel {
+
+    pub fn new() -> AovPixel {
+        AovPixel {
+            samples: 0,
+            hits: 0,
+            depth_sum: 0.0,
+            position_sum: Vec3::new(0.0, 0.0, 0.0),
+            normal_sum: Vec3::new(0.0, 0.0, 0.0),
+            albedo_sum: Vec3::new(0.0, 0.0, 0.0),
+            object_id: None,
+            material_id: None,
+        }
+    }
+
+    // Add a sample, where None means that the camera ray escaped and saw the background.
+    pub fn add_sample(&mut self, sample: Option<&AovSample>, background: Vec3) {
+        self.samples += 1;
+        match sample {
+            Some(sample) => {
+                self.hits += 1;
+                self.depth_sum += sample.depth;
+                self.position_sum = self.position_sum + sample.position;
+                self.normal_sum = self.normal_sum + sample.normal;
+                self.albedo_sum = self.albedo_sum + sample.albedo;
+                self.object_id = self.object_id.or(Some(sample.object_id));
+                self.material_id = self.material_id.or(Some(sample.material_id));
+            }
+            None => self.albedo_sum = self.albedo_sum + background,
+        }
+    }
+
+    pub fn merge(&mut self, other: &AovPixel) {
+        self.samples += other.samples;
+        self.hits += other.hits;
+        self.depth_sum += other.depth_sum;
+        self.position_sum = self.position_sum + other.position_sum;
+        self.normal_sum = self.normal_sum + other.normal_sum;
+        self.albedo_sum = self.albedo_sum + other.albedo_sum;
+        self.object_id = self.object_id.or(other.object_id);
+        self.material_id = self.material_id.or(other.material_id);
+    }
+
+    pub fn depth(&self) -> Option<f64> {
+        return if self.hits == 0 { None }
+        else { Some(self.depth_sum / self.hits as f64) };
+    }
+
+    pub fn position(&self) -> Option<Vec3> {
+        return if self.hits == 0 { None }
+        else { Some(self.position_sum / self.hits as f64) };
+    }
+
+    // The average normal, renormalised. Zero if no sample hit a surface.
+    pub fn normal(&self) -> Vec3 {
+        return if self.normal_sum.squared_length() == 0.0 { self.normal_sum }
+        else { self.normal_sum.unit_vector() };
+    }
+
+    pub fn albedo(&self) -> Vec3 {
+        return if self.samples == 0 { self.albedo_sum }
+        else { self.albedo_sum / self.samples as f64 };
+    }
+}
+
+impl Default for AovPixel {
+    fn default() -> Self { AovPixel::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(depth: f64, normal: Vec3, object_id: u32) -> AovSample {
+        AovSample {
+            depth,
+            position: Vec3::new(depth, 0.0, 0.0),
+            normal,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            object_id,
+            material_id: 7,
+        }
+    }
+
+    #[test]
+    fn test_depth_and_position_are_averaged_over_hits() {
+        let mut pixel = AovPixel::new();
+        pixel.add_sample(Some(&sample(1.0, Vec3::new(0.0, 1.0, 0.0), 1)), Vec3::new(1.0, 1.0, 1.0));
+        pixel.add_sample(Some(&sample(3.0, Vec3::new(0.0, 1.0, 0.0), 2)), Vec3::new(1.0, 1.0, 1.0));
+        pixel.add_sample(None, Vec3::new(1.0, 1.0, 1.0));
+
+        assert_eq!(pixel.depth(), Some(2.0));
+        assert_eq!(pixel.position(), Some(Vec3::new(2.0, 0.0, 0.0)));
+        assert_eq!(pixel.normal(), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(pixel.albedo(), Vec3::new(2.0, 2.0, 2.0) / 3.0);
+        assert_eq!(pixel.object_id, Some(1));
+        assert_eq!(pixel.material_id, Some(7));
+    }
+
+    #[test]
+    fn test_pixels_that_only_see_the_background_have_no_depth() {
+        let mut pixel = AovPixel::new();
+        pixel.add_sample(None, Vec3::new(0.5, 0.7, 1.0));
+
+        assert_eq!(pixel.depth(), None);
+        assert_eq!(pixel.normal(), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(pixel.albedo(), Vec3::new(0.5, 0.7, 1.0));
+        assert_eq!(pixel.object_id, None);
+    }
+}
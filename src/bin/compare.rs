@@ -0,0 +1,89 @@
+#![allow(clippy::needless_return)]
+
+use raytracer::compare::{compare, difference_image};
+use raytracer::output::{encode_rgba, read_png, write_rgba_png};
+
+fn usage() -> String {
+    return [
+        "Usage: compare <image> <reference> [options]",
+        "",
+        "Compare two PNG renders of the same size, printing the MSE, PSNR, SSIM and relative MSE.",
+        "",
+        "Options:",
+        "  --difference <file>  Also write a false colour image of the difference",
+        "  --help               Show this message",
+    ].join("\n");
+}
+
+struct Options {
+    image: String,
+    reference: String,
+    difference_file_name: Option<String>,
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut files = vec![];
+    let mut difference_file_name = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--difference" => difference_file_name = Some(args.next().ok_or(format!("Missing value for {}", arg))?),
+            "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.len() != 2 {
+        return Err(String::from("Expected an image and a reference"));
+    }
+    let reference = files.pop().unwrap();
+    let image = files.pop().unwrap();
+
+    return Ok(Options { image, reference, difference_file_name });
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let read = |file_name: &str| read_png(file_name).map_err(|e| format!("Unable to read {}: {}", file_name, e));
+    let (width, height, image) = read(&options.image)?;
+    let (reference_width, reference_height, reference) = read(&options.reference)?;
+    if (width, height) != (reference_width, reference_height) {
+        return Err(format!(
+            "Image is {}x{} but the reference is {}x{}", width, height, reference_width, reference_height
+        ));
+    }
+
+    let comparison = compare(&image, &reference, width, height);
+    println!("MSE:          {:.6}", comparison.mse);
+    println!("PSNR:         {:.2} dB", comparison.psnr);
+    println!("SSIM:         {:.4}", comparison.ssim);
+    println!("Relative MSE: {:.6}", comparison.relative_mse);
+
+    if let Some(ref file_name) = options.difference_file_name {
+        // The difference image is already in display space, so write it without gamma correction.
+        let data = encode_rgba(width, &difference_image(&image, &reference));
+        write_rgba_png(file_name, width, height, &data).map_err(|e| format!("Unable to write {}: {}", file_name, e))?;
+    }
+
+    return Ok(());
+}
+
+fn main() {
+    let options = match parse_options(std::env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(ref message) if message.is_empty() => {
+            println!("{}", usage());
+            return;
+        }
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, usage());
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(message) = run(&options) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+}
@@ -1,17 +1,36 @@
 use crate::vec3::Vec3;
 use crate::ray::Ray;
+use crate::spectrum::Wavelengths;
 use std::f64::consts::PI;
-use rand::prelude::*;
-use crate::hitable::sphere::random_point_in_unit_sphere;
+use crate::sampling::sample_unit_disk;
 
 pub struct Camera {
     origin: Vec3,
     u: Vec3,
     v: Vec3,
+    w: Vec3,
     lower_left_corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
-    lens_radius: f64
+    lens_radius: f64,
+    focus_distance: f64,
+    // Area of the image on a plane at unit distance in front of the camera.
+    image_area: f64,
+}
+
+// A point on the camera's lens that a point in the scene can be seen from, found by
+// Camera::sample_lens_towards.
+#[derive(Debug, Copy, Clone)]
+pub struct LensSample {
+    pub p: Vec3,
+    // Position of the point on the image, in the same coordinates as get_ray takes.
+    pub s: f64,
+    pub t: f64,
+    // The camera's importance for the ray from the lens point to the scene point.
+    pub importance: f64,
+    // Probability density of choosing the lens point, with respect to solid angle at the scene
+    // point.
+    pub pdf: f64,
 }
 
 impl Camera {
@@ -44,27 +63,122 @@ impl Camera {
             origin,
             u,
             v,
+            w,
             lower_left_corner,
             horizontal,
             vertical,
-            lens_radius
+            lens_radius,
+            focus_distance,
+            image_area: 4.0 * half_width * half_height,
         }
     }
 
-    fn random_point_in_unit_disk(&self) -> Vec3 {
-        let p = 2.8 * Vec3 { x: random::<f64>(), y: random::<f64>(), z: 0.0 } - Vec3 { x: 1.0, y: 1.0, z: 0.0 };
-        return if p.dot(&p) >= 1.0 { random_point_in_unit_sphere() }
-        else { p }
-    }
-
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * self.random_point_in_unit_disk();
+    // Generate a ray through the point (s, t) on the image plane, where lens_sample is a uniformly
+    // distributed sample used to choose the point on the lens that the ray starts from.
+    pub fn get_ray(&self, s: f64, t: f64, lens_sample: (f64, f64)) -> Ray {
+        let rd = self.lens_radius * sample_unit_disk(lens_sample);
         let offset = self.u * rd.x + self.v * rd.y;
 
         return Ray {
             origin: self.origin + offset,
-            direction: self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset
+            direction: self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            wavelengths: Wavelengths::Rgb,
         }
     }
 
-}
\ No newline at end of file
+    // Bidirectional integrators also need to connect points in the scene to the camera, which
+    // means treating the camera as a sensor with an importance function, the counterpart of a
+    // light's emission. Camera rays are spread evenly over the image, so for a ray at an angle
+    // theta to the viewing direction the importance is 1 / (A * L * cos^4(theta)), where A is the
+    // area of the image at unit distance and L is the area of the lens. This is normalised so that
+    // a light path connected to the camera gives an estimate for the whole image, which is spread
+    // over the pixels by dividing by the number of samples per pixel.
+
+    fn lens_area(&self) -> f64 {
+        return if self.lens_radius > 0.0 { PI * self.lens_radius * self.lens_radius } else { 1.0 };
+    }
+
+    // Cosine of the angle between a direction and the viewing direction.
+    fn cos_theta(&self, direction: Vec3) -> f64 {
+        -direction.unit_vector().dot(&self.w)
+    }
+
+    // Where a ray leaving the lens crosses the image, in the coordinates that get_ray takes, if it
+    // passes through the image.
+    pub fn image_position(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let cos_theta = self.cos_theta(ray.direction);
+        if cos_theta <= 0.0 { return None }
+        let distance = self.focus_distance / cos_theta;
+        let focus = ray.origin + distance * ray.direction.unit_vector() - self.lower_left_corner;
+        let s = focus.dot(&self.horizontal) / self.horizontal.squared_length();
+        let t = focus.dot(&self.vertical) / self.vertical.squared_length();
+        return if (0.0..1.0).contains(&s) && (0.0..1.0).contains(&t) { Some((s, t)) } else { None };
+    }
+
+    // Importance of a ray leaving the lens, which is zero if the ray doesn't pass through the image.
+    pub fn importance(&self, ray: &Ray) -> f64 {
+        if self.image_position(ray).is_none() { return 0.0 }
+        let cos_theta = self.cos_theta(ray.direction);
+        return 1.0 / (self.image_area * self.lens_area() * cos_theta.powi(4));
+    }
+
+    // Probability density, with respect to solid angle, of get_ray generating a ray in the given
+    // direction from its point on the lens.
+    pub fn pdf_direction(&self, ray: &Ray) -> f64 {
+        if self.image_position(ray).is_none() { return 0.0 }
+        return 1.0 / (self.image_area * self.cos_theta(ray.direction).powi(3));
+    }
+
+    // Choose a point on the lens to connect a point in the scene to, returning None if the scene
+    // point isn't in view.
+    pub fn sample_lens_towards(&self, p: Vec3, lens_sample: (f64, f64)) -> Option<LensSample> {
+        let rd = self.lens_radius * sample_unit_disk(lens_sample);
+        let lens_point = self.origin + self.u * rd.x + self.v * rd.y;
+        let ray = Ray { origin: lens_point, direction: p - lens_point, wavelengths: Wavelengths::Rgb };
+        let (s, t) = self.image_position(&ray)?;
+        let pdf = ray.direction.squared_length() / (self.cos_theta(ray.direction) * self.lens_area());
+        return Some(LensSample { p: lens_point, s, t, importance: self.importance(&ray), pdf });
+    }
+
+    // The direction the camera is looking in.
+    pub fn forward(&self) -> Vec3 {
+        -self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(aperture: f64) -> Camera {
+        Camera::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 40.0, 1.5, aperture, 4.0)
+    }
+
+    #[test]
+    fn test_image_position_is_where_get_ray_aimed() {
+        for aperture in [0.0, 0.5].iter() {
+            let camera = camera(*aperture);
+            let ray = camera.get_ray(0.25, 0.75, (0.3, 0.6));
+            let (s, t) = camera.image_position(&ray).unwrap();
+            assert!((s - 0.25).abs() < 1e-9 && (t - 0.75).abs() < 1e-9);
+            assert!(camera.image_position(&Ray { direction: -ray.direction, ..ray }).is_none());
+        }
+    }
+
+    #[test]
+    fn test_pdf_direction_integrates_to_one_over_the_image() {
+        // Integrate over the image at the focus distance, converting from area to solid angle.
+        let camera = camera(0.0);
+        let n = 200;
+        let mut total = 0.0;
+        let cell_area = camera.horizontal.length() * camera.vertical.length() / (n * n) as f64;
+        for j in 0..n {
+            for i in 0..n {
+                let ray = camera.get_ray((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64, (0.5, 0.5));
+                let cos_theta = camera.cos_theta(ray.direction);
+                total += camera.pdf_direction(&ray) * cell_area * cos_theta / ray.direction.squared_length();
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-3, "pdf integrates to {}", total);
+    }
+}
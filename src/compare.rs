@@ -0,0 +1,164 @@
+use crate::film::luminance;
+use crate::vec3::Vec3;
+
+// Metrics for comparing a render against a reference image of the same size. Images are given as
+// colours stored bottom row first, matching Film, and are compared channel by channel unless noted.
+
+#[derive(Debug, Copy, Clone)]
+pub struct Comparison {
+    // Mean squared error.
+    pub mse: f64,
+    // Peak signal to noise ratio in decibels, treating 1 as the peak value. Infinite for identical
+    // images.
+    pub psnr: f64,
+    // Mean structural similarity of the luminance, where 1 means the images are identical.
+    pub ssim: f64,
+    // Squared error relative to the squared reference value, which gives dark and bright regions
+    // similar importance.
+    pub relative_mse: f64,
+}
+
+// Avoids dividing by zero in the relative MSE of black reference pixels.
+const RELATIVE_MSE_EPSILON: f64 = 0.01;
+
+// Half the width of the window that SSIM statistics are gathered over.
+const SSIM_RADIUS: i64 = 3;
+// Constants that stabilise SSIM for dark and flat regions, from Wang et al.
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+
+fn channels(colour: Vec3) -> [f64; 3] {
+    [colour.r(), colour.g(), colour.b()]
+}
+
+pub fn mse(image: &[Vec3], reference: &[Vec3]) -> f64 {
+    let squared_error: f64 = image.iter().zip(reference.iter()).map(|(a, b)| (*a - *b).squared_length()).sum();
+    return squared_error / (3 * image.len()) as f64;
+}
+
+pub fn psnr(image: &[Vec3], reference: &[Vec3]) -> f64 {
+    return -10.0 * mse(image, reference).log10();
+}
+
+pub fn relative_mse(image: &[Vec3], reference: &[Vec3]) -> f64 {
+    let error: f64 = image.iter().zip(reference.iter()).map(|(a, b)| {
+        channels(*a).iter().zip(channels(*b).iter())
+            .map(|(a, b)| (a - b).powi(2) / (b * b + RELATIVE_MSE_EPSILON))
+            .sum::<f64>()
+    }).sum();
+    return error / (3 * image.len()) as f64;
+}
+
+// Structural similarity of the luminance of the two images, averaged over a window centred on
+// each pixel. Windows are clipped at the edges of the image.
+pub fn ssim(image: &[Vec3], reference: &[Vec3], width: usize, height: usize) -> f64 {
+    let a: Vec<f64> = image.iter().map(|c| luminance(*c)).collect();
+    let b: Vec<f64> = reference.iter().map(|c| luminance(*c)).collect();
+
+    let mut total = 0.0;
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let (mut n, mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+            for wy in (y - SSIM_RADIUS).max(0)..(y + SSIM_RADIUS + 1).min(height as i64) {
+                for wx in (x - SSIM_RADIUS).max(0)..(x + SSIM_RADIUS + 1).min(width as i64) {
+                    let index = wy as usize * width + wx as usize;
+                    n += 1.0;
+                    sum_a += a[index];
+                    sum_b += b[index];
+                    sum_aa += a[index] * a[index];
+                    sum_bb += b[index] * b[index];
+                    sum_ab += a[index] * b[index];
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let variance_a = sum_aa / n - mean_a * mean_a;
+            let variance_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (variance_a + variance_b + SSIM_C2));
+        }
+    }
+
+    return total / (width * height) as f64;
+}
+
+pub fn compare(image: &[Vec3], reference: &[Vec3], width: usize, height: usize) -> Comparison {
+    assert_eq!(image.len(), width * height, "image doesn't match the given size");
+    assert_eq!(reference.len(), width * height, "reference doesn't match the given size");
+    Comparison {
+        mse: mse(image, reference),
+        psnr: psnr(image, reference),
+        ssim: ssim(image, reference, width, height),
+        relative_mse: relative_mse(image, reference),
+    }
+}
+
+// Map the difference between two colours onto a false colour scale running from black for no
+// difference, through blue and magenta, to white for a difference of at least scale.
+fn difference_colour(difference: f64, scale: f64) -> Vec3 {
+    let v = (difference / scale).clamp(0.0, 1.0) * 3.0;
+    Vec3::new((v - 1.0).clamp(0.0, 1.0), (v - 2.0).clamp(0.0, 1.0), v.min(1.0))
+}
+
+// False colour image of the per pixel error, scaled so that the largest difference is white.
+pub fn difference_image(image: &[Vec3], reference: &[Vec3]) -> Vec<Vec3> {
+    let differences: Vec<f64> = image.iter().zip(reference.iter()).map(|(a, b)| (*a - *b).length()).collect();
+    let maximum = differences.iter().cloned().fold(0.0, f64::max).max(f64::MIN_POSITIVE);
+    return differences.iter().map(|d| difference_colour(*d, maximum)).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Vec<Vec3> {
+        (0..width * height).map(|i| {
+            let v = ((i % width) + (i / width)) as f64 / (width + height) as f64;
+            Vec3::new(v, 0.5 * v, 1.0 - v)
+        }).collect()
+    }
+
+    #[test]
+    fn test_identical_images_compare_as_identical() {
+        let image = gradient(16, 8);
+        let comparison = compare(&image, &image, 16, 8);
+        assert_eq!(comparison.mse, 0.0);
+        assert_eq!(comparison.psnr, f64::INFINITY);
+        assert!((comparison.ssim - 1.0).abs() < 1e-9);
+        assert_eq!(comparison.relative_mse, 0.0);
+    }
+
+    #[test]
+    fn test_mse_and_psnr_of_a_constant_offset() {
+        let reference = gradient(16, 8);
+        let image: Vec<Vec3> = reference.iter().map(|c| *c + Vec3::new(0.1, 0.1, 0.1)).collect();
+        let comparison = compare(&image, &reference, 16, 8);
+        assert!((comparison.mse - 0.01).abs() < 1e-12);
+        assert!((comparison.psnr - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ssim_drops_as_noise_is_added() {
+        let reference = gradient(16, 16);
+        let noisy = |amount: f64| -> Vec<Vec3> {
+            reference.iter().enumerate().map(|(i, c)| {
+                let sign = if (i * 7919) % 3 == 0 { 1.0 } else { -0.5 };
+                *c + amount * sign * Vec3::new(1.0, 1.0, 1.0)
+            }).collect()
+        };
+        let slightly_noisy = ssim(&noisy(0.02), &reference, 16, 16);
+        let very_noisy = ssim(&noisy(0.2), &reference, 16, 16);
+        assert!(slightly_noisy < 1.0);
+        assert!(very_noisy < slightly_noisy);
+    }
+
+    #[test]
+    fn test_difference_image_is_black_where_images_match() {
+        let reference = gradient(4, 4);
+        let mut image = reference.clone();
+        image[5] = image[5] + Vec3::new(0.5, 0.0, 0.0);
+        let difference = difference_image(&image, &reference);
+        assert_eq!(difference[0], Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(difference[5], Vec3::new(1.0, 1.0, 1.0));
+    }
+}
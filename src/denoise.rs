@@ -0,0 +1,181 @@
+use crate::aov::AovPixel;
+use crate::film::Film;
+use crate::vec3::Vec3;
+
+// Post-process denoisers that smooth the noise left in low sample count renders. They use the
+// film's AOVs as feature buffers, which are far less noisy than the image itself, to avoid
+// blurring across the edges of objects.
+#[derive(Debug, Copy, Clone)]
+pub enum Denoiser {
+    // The edge-avoiding À-trous wavelet filter from Dammertz et al. A small kernel is applied
+    // repeatedly with its taps spread further apart each time, so a large area is covered cheaply.
+    // Each tap is weighted by how similar its colour, normal, depth and albedo are to the centre.
+    ATrous { iterations: u32, colour_sigma: f64, normal_sigma: f64, depth_sigma: f64, albedo_sigma: f64 },
+}
+
+// B3 spline weights, which approximate a Gaussian when applied repeatedly.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Avoid dividing by zero when removing the albedo from black surfaces.
+const MINIMUM_ALBEDO: f64 = 0.01;
+
+impl Denoiser {
+
+    pub fn a_trous() -> Denoiser {
+        Denoiser::ATrous { iterations: 5, colour_sigma: 1.0, normal_sigma: 0.3, depth_sigma: 0.05, albedo_sigma: 0.1 }
+    }
+
+    // Return the denoised colour of each pixel of the film, in the same order as film.pixels.
+    // Films without AOVs have nothing to guide the filter, so their colours are returned as is.
+    pub fn denoise(&self, film: &Film) -> Vec<Vec3> {
+        let colours = film.colours();
+        let aovs = match film.aovs {
+            Some(ref aovs) => aovs,
+            None => return colours,
+        };
+
+        match *self {
+            Denoiser::ATrous { iterations, colour_sigma, normal_sigma, depth_sigma, albedo_sigma } => {
+                let features: Vec<Features> = aovs.iter().map(Features::new).collect();
+
+                // Filter the illumination rather than the colour, so that the detail in the
+                // albedo isn't blurred, and multiply the albedo back in afterwards.
+                let mut illumination: Vec<Vec3> = colours.iter().zip(features.iter())
+                    .map(|(colour, f)| *colour / f.demodulation_albedo())
+                    .collect();
+
+                for iteration in 0..iterations {
+                    let step = 1 << iteration;
+                    // Narrow the colour weight each iteration, as the image has become smoother.
+                    let colour_sigma = colour_sigma / (1 << iteration) as f64;
+                    illumination = (0..illumination.len()).map(|index| {
+                        let (x, y) = (index % film.width, index / film.width);
+                        let centre = &features[index];
+                        let centre_colour = illumination[index];
+                        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+                        let mut weight_sum = 0.0;
+
+                        for (j, ky) in KERNEL.iter().enumerate() {
+                            for (i, kx) in KERNEL.iter().enumerate() {
+                                let qx = x as i64 + (i as i64 - 2) * step;
+                                let qy = y as i64 + (j as i64 - 2) * step;
+                                if qx < 0 || qy < 0 || qx >= film.width as i64 || qy >= film.height as i64 {
+                                    continue;
+                                }
+                                let q = qy as usize * film.width + qx as usize;
+                                let other = &features[q];
+                                let colour = illumination[q];
+
+                                let colour_distance = (colour - centre_colour).squared_length();
+                                let weight = kx * ky
+                                    * (-colour_distance / (colour_sigma * colour_sigma)).exp()
+                                    * centre.weight(other, normal_sigma, depth_sigma, albedo_sigma);
+                                sum = sum + weight * colour;
+                                weight_sum += weight;
+                            }
+                        }
+
+                        // The centre tap always has a positive weight.
+                        sum / weight_sum
+                    }).collect();
+                }
+
+                return illumination.iter().zip(features.iter())
+                    .map(|(illumination, f)| *illumination * f.demodulation_albedo())
+                    .collect();
+            }
+        }
+    }
+}
+
+// The AOVs of a pixel used to guide the filter.
+struct Features {
+    normal: Vec3,
+    depth: Option<f64>,
+    albedo: Vec3,
+}
+
+impl Features {
+
+    fn new(aov: &AovPixel) -> Features {
+        Features { normal: aov.normal(), depth: aov.depth(), albedo: aov.albedo() }
+    }
+
+    fn demodulation_albedo(&self) -> Vec3 {
+        Vec3::new(self.albedo.x.max(MINIMUM_ALBEDO), self.albedo.y.max(MINIMUM_ALBEDO), self.albedo.z.max(MINIMUM_ALBEDO))
+    }
+
+    // Edge-stopping weight between two pixels. Depths are compared relative to the centre's depth
+    // so that the same setting works for near and far surfaces. Surfaces never blend with the
+    // background.
+    fn weight(&self, other: &Features, normal_sigma: f64, depth_sigma: f64, albedo_sigma: f64) -> f64 {
+        let depth_weight = match (self.depth, other.depth) {
+            (Some(a), Some(b)) => (-(a - b).abs() / (depth_sigma * a.max(f64::MIN_POSITIVE))).exp(),
+            (None, None) => 1.0,
+            _ => return 0.0,
+        };
+        let normal_distance = (self.normal - other.normal).squared_length();
+        let albedo_distance = (self.albedo - other.albedo).squared_length();
+        return depth_weight
+            * (-normal_distance / (normal_sigma * normal_sigma)).exp()
+            * (-albedo_distance / (albedo_sigma * albedo_sigma)).exp();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::compare::psnr;
+    use crate::filter::Filter;
+    use crate::hitable::Hitable;
+    use crate::material::Material;
+    use crate::progress::QuietProgress;
+    use crate::integrator::path::PathSettings;
+    use crate::integrator::Integrator;
+    use crate::render::{render_pass, RenderSettings};
+    use crate::sampler::Sampler;
+    use crate::scene::Scene;
+
+    fn render(film: &mut Film, samples: u64, seed: u64) {
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::lambertian(0.8, 0.3, 0.3)),
+            Hitable::sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::lambertian(0.5, 0.5, 0.5)),
+        ]);
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0
+        );
+        let settings = RenderSettings { sampler: Sampler::independent(seed), filter: Filter::box_filter(0.5), adaptive: None, denoiser: None, integrator: Integrator::path(PathSettings::default()), spectral: false };
+        render_pass(&Scene::new(world, camera), film, &settings, samples, &QuietProgress);
+    }
+
+    #[test]
+    fn test_a_trous_denoising_improves_psnr_against_a_reference() {
+        let mut reference = Film::new(32, 32);
+        render(&mut reference, 256, 1);
+        let reference: Vec<Vec3> = reference.colours();
+
+        let mut film = Film::with_aovs(32, 32);
+        render(&mut film, 4, 2);
+        let noisy = film.colours();
+        let denoised = Denoiser::a_trous().denoise(&film);
+
+        let noisy_psnr = psnr(&noisy, &reference);
+        let denoised_psnr = psnr(&denoised, &reference);
+        assert!(denoised_psnr > noisy_psnr + 3.0, "noisy {} dB, denoised {} dB", noisy_psnr, denoised_psnr);
+    }
+
+    #[test]
+    fn test_films_without_aovs_are_not_denoised() {
+        let mut film = Film::new(8, 8);
+        render(&mut film, 2, 0);
+        let colours = film.colours();
+        assert!(Denoiser::a_trous().denoise(&film) == colours);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::aov::{AovPixel, AovSample};
+use crate::filter::Filter;
+use crate::vec3::Vec3;
+
+// Running statistics for a single pixel.
+// Colour samples taken within the pixel are summed so the average can be taken at any point
+// during a render, and the mean and variance of the sample luminance are tracked using Welford's
+// algorithm so that we can estimate how noisy the pixel still is.
+// Separately, the weighted sum of every sample that falls within the reconstruction filter
+// centred on this pixel is accumulated, which includes samples taken in neighbouring pixels.
+#[derive(Debug, Copy, Clone)]
+pub struct Pixel {
+    pub sum: Vec3,
+    pub samples: u64,
+    pub weighted_sum: Vec3,
+    pub weight_sum: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Pixel {
+
+    pub fn new() -> Pixel {
+        Pixel {
+            sum: Vec3::new(0.0, 0.0, 0.0),
+            samples: 0,
+            weighted_sum: Vec3::new(0.0, 0.0, 0.0),
+            weight_sum: 0.0,
+            mean: 0.0,
+            m2: 0.0
+        }
+    }
+
+    pub fn add_sample(&mut self, colour: Vec3) {
+        self.sum = self.sum + colour;
+        self.samples += 1;
+
+        let value = luminance(colour);
+        let delta = value - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Add the contribution of a sample to the filtered value of this pixel.
+    pub fn add_weighted_sample(&mut self, colour: Vec3, weight: f64) {
+        self.weighted_sum = self.weighted_sum + weight * colour;
+        self.weight_sum += weight;
+    }
+
+    // Combine the samples accumulated in another pixel into this one, using Chan et al.'s
+    // method of merging the luminance statistics.
+    pub fn merge(&mut self, other: &Pixel) {
+        self.weighted_sum = self.weighted_sum + other.weighted_sum;
+        self.weight_sum += other.weight_sum;
+
+        if other.samples == 0 { return }
+
+        let samples = self.samples + other.samples;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.samples as f64 / samples as f64;
+        self.m2 += other.m2 + delta * delta * (self.samples as f64 * other.samples as f64) / samples as f64;
+        self.sum = self.sum + other.sum;
+        self.samples = samples;
+    }
+
+    // The average of all samples taken within the pixel so far.
+    pub fn average(&self) -> Vec3 {
+        return if self.samples == 0 { Vec3::new(0.0, 0.0, 0.0) }
+        else { self.sum / self.samples as f64 };
+    }
+
+    // The reconstructed value of the pixel, falling back to the plain average when the filtered
+    // samples have too little weight to divide by. Filters with negative lobes can leave the
+    // weight negative or close to zero, which would give huge or sign-flipped colours.
+    pub fn colour(&self) -> Vec3 {
+        return if self.weight_sum <= MINIMUM_WEIGHT_SUM { self.average() }
+        else { self.weighted_sum / self.weight_sum };
+    }
+
+    // Unbiased estimate of the variance of the sample luminance.
+    pub fn variance(&self) -> f64 {
+        return if self.samples < 2 { 0.0 }
+        else { self.m2 / (self.samples - 1) as f64 };
+    }
+
+    // Estimated error of the pixel value, computed as the standard error of the mean luminance
+    // relative to the square root of the mean. Normalising by the square root rather than the
+    // mean itself roughly matches the gamma applied on output, so dark pixels aren't penalised
+    // for noise that would never be visible.
+    pub fn error(&self) -> f64 {
+        if self.samples < 2 { return f64::INFINITY }
+        let standard_error = (self.variance() / self.samples as f64).sqrt();
+        return standard_error / self.mean.max(MINIMUM_LUMINANCE).sqrt();
+    }
+}
+
+impl Default for Pixel {
+    fn default() -> Self { Pixel::new() }
+}
+
+// Avoid dividing by zero when computing the relative error of black pixels.
+const MINIMUM_LUMINANCE: f64 = 0.001;
+
+// Smallest total filter weight that a pixel's colour is reconstructed from.
+const MINIMUM_WEIGHT_SUM: f64 = 0.001;
+
+// Relative luminance of a linear RGB colour using the Rec. 709 weights.
+pub fn luminance(colour: Vec3) -> f64 {
+    0.2126 * colour.r() + 0.7152 * colour.g() + 0.0722 * colour.b()
+}
+
+// Accumulation buffer for a render. Rows are stored bottom to top, matching the orientation of
+// the camera's v coordinate.
+#[derive(Clone)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Pixel>,
+    // Per pixel AOVs, which are only accumulated if the film was created with them.
+    pub aovs: Option<Vec<AovPixel>>,
+    // Sum of the contributions that paths traced from lights make to each pixel. These don't
+    // belong to any one pixel sample, so they're kept apart from the pixel statistics.
+    pub splats: Vec<Vec3>,
+    // Number of samples that only contributed splats, such as the mutations of Metropolis light
+    // transport, which aren't counted in any pixel.
+    pub splat_samples: u64,
+}
+
+impl Film {
+
+    pub fn new(width: usize, height: usize) -> Film {
+        Film { width, height, pixels: vec![Pixel::new(); width * height], aovs: None, splats: vec![Vec3::new(0.0, 0.0, 0.0); width * height], splat_samples: 0 }
+    }
+
+    // Create a film that also accumulates AOVs for each pixel.
+    pub fn with_aovs(width: usize, height: usize) -> Film {
+        Film { aovs: Some(vec![AovPixel::new(); width * height]), ..Film::new(width, height) }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> &Pixel {
+        &self.pixels[y * self.width + x]
+    }
+
+    // Number of samples taken in each pixel, which varies across the film if adaptive sampling is
+    // used or a render was cancelled.
+    pub fn sample_counts(&self) -> Vec<u64> {
+        self.pixels.iter().map(|p| p.samples).collect()
+    }
+
+    // Split the film into square tiles of the given size, clipped to the edges of the film.
+    pub fn tiles(&self, size: usize) -> Vec<Bounds> {
+        let mut tiles = vec![];
+        for y in (0..self.height).step_by(size) {
+            for x in (0..self.width).step_by(size) {
+                tiles.push(Bounds { x0: x, y0: y, x1: (x + size).min(self.width), y1: (y + size).min(self.height) });
+            }
+        }
+        return tiles;
+    }
+
+    // Create an empty tile for rendering the pixels within bounds. The tile also covers the
+    // surrounding pixels that samples can contribute to through the filter.
+    pub fn tile(&self, bounds: Bounds, filter: Filter) -> FilmTile {
+        let margin = ((filter.radius() + 0.5).ceil() as usize).saturating_sub(1);
+        let extended = Bounds {
+            x0: bounds.x0.saturating_sub(margin),
+            y0: bounds.y0.saturating_sub(margin),
+            x1: (bounds.x1 + margin).min(self.width),
+            y1: (bounds.y1 + margin).min(self.height),
+        };
+        return FilmTile {
+            bounds,
+            extended_bounds: extended,
+            filter,
+            pixels: vec![Pixel::new(); extended.width() * extended.height()],
+            aovs: self.aovs.as_ref().map(|_| vec![AovPixel::new(); bounds.width() * bounds.height()]),
+            width: self.width,
+            height: self.height,
+            splats: vec![],
+            splat_samples: 0,
+        };
+    }
+
+    // Add the samples accumulated in a tile into the film.
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        let bounds = tile.extended_bounds;
+        for y in bounds.y0..bounds.y1 {
+            for x in bounds.x0..bounds.x1 {
+                self.pixels[y * self.width + x].merge(tile.pixel(x, y));
+            }
+        }
+
+        if let (Some(aovs), Some(tile_aovs)) = (self.aovs.as_mut(), tile.aovs.as_ref()) {
+            let bounds = tile.bounds;
+            for y in bounds.y0..bounds.y1 {
+                for x in bounds.x0..bounds.x1 {
+                    aovs[y * self.width + x].merge(&tile_aovs[(y - bounds.y0) * bounds.width() + (x - bounds.x0)]);
+                }
+            }
+        }
+
+        for (index, colour) in tile.splats.iter() {
+            self.splats[*index] = self.splats[*index] + *colour;
+        }
+        self.splat_samples += tile.splat_samples;
+    }
+
+    // The colour of each pixel. Splats are estimates for the whole image from every sample taken,
+    // so they're scaled by the number of pixels over the number of samples to give the
+    // contribution per pixel.
+    pub fn colours(&self) -> Vec<Vec3> {
+        let total_samples = self.total_samples() + self.splat_samples;
+        let scale = if total_samples == 0 { 0.0 } else { self.pixels.len() as f64 / total_samples as f64 };
+        return self.pixels.iter().zip(self.splats.iter()).map(|(p, splat)| p.colour() + scale * *splat).collect();
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|p| p.samples).sum()
+    }
+
+    // Average error over all pixels in the film. See Pixel::error.
+    pub fn noise(&self) -> f64 {
+        self.pixels.iter().map(|p| p.error()).sum::<f64>() / self.pixels.len() as f64
+    }
+}
+
+// A rectangular region of pixels, from (x0, y0) up to but excluding (x1, y1).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Bounds {
+    pub fn width(&self) -> usize { self.x1 - self.x0 }
+    pub fn height(&self) -> usize { self.y1 - self.y0 }
+}
+
+// Accumulates the samples for one tile of a render so that tiles can be rendered in parallel and
+// merged into the film afterwards.
+pub struct FilmTile {
+    // Pixels that samples are taken from.
+    pub bounds: Bounds,
+    // Pixels that samples can contribute to.
+    pub extended_bounds: Bounds,
+    filter: Filter,
+    pixels: Vec<Pixel>,
+    aovs: Option<Vec<AovPixel>>,
+    // Size of the whole film, since splats can land anywhere on it.
+    width: usize,
+    height: usize,
+    // Splats added to the tile, as the index of the pixel and the colour.
+    splats: Vec<(usize, Vec3)>,
+    splat_samples: u64,
+}
+
+impl FilmTile {
+
+    pub fn pixel(&self, x: usize, y: usize) -> &Pixel {
+        let bounds = self.extended_bounds;
+        &self.pixels[(y - bounds.y0) * bounds.width() + (x - bounds.x0)]
+    }
+
+    fn pixel_mut(&mut self, x: usize, y: usize) -> &mut Pixel {
+        let bounds = self.extended_bounds;
+        &mut self.pixels[(y - bounds.y0) * bounds.width() + (x - bounds.x0)]
+    }
+
+    // Whether the film this tile belongs to is accumulating AOVs.
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    // Add the AOVs for a sample taken within the pixel (x, y). A sample of None means the camera
+    // ray escaped, in which case background is used as the albedo.
+    pub fn add_aov_sample(&mut self, x: usize, y: usize, sample: Option<&AovSample>, background: Vec3) {
+        let bounds = self.bounds;
+        if let Some(aovs) = self.aovs.as_mut() {
+            aovs[(y - bounds.y0) * bounds.width() + (x - bounds.x0)].add_sample(sample, background);
+        }
+    }
+
+    // Add a splat at the position (x, y) on the film, measured in pixels. Unlike samples, splats
+    // aren't filtered and can land on any pixel of the film, not just those in the tile.
+    pub fn add_splat(&mut self, x: f64, y: f64, colour: Vec3) {
+        if x < 0.0 || y < 0.0 { return }
+        let (i, j) = (x as usize, y as usize);
+        if i < self.width && j < self.height {
+            self.splats.push((j * self.width + i, colour));
+        }
+    }
+
+    // Count samples that only contributed splats. See Film::splat_samples.
+    pub fn add_splat_samples(&mut self, count: u64) {
+        self.splat_samples += count;
+    }
+
+    // Add a sample taken at the position (x, y) on the film, measured in pixels, where the sample
+    // was taken within the pixel (pixel_x, pixel_y). The sample is weighted by the filter and
+    // added to every pixel whose centre is within the filter radius.
+    pub fn add_sample(&mut self, pixel_x: usize, pixel_y: usize, x: f64, y: f64, colour: Vec3) {
+        self.pixel_mut(pixel_x, pixel_y).add_sample(colour);
+
+        let radius = self.filter.radius();
+        let bounds = self.extended_bounds;
+        // Range of pixels whose centres are strictly within the radius of the sample.
+        let x0 = ((x - radius - 0.5).floor() as i64 + 1).max(bounds.x0 as i64);
+        let y0 = ((y - radius - 0.5).floor() as i64 + 1).max(bounds.y0 as i64);
+        let x1 = ((x + radius - 0.5).ceil() as i64).min(bounds.x1 as i64);
+        let y1 = ((y + radius - 0.5).ceil() as i64).min(bounds.y1 as i64);
+
+        for j in (y0..y1).map(|j| j as usize) {
+            for i in (x0..x1).map(|i| i as usize) {
+                let weight = self.filter.evaluate(i as f64 + 0.5 - x, j as f64 + 0.5 - y);
+                if weight != 0.0 {
+                    self.pixel_mut(i, j).add_weighted_sample(colour, weight);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colour_is_the_average_of_all_samples() {
+        let mut pixel = Pixel::new();
+        pixel.add_sample(Vec3::new(1.0, 0.0, 0.0));
+        pixel.add_sample(Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(pixel.colour(), Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(pixel.samples, 2);
+    }
+
+    #[test]
+    fn test_variance_is_zero_for_identical_samples() {
+        let mut pixel = Pixel::new();
+        (0..10).for_each(|_| pixel.add_sample(Vec3::new(0.5, 0.5, 0.5)));
+        assert_eq!(pixel.variance(), 0.0);
+        assert_eq!(pixel.error(), 0.0);
+    }
+
+    #[test]
+    fn test_variance_matches_the_sample_variance_of_the_luminance() {
+        let mut pixel = Pixel::new();
+        pixel.add_sample(Vec3::new(0.0, 0.0, 0.0));
+        pixel.add_sample(Vec3::new(1.0, 1.0, 1.0));
+        assert!((pixel.variance() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_error_decreases_as_samples_are_added() {
+        let mut pixel = Pixel::new();
+        let mut errors = vec![];
+        for i in 0..100 {
+            pixel.add_sample(Vec3::new((i % 2) as f64, (i % 2) as f64, (i % 2) as f64));
+            errors.push(pixel.error());
+        }
+        assert!(errors[99] < errors[9]);
+    }
+
+    #[test]
+    fn test_merge_combines_the_statistics_of_two_pixels() {
+        let samples: Vec<Vec3> = (0..10).map(|i| Vec3::new(i as f64 / 10.0, 0.5, (i * i) as f64 / 100.0)).collect();
+        let mut all = Pixel::new();
+        samples.iter().for_each(|s| all.add_sample(*s));
+
+        let mut first = Pixel::new();
+        let mut second = Pixel::new();
+        samples[..3].iter().for_each(|s| first.add_sample(*s));
+        samples[3..].iter().for_each(|s| second.add_sample(*s));
+        first.merge(&second);
+
+        assert_eq!(first.samples, all.samples);
+        assert!((first.variance() - all.variance()).abs() < 1e-12);
+        assert!((first.average() - all.average()).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_tiles_cover_the_film() {
+        let film = Film::new(10, 7);
+        let tiles = film.tiles(4);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles.iter().map(|t| t.width() * t.height()).sum::<usize>(), 70);
+        assert_eq!(tiles[5], Bounds { x0: 8, y0: 4, x1: 10, y1: 7 });
+    }
+
+    #[test]
+    fn test_box_filter_with_half_pixel_radius_only_contributes_to_its_own_pixel() {
+        let mut film = Film::new(3, 3);
+        let mut tile = film.tile(Bounds { x0: 1, y0: 1, x1: 2, y1: 2 }, Filter::box_filter(0.5));
+        tile.add_sample(1, 1, 1.99, 1.01, Vec3::new(1.0, 1.0, 1.0));
+        film.merge_tile(&tile);
+
+        assert_eq!(film.pixel(1, 1).colour(), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixels.iter().filter(|p| p.weight_sum != 0.0).count(), 1);
+    }
+
+    #[test]
+    fn test_wider_filters_contribute_to_neighbouring_pixels() {
+        let mut film = Film::new(5, 5);
+        let mut tile = film.tile(Bounds { x0: 2, y0: 2, x1: 3, y1: 3 }, Filter::tent(1.5));
+        tile.add_sample(2, 2, 2.5, 2.5, Vec3::new(1.0, 1.0, 1.0));
+        film.merge_tile(&tile);
+
+        // The tent reaches the centres of the eight neighbouring pixels but not beyond.
+        assert_eq!(film.pixels.iter().filter(|p| p.weight_sum != 0.0).count(), 9);
+        assert_eq!(film.pixel(2, 2).weight_sum, 1.5 * 1.5);
+        assert_eq!(film.pixel(1, 2).weight_sum, 0.5 * 1.5);
+        assert_eq!(film.pixel(2, 2).samples, 1);
+        assert_eq!(film.pixel(1, 2).samples, 0);
+    }
+
+    #[test]
+    fn test_pixels_only_reached_by_a_negative_lobe_fall_back_to_their_average() {
+        let mut film = Film::new(5, 5);
+        let mut tile = film.tile(Bounds { x0: 2, y0: 2, x1: 3, y1: 3 }, Filter::lanczos(3.0));
+        tile.add_sample(2, 2, 2.0, 2.5, Vec3::new(1.0, 1.0, 1.0));
+        film.merge_tile(&tile);
+
+        // The centre of the pixel to the right is 1.5 pixels from the sample, in the first
+        // negative lobe. It has no samples of its own, so it stays black.
+        assert!(film.pixel(3, 2).weight_sum < 0.0);
+        assert_eq!(film.pixel(3, 2).colour(), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(film.pixel(2, 2).colour(), Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_splats_are_scaled_by_the_number_of_pixels_over_the_number_of_samples() {
+        let mut film = Film::new(2, 2);
+        let mut tile = film.tile(Bounds { x0: 0, y0: 0, x1: 1, y1: 1 }, Filter::box_filter(0.5));
+        (0..8).for_each(|_| tile.add_sample(0, 0, 0.5, 0.5, Vec3::new(0.0, 0.0, 0.0)));
+        // Splats can land outside of the tile, but not outside of the film.
+        tile.add_splat(1.5, 1.5, Vec3::new(1.0, 1.0, 1.0));
+        tile.add_splat(2.5, 0.5, Vec3::new(1.0, 1.0, 1.0));
+        film.merge_tile(&tile);
+
+        let colours = film.colours();
+        assert_eq!(colours[3], Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!(colours[0] + colours[1] + colours[2], Vec3::new(0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,130 @@
+use std::f64::consts::PI;
+
+// Reconstruction filters used to weight the contribution of each sample to the pixels around it.
+// Each filter is separable and is zero beyond its radius, measured in pixels from the centre of
+// the pixel being reconstructed. A box filter with a radius of half a pixel gives the plain
+// average of the samples taken within each pixel.
+#[derive(Debug, Copy, Clone)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, sigma: f64 },
+    // Mitchell and Netravali's cubic filter, parameterised by b and c.
+    Mitchell { radius: f64, b: f64, c: f64 },
+    // A sinc filter windowed by a wider sinc lobe that reaches zero at the radius.
+    Lanczos { radius: f64 },
+}
+
+fn sinc(x: f64) -> f64 {
+    return if x.abs() < 1e-5 { 1.0 }
+    else { (PI * x).sin() / (PI * x) };
+}
+
+// One dimensional Mitchell-Netravali filter, defined over [-2, 2].
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    return if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2) + (6.0 - 2.0 * b)) / 6.0
+    }
+    else if x < 2.0 {
+        ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x.powi(2) + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    }
+    else { 0.0 };
+}
+
+impl Filter {
+
+    pub fn box_filter(radius: f64) -> Filter {
+        Filter::Box { radius }
+    }
+
+    pub fn tent(radius: f64) -> Filter {
+        Filter::Tent { radius }
+    }
+
+    // The standard deviation is chosen so that the filter has fallen to around 1% at the radius.
+    pub fn gaussian(radius: f64) -> Filter {
+        Filter::Gaussian { radius, sigma: radius / 3.0 }
+    }
+
+    // Uses the b = c = 1/3 parameters recommended by Mitchell and Netravali.
+    pub fn mitchell(radius: f64) -> Filter {
+        Filter::Mitchell { radius, b: 1.0 / 3.0, c: 1.0 / 3.0 }
+    }
+
+    pub fn lanczos(radius: f64) -> Filter {
+        Filter::Lanczos { radius }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius } => radius,
+            Filter::Tent { radius } => radius,
+            Filter::Gaussian { radius, .. } => radius,
+            Filter::Mitchell { radius, .. } => radius,
+            Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    // Evaluate the filter along one axis at an offset of x pixels from its centre.
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x >= self.radius() { return 0.0 }
+
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            // Subtract the value at the radius so the filter goes smoothly to zero.
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |d: f64| (-d * d / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(radius)
+            }
+            Filter::Mitchell { radius, b, c } => mitchell(2.0 * x / radius, b, c),
+            Filter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+
+    // Evaluate the filter at an offset of (x, y) pixels from its centre. Note that the Mitchell and
+    // Lanczos filters have negative lobes, so the result may be negative.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_filters(radius: f64) -> Vec<Filter> {
+        vec![
+            Filter::box_filter(radius),
+            Filter::tent(radius),
+            Filter::gaussian(radius),
+            Filter::mitchell(radius),
+            Filter::lanczos(radius),
+        ]
+    }
+
+    #[test]
+    fn test_filters_are_zero_beyond_their_radius() {
+        for filter in all_filters(1.5) {
+            assert_eq!(filter.evaluate(1.5, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, -2.0), 0.0);
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_filters_are_symmetric() {
+        for filter in all_filters(2.0) {
+            assert_eq!(filter.evaluate(0.3, 0.7), filter.evaluate(-0.3, -0.7));
+            assert_eq!(filter.evaluate(0.3, 0.7), filter.evaluate(0.7, 0.3));
+        }
+    }
+
+    #[test]
+    fn test_mitchell_and_lanczos_filters_have_negative_lobes() {
+        assert!(Filter::mitchell(2.0).evaluate(1.5, 0.0) < 0.0);
+        assert!(Filter::lanczos(3.0).evaluate(1.5, 0.0) < 0.0);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+
+// An axis aligned bounding box, which lets acceleration structures skip everything inside it when
+// a ray misses it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+// The component of v along the given axis, with 0, 1 and 2 for x, y and z.
+pub fn component(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+impl Aabb {
+
+    // A box containing nothing, which is the identity for union.
+    pub fn empty() -> Aabb {
+        Aabb { min: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), max: Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY) }
+    }
+
+    pub fn around_points(points: &[Vec3]) -> Aabb {
+        points.iter().fold(Aabb::empty(), |bounds, p| bounds.union(&Aabb { min: *p, max: *p }))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vec3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn centre(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let size = self.max - self.min;
+        return if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+    }
+
+    // Whether the ray passes through the box between tmin and tmax, found by clipping the ray to
+    // the slab between the box's faces on each axis in turn.
+    pub fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> bool {
+        let (mut tmin, mut tmax) = (tmin, tmax);
+        for axis in 0..3 {
+            let inverse = 1.0 / component(r.direction, axis);
+            let origin = component(r.origin, axis);
+            let mut t0 = (component(self.min, axis) - origin) * inverse;
+            let mut t1 = (component(self.max, axis) - origin) * inverse;
+            if inverse < 0.0 { std::mem::swap(&mut t0, &mut t1) }
+            tmin = tmin.max(t0);
+            tmax = tmax.min(t1);
+            if tmax < tmin { return false }
+        }
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::Wavelengths;
+
+    #[test]
+    fn test_hit_finds_rays_that_pass_through_the_box() {
+        let bounds = Aabb::around_points(&[Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 2.0, 1.0)]);
+        let ray = |origin: Vec3, direction: Vec3| Ray { origin, direction, wavelengths: Wavelengths::Rgb };
+        assert!(bounds.hit(&ray(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX));
+        assert!(bounds.hit(&ray(Vec3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::MAX));
+        assert!(!bounds.hit(&ray(Vec3::new(0.0, 3.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX));
+        // Behind the ray, or beyond tmax.
+        assert!(!bounds.hit(&ray(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0)), 0.0, f64::MAX));
+        assert!(!bounds.hit(&ray(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, 3.0));
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+use crate::hitable::mesh::{smooth_normals, weld_key};
+use crate::texture::Texture;
+
+// Most times each triangle is split in four while tessellating, which limits the memory that a
+// very short edge length can use.
+const MAXIMUM_SUBDIVISIONS: u32 = 8;
+
+// Vertices and triangles of a mesh before it's built, along with the normals it's displaced along.
+pub struct Tessellation {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+// Displacement of a surface along its normals by a scalar texture, which unlike a bump map changes
+// the surface's silhouette. Surfaces are tessellated into triangles with edges no longer than
+// edge_length when they're created, and each vertex is moved by scale times the texture. The mesh's
+// BVH is built around the displaced vertices, so its bounds are exact and never need padding for
+// how far the surface could have moved.
+#[derive(Debug, Clone)]
+pub struct Displacement {
+    pub height: Texture,
+    pub scale: f64,
+    pub edge_length: f64,
+}
+
+impl Displacement {
+
+    // Move the vertices along their normals. Vertices at the same position, such as along uv
+    // seams, all move as the first of them does, so that textures that don't match across a seam
+    // don't open cracks in the surface.
+    pub fn displace(&self, tessellation: &Tessellation) -> Vec<Vec3> {
+        let mut moved: HashMap<[u64; 3], Vec3> = HashMap::new();
+        return tessellation.positions.iter().zip(tessellation.normals.iter()).zip(tessellation.uvs.iter()).map(|((p, n), uv)| {
+            *moved.entry(weld_key(*p)).or_insert_with(|| *p + self.scale * self.height.scalar_value_at(*uv) * *n)
+        }).collect();
+    }
+}
+
+// A sphere as a grid of triangles in its uv coordinates, which match those of Sphere, with edges
+// no longer than edge_length. Like Sphere, a negative radius turns the normals inwards.
+pub fn tessellate_sphere(centre: Vec3, radius: f64, edge_length: f64) -> Tessellation {
+    // The grid is spaced so that the diagonals of its cells, which are the longest edges, are no
+    // longer than edge_length along the equator, where the cells are widest.
+    let spacing = edge_length / 2.0_f64.sqrt();
+    let rings = ((PI * radius.abs() / spacing).ceil() as usize).max(2);
+    let segments = ((2.0 * PI * radius.abs() / spacing).ceil() as usize).max(3);
+
+    let mut tessellation = Tessellation { positions: vec![], normals: vec![], uvs: vec![], triangles: vec![] };
+    for ring in 0..=rings {
+        for segment in 0..=segments {
+            let (u, v) = (segment as f64 / segments as f64, ring as f64 / rings as f64);
+            // The vertices along the seam and at the poles are placed exactly where their
+            // duplicates are, so that they're welded together.
+            let phi = if segment == segments { 0.0 } else { u * 2.0 * PI };
+            let theta = v * PI;
+            let sin_theta = if ring == 0 || ring == rings { 0.0 } else { theta.sin() };
+            let d = Vec3::new(-sin_theta * phi.cos(), -theta.cos(), sin_theta * phi.sin());
+            tessellation.positions.push(centre + radius.abs() * d);
+            tessellation.normals.push(if radius < 0.0 { -d } else { d });
+            tessellation.uvs.push((u, v));
+        }
+    }
+
+    let index = |ring: usize, segment: usize| ring * (segments + 1) + segment;
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let (a, b) = (index(ring, segment), index(ring, segment + 1));
+            let (c, d) = (index(ring + 1, segment), index(ring + 1, segment + 1));
+            // The triangles touching the poles would have two vertices at the pole, so one of
+            // each pair is left out there.
+            let mut triangles = vec![];
+            if ring > 0 { triangles.push([a, b, d]) }
+            if ring < rings - 1 { triangles.push([a, d, c]) }
+            for [a, b, c] in triangles {
+                tessellation.triangles.push(if radius < 0.0 { [a, c, b] } else { [a, b, c] });
+            }
+        }
+    }
+    return tessellation;
+}
+
+// Split every triangle into four at the midpoints of its edges until no edge is longer than
+// edge_length. Every triangle is split each time, so neighbouring triangles always share the
+// vertices along their edges and the surface has no cracks once it's displaced.
+pub fn subdivide(mut tessellation: Tessellation, edge_length: f64) -> Tessellation {
+    for _ in 0..MAXIMUM_SUBDIVISIONS {
+        let positions = &tessellation.positions;
+        let too_long = |a: usize, b: usize| (positions[a] - positions[b]).length() > edge_length;
+        if !tessellation.triangles.iter().any(|[a, b, c]| too_long(*a, *b) || too_long(*b, *c) || too_long(*c, *a)) { break }
+
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let triangles = std::mem::take(&mut tessellation.triangles);
+        for [a, b, c] in triangles {
+            let ab = midpoint(&mut tessellation, &mut midpoints, a, b);
+            let bc = midpoint(&mut tessellation, &mut midpoints, b, c);
+            let ca = midpoint(&mut tessellation, &mut midpoints, c, a);
+            tessellation.triangles.extend_from_slice(&[[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+        }
+    }
+    return tessellation;
+}
+
+// The vertex halfway along the edge between vertices a and b, added the first time it's needed.
+fn midpoint(tessellation: &mut Tessellation, midpoints: &mut HashMap<(usize, usize), usize>, a: usize, b: usize) -> usize {
+    let key = (a.min(b), a.max(b));
+    if let Some(index) = midpoints.get(&key) { return *index }
+
+    let normal = tessellation.normals[a] + tessellation.normals[b];
+    let (uv_a, uv_b) = (tessellation.uvs[a], tessellation.uvs[b]);
+    tessellation.positions.push(0.5 * (tessellation.positions[a] + tessellation.positions[b]));
+    tessellation.normals.push(if normal.squared_length() > 0.0 { normal.unit_vector() } else { normal });
+    tessellation.uvs.push((0.5 * (uv_a.0 + uv_b.0), 0.5 * (uv_a.1 + uv_b.1)));
+    midpoints.insert(key, tessellation.positions.len() - 1);
+    return tessellation.positions.len() - 1;
+}
+
+// A mesh given by its vertices and triangles, subdivided until its edges are no longer than
+// edge_length and ready to be displaced along its smooth normals.
+pub fn tessellate_mesh(positions: Vec<Vec3>, uvs: Vec<(f64, f64)>, triangles: Vec<[usize; 3]>, edge_length: f64) -> Tessellation {
+    let normals = smooth_normals(&positions, &triangles);
+    return subdivide(Tessellation { positions, normals, uvs, triangles }, edge_length);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::Hitable;
+    use crate::hitable::sphere::Sphere;
+    use crate::material::Material;
+    use crate::ray::Ray;
+    use crate::spectrum::Wavelengths;
+
+    fn longest_edge(tessellation: &Tessellation) -> f64 {
+        let edge = |a: usize, b: usize| (tessellation.positions[a] - tessellation.positions[b]).length();
+        tessellation.triangles.iter().map(|[a, b, c]| edge(*a, *b).max(edge(*b, *c)).max(edge(*c, *a))).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_tessellated_sphere_matches_the_sphere() {
+        let sphere = Sphere { centre: Vec3::new(1.0, 2.0, 3.0), radius: 1.5, material: Material::lambertian(0.5, 0.5, 0.5), material_id: 0 };
+        let tessellation = tessellate_sphere(sphere.centre, sphere.radius, 0.1);
+        assert!(longest_edge(&tessellation) <= 0.1);
+        for (p, uv) in tessellation.positions.iter().zip(tessellation.uvs.iter()) {
+            assert!(((*p - sphere.centre).length() - 1.5).abs() < 1e-9);
+            // Away from the seam and the poles, where u isn't unique, the uvs are the sphere's.
+            if uv.0 > 0.0 && uv.0 < 1.0 && uv.1 > 0.0 && uv.1 < 1.0 {
+                let expected = sphere.uv(*p);
+                assert!((uv.0 - expected.0).abs() < 1e-9 && (uv.1 - expected.1).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_subdivide_shares_midpoints_between_triangles() {
+        let square = Tessellation {
+            positions: vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+            normals: vec![Vec3::new(0.0, 0.0, 1.0); 4],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            triangles: vec![[0, 1, 2], [0, 2, 3]],
+        };
+        let subdivided = subdivide(square, 0.3);
+        assert!(longest_edge(&subdivided) <= 0.3);
+        // Three splits give a grid of 9 by 9 vertices, each made once.
+        assert_eq!(subdivided.triangles.len(), 2 * 64);
+        assert_eq!(subdivided.positions.len(), 81);
+    }
+
+    #[test]
+    fn test_displaced_sphere_grows_by_the_height() {
+        let displacement = Displacement { height: Texture::scalar(0.5), scale: 0.2, edge_length: 0.1 };
+        let centre = Vec3::new(0.0, 1.0, 0.0);
+        let displaced = Hitable::displaced_sphere(centre, 1.0, Material::lambertian(0.5, 0.5, 0.5), &displacement);
+        let sphere = Hitable::sphere(centre, 1.0, Material::lambertian(0.5, 0.5, 0.5));
+
+        match displaced {
+            Hitable::Mesh(ref mesh) => assert!(mesh.positions.iter().all(|p| ((*p - centre).length() - 1.1).abs() < 1e-9)),
+            _ => panic!("displaced sphere isn't a mesh"),
+        }
+
+        // The silhouette grows, so a ray just missing the sphere hits the displaced sphere.
+        let ray = Ray { origin: Vec3::new(-5.0, 2.05, 0.0), direction: Vec3::new(1.0, 0.0, 0.0), wavelengths: Wavelengths::Rgb };
+        assert!(sphere.hit(&ray, 0.0, f64::MAX).is_none());
+        assert!(displaced.hit(&ray, 0.0, f64::MAX).is_some());
+    }
+
+    #[test]
+    fn test_displacement_keeps_seams_closed() {
+        // A checker with a single square in u changes value across the seam at u = 0.
+        let height = Texture::checker(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), 1.0);
+        let displacement = Displacement { height, scale: 0.3, edge_length: 0.2 };
+        let tessellation = tessellate_sphere(Vec3::new(0.0, 0.0, 0.0), 1.0, displacement.edge_length);
+        let displaced = displacement.displace(&tessellation);
+        let mut shared = 0;
+        for (i, p) in tessellation.positions.iter().enumerate() {
+            for (j, q) in tessellation.positions.iter().enumerate().skip(i + 1) {
+                if weld_key(*p) == weld_key(*q) {
+                    assert_eq!(displaced[i], displaced[j]);
+                    shared += 1;
+                }
+            }
+        }
+        // Every vertex on the seam has a duplicate at the other end of u.
+        assert!(shared >= tessellation.uvs.iter().filter(|uv| uv.0 == 1.0).count());
+        // No vertex moves further than the largest height.
+        assert!(tessellation.positions.iter().zip(displaced.iter()).all(|(p, q)| (*q - *p).length() <= 0.3 + 1e-9));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::hitable::{HitRecord, Hitable};
 use crate::ray::Ray;
 use crate::hitable::_Hitable;
+use crate::stats::record_intersection_tests;
 
 #[derive(Clone)]
 pub struct HitableList {
@@ -9,15 +10,15 @@ pub struct HitableList {
 
 impl _Hitable for HitableList {
     // Note - this was implemented using fold, however the following runs around half the time.
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord<'_>> {
         let mut result = None;
         let mut closest_so_far = tmax;
+        record_intersection_tests(self.hitables.len() as u64);
 
-        for hitable in self.hitables.iter() {
-            let hit = hitable.hit(r, tmin, closest_so_far);
-            if hit.is_some() {
-                result = hit;
-                result.map(|h| closest_so_far = h.t);
+        for (index, hitable) in self.hitables.iter().enumerate() {
+            if let Some(hit) = hitable.hit(r, tmin, closest_so_far) {
+                closest_so_far = hit.t;
+                result = Some(HitRecord { object_id: index as u32, ..hit });
             }
         }
 
@@ -33,6 +34,7 @@ mod tests {
     use crate::material::lambertian::Lambertian;
     use crate::material::Material;
     use crate::hitable::Hitable;
+    use crate::spectrum::Wavelengths;
 
     #[test]
     fn test_hit_returns_hit_record_if_one_of_the_objects_intersects_the_ray() {
@@ -40,10 +42,12 @@ mod tests {
             centre: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
             radius: 1.0,
             material: Material::Lambertian(Lambertian { albedo: Vec3 { x: 1.0, y: 1.0, z: 1.0 }}),
+            material_id: 0,
         });
         let ray = Ray {
             origin: Vec3 { x: 2.0, y: 2.0, z: 2.0 },
             direction: Vec3 { x: -2.0, y: -2.0, z: -2.0 },
+            wavelengths: Wavelengths::Rgb,
         };
         let hitables = HitableList {
             hitables: vec![sphere]
@@ -52,4 +56,23 @@ mod tests {
 
         assert!(hit.is_some());
     }
+
+    #[test]
+    fn test_hit_sets_the_object_id_to_the_index_of_the_closest_object() {
+        let material = Material::Lambertian(Lambertian { albedo: Vec3 { x: 1.0, y: 1.0, z: 1.0 }});
+        let hitables = HitableList {
+            hitables: vec![
+                Hitable::sphere(Vec3 { x: 0.0, y: 0.0, z: -10.0 }, 1.0, material.clone()),
+                Hitable::sphere(Vec3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, material.clone()),
+                Hitable::sphere(Vec3 { x: 0.0, y: 0.0, z: -20.0 }, 1.0, material),
+            ]
+        };
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 },
+            wavelengths: Wavelengths::Rgb,
+        };
+
+        assert_eq!(hitables.hit(&ray, 0.0, 100.0).map(|h| h.object_id), Some(1));
+    }
 }
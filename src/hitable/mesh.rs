@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::hitable::{HitRecord, _Hitable};
+use crate::hitable::aabb::{component, Aabb};
+use crate::material::Material;
+use crate::material::microfacet::Frame;
+use crate::stats::{record_bvh_node_visit, record_intersection_tests};
+
+// Most triangles in a leaf of a mesh's BVH.
+const LEAF_SIZE: usize = 4;
+// Deepest a BVH can be traversed, which is far deeper than any mesh that fits in memory needs.
+const MAXIMUM_DEPTH: usize = 64;
+
+// A node of a mesh's bounding volume hierarchy. The nodes are stored in a flat array in depth first
+// order, so the first child of an interior node directly follows it.
+#[derive(Debug, Clone)]
+struct BvhNode {
+    bounds: Aabb,
+    // For a leaf, the first of its count triangles. For an interior node, whose count is zero, the
+    // index of its second child.
+    start: usize,
+    count: usize,
+}
+
+// A triangle mesh sharing a single material, with positions, uvs and normals for each vertex and
+// a BVH over the triangles so that rays only test the triangles near them. Normals are computed
+// from the triangles when the mesh is created, with triangles given anticlockwise when seen from
+// the outside. Hits have the normal of the facet, and the vertex normals are interpolated into the
+// shading normal, which the material is smooth shaded with.
+#[derive(Clone)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub triangles: Vec<[usize; 3]>,
+    pub material: Material,
+    pub material_id: u32,
+    nodes: Vec<BvhNode>,
+}
+
+// Key identifying vertices at the same position, so that vertices duplicated along uv seams can be
+// treated as one. Adding zero turns -0 into 0.
+pub fn weld_key(p: Vec3) -> [u64; 3] {
+    [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]
+}
+
+// Smooth vertex normals, which are the area weighted average of the normals of the triangles
+// around each vertex. Vertices at the same position share a normal, so the shading is smooth
+// across seams.
+pub fn smooth_normals(positions: &[Vec3], triangles: &[[usize; 3]]) -> Vec<Vec3> {
+    let mut welded: HashMap<[u64; 3], Vec3> = HashMap::new();
+    for triangle in triangles {
+        let [a, b, c] = *triangle;
+        // The cross product's length is twice the triangle's area.
+        let normal = (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+        for vertex in triangle {
+            let total = welded.entry(weld_key(positions[*vertex])).or_insert(Vec3::new(0.0, 0.0, 0.0));
+            *total = *total + normal;
+        }
+    }
+    return positions.iter().map(|p| match welded.get(&weld_key(*p)) {
+        Some(normal) if normal.squared_length() > 0.0 => normal.unit_vector(),
+        _ => Vec3::new(0.0, 0.0, 0.0),
+    }).collect();
+}
+
+impl Mesh {
+
+    pub fn new(positions: Vec<Vec3>, uvs: Vec<(f64, f64)>, triangles: Vec<[usize; 3]>, material: Material) -> Mesh {
+        let normals = smooth_normals(&positions, &triangles);
+        let mut mesh = Mesh { positions, normals, uvs, triangles, material: Material::smooth_shaded(material), material_id: 0, nodes: vec![] };
+        mesh.build_bvh();
+        return mesh;
+    }
+
+    fn triangle_bounds(&self, triangle: usize) -> Aabb {
+        let [a, b, c] = self.triangles[triangle];
+        Aabb::around_points(&[self.positions[a], self.positions[b], self.positions[c]])
+    }
+
+    // Build the BVH by splitting the triangles in half at the median of their centres along the
+    // axis their centres are most spread out on, and reorder the triangles so that each leaf's are
+    // together.
+    fn build_bvh(&mut self) {
+        if self.triangles.is_empty() { return }
+        let bounds: Vec<Aabb> = (0..self.triangles.len()).map(|i| self.triangle_bounds(i)).collect();
+        let mut order: Vec<usize> = (0..self.triangles.len()).collect();
+        let mut nodes = vec![];
+        build_node(&bounds, &mut order, 0, &mut nodes);
+        self.triangles = order.iter().map(|i| self.triangles[*i]).collect();
+        self.nodes = nodes;
+    }
+
+    // Intersect the ray with a triangle using the Möller-Trumbore algorithm, returning the
+    // distance along the ray and the barycentric coordinates of the second and third vertices.
+    fn intersect(&self, triangle: usize, r: &Ray, tmin: f64, tmax: f64) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.triangles[triangle];
+        let (p0, p1, p2) = (self.positions[a], self.positions[b], self.positions[c]);
+        let (edge1, edge2) = (p1 - p0, p2 - p0);
+        let p = r.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant.abs() < 1e-12 { return None }
+
+        let inverse = 1.0 / determinant;
+        let s = r.origin - p0;
+        let b1 = s.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&b1) { return None }
+        let q = s.cross(&edge1);
+        let b2 = r.direction.dot(&q) * inverse;
+        if b2 < 0.0 || b1 + b2 > 1.0 { return None }
+
+        let t = edge2.dot(&q) * inverse;
+        return if t < tmax && t > tmin { Some((t, b1, b2)) } else { None };
+    }
+
+    fn hit_record(&self, r: &Ray, triangle: usize, t: f64, b1: f64, b2: f64) -> HitRecord<'_> {
+        let [a, b, c] = self.triangles[triangle];
+        let b0 = 1.0 - b1 - b2;
+        let (p0, p1, p2) = (self.positions[a], self.positions[b], self.positions[c]);
+        let geometric = (p1 - p0).cross(&(p2 - p0)).unit_vector();
+
+        // The interpolated normal smooths the shading of the facets. It can lean a long way from
+        // the facet where the mesh is coarse, but never past it.
+        let interpolated = b0 * self.normals[a] + b1 * self.normals[b] + b2 * self.normals[c];
+        let shading_normal = if interpolated.dot(&geometric) > 1e-9 { interpolated.unit_vector() } else { geometric };
+
+        let (uv0, uv1, uv2) = (self.uvs[a], self.uvs[b], self.uvs[c]);
+        let uv = (b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0, b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1);
+
+        // Solve p0 - p2 = (u0 - u2) dpdu + (v0 - v2) dpdv and the same for p1 - p2.
+        let (du02, dv02, du12, dv12) = (uv0.0 - uv2.0, uv0.1 - uv2.1, uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+        let determinant = du02 * dv12 - dv02 * du12;
+        let (dpdu, dpdv) = if determinant.abs() > 1e-12 {
+            ((dv12 * dp02 - dv02 * dp12) / determinant, (du02 * dp12 - du12 * dp02) / determinant)
+        }
+        else {
+            let frame = Frame::from_normal(geometric);
+            (frame.s, frame.t)
+        };
+
+        return HitRecord { t, p: r.point_at_parameter(t), normal: geometric, shading_normal, uv, dpdu, dpdv, material: &self.material, material_id: self.material_id, object_id: 0 };
+    }
+}
+
+fn build_node(bounds: &[Aabb], order: &mut [usize], start: usize, nodes: &mut Vec<BvhNode>) {
+    let index = nodes.len();
+    let node_bounds = order.iter().fold(Aabb::empty(), |total, i| total.union(&bounds[*i]));
+    nodes.push(BvhNode { bounds: node_bounds, start, count: order.len() });
+    if order.len() <= LEAF_SIZE { return }
+
+    let centres = order.iter().fold(Aabb::empty(), |total, i| total.union(&Aabb { min: bounds[*i].centre(), max: bounds[*i].centre() }));
+    let axis = centres.longest_axis();
+    let middle = order.len() / 2;
+    order.select_nth_unstable_by(middle, |a, b| component(bounds[*a].centre(), axis).total_cmp(&component(bounds[*b].centre(), axis)));
+
+    let (first, second) = order.split_at_mut(middle);
+    build_node(bounds, first, start, nodes);
+    nodes[index].start = nodes.len();
+    nodes[index].count = 0;
+    build_node(bounds, second, start + middle, nodes);
+}
+
+impl _Hitable for Mesh {
+    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord<'_>> {
+        if self.nodes.is_empty() { return None }
+        let mut closest = None;
+        let mut closest_so_far = tmax;
+
+        let mut stack = [0; MAXIMUM_DEPTH];
+        let mut size = 1;
+        while size > 0 {
+            size -= 1;
+            let index = stack[size];
+            let node = &self.nodes[index];
+            record_bvh_node_visit();
+            if !node.bounds.hit(r, tmin, closest_so_far) { continue }
+
+            if node.count > 0 {
+                record_intersection_tests(node.count as u64);
+                for triangle in node.start..node.start + node.count {
+                    if let Some((t, b1, b2)) = self.intersect(triangle, r, tmin, closest_so_far) {
+                        closest_so_far = t;
+                        closest = Some((triangle, b1, b2));
+                    }
+                }
+            }
+            else {
+                stack[size] = node.start;
+                stack[size + 1] = index + 1;
+                size += 2;
+            }
+        }
+
+        return closest.map(|(triangle, b1, b2)| self.hit_record(r, triangle, closest_so_far, b1, b2));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::displacement::tessellate_sphere;
+    use crate::random::Rng;
+    use crate::sampler::{bsdf_dimension, Sampler};
+    use crate::sampling::sample_unit_sphere;
+    use crate::spectrum::Wavelengths;
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray { origin, direction, wavelengths: Wavelengths::Rgb }
+    }
+
+    #[test]
+    fn test_hit_interpolates_across_the_triangle() {
+        let positions = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(2.0, 2.0, 0.0), Vec3::new(0.0, 2.0, 0.0)];
+        let uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let mesh = Mesh::new(positions, uvs, vec![[0, 1, 2], [0, 2, 3]], Material::lambertian(0.5, 0.5, 0.5));
+        let hit = mesh.hit(&ray(Vec3::new(0.5, 1.5, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-9);
+        assert!((hit.uv.0 - 0.25).abs() < 1e-9 && (hit.uv.1 - 0.75).abs() < 1e-9);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!((hit.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-9 && (hit.dpdv - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-9);
+        assert!(mesh.hit(&ray(Vec3::new(2.5, 1.5, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn test_bvh_finds_the_same_hits_as_testing_every_triangle() {
+        let tessellation = tessellate_sphere(Vec3::new(1.0, 0.0, 0.0), 2.0, 0.3);
+        let mesh = Mesh::new(tessellation.positions, tessellation.uvs, tessellation.triangles, Material::lambertian(0.5, 0.5, 0.5));
+        assert!(mesh.nodes.len() > 1);
+
+        let mut rng = Rng::new(3);
+        for _ in 0..1000 {
+            let origin = Vec3::new(1.0, 0.0, 0.0) + 4.0 * sample_unit_sphere((rng.next_f64(), rng.next_f64()));
+            let target = Vec3::new(1.0, 0.0, 0.0) + 2.5 * sample_unit_sphere((rng.next_f64(), rng.next_f64()));
+            let r = ray(origin, target - origin);
+            let expected = (0..mesh.triangles.len()).filter_map(|i| mesh.intersect(i, &r, 0.0, f64::MAX)).map(|(t, _, _)| t).fold(None, |closest: Option<f64>, t| Some(closest.map_or(t, |c| c.min(t))));
+            assert_eq!(mesh.hit(&r, 0.0, f64::MAX).map(|hit| hit.t), expected);
+        }
+    }
+
+    #[test]
+    fn test_light_scattered_from_a_coarse_mesh_stays_above_the_facets() {
+        // Across the big facets of a coarse sphere the interpolated normal leans far from the facet
+        // normal, so directions around it can point into the surface.
+        let tessellation = tessellate_sphere(Vec3::new(0.0, 0.0, 0.0), 1.0, 1.0);
+        let mut rng = Rng::new(5);
+        for material in [Material::lambertian(0.8, 0.8, 0.8), Material::metal(0.8, 0.8, 0.8, 0.3)] {
+            let mesh = Mesh::new(tessellation.positions.clone(), tessellation.uvs.clone(), tessellation.triangles.clone(), material.clone());
+            let mut sampler = Sampler::independent(2);
+            for i in 0..20000 {
+                let origin = 3.0 * sample_unit_sphere((rng.next_f64(), rng.next_f64()));
+                let target = 0.5 * sample_unit_sphere((rng.next_f64(), rng.next_f64()));
+                let r = ray(origin, target - origin);
+                let hit = mesh.hit(&r, 0.0, f64::MAX).unwrap();
+                sampler.start_pixel_sample(0, 0, i);
+                sampler.start_dimension(bsdf_dimension(0));
+                if let Some(scatter) = hit.material.scatter(&r, &hit, &mut sampler) {
+                    assert!(scatter.ray.direction.dot(&hit.normal) > 0.0, "{:?} scattered {} below the facet {}", material, scatter.ray.direction, hit.normal);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_smooth_normals_point_out_of_a_closed_mesh() {
+        let tessellation = tessellate_sphere(Vec3::new(0.0, 0.0, 0.0), 1.0, 0.2);
+        let normals = smooth_normals(&tessellation.positions, &tessellation.triangles);
+        for (p, n) in tessellation.positions.iter().zip(normals.iter()) {
+            // The triangles around each vertex aren't quite symmetric, so the normals lean a little
+            // where the triangles are thin near the poles.
+            assert!((*n - p.unit_vector()).length() < 0.05, "normal at {} is {}", p, n);
+        }
+    }
+}
@@ -5,50 +5,116 @@ use crate::material::Material;
 use std::fmt::{Display, Formatter, Error};
 use crate::hitable::sphere::Sphere;
 use crate::hitable::hitable_list::HitableList;
+use crate::hitable::mesh::Mesh;
+use crate::hitable::displacement::{tessellate_mesh, tessellate_sphere, Displacement};
 
 pub mod hitable_list;
 pub mod sphere;
+pub mod mesh;
+pub mod aabb;
+pub mod displacement;
 
 #[derive(Copy, Clone)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub t: f64,
     pub p: Vec3,
     pub normal: Vec3,
-    pub material: Material
+    // Normal used for shading, on the same side of the surface as normal. It's the same as normal
+    // except on meshes, whose vertex normals smooth the shading of their facets. Materials only
+    // see it through NormalMapped, which keeps scattered light on the right side of normal.
+    pub shading_normal: Vec3,
+    // Surface coordinates of the hit, in [0, 1], which textures are looked up by.
+    pub uv: (f64, f64),
+    // Rates of change of p with u and v, which give the directions of the surface's tangent and
+    // bitangent. Normal maps are applied in the frame they make with the normal.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub material: &'a Material,
+    // Identifier of the material, shared by objects whose materials are identical. Assigned when
+    // the scene is built by Hitable::assign_material_ids.
+    pub material_id: u32,
+    // Index of the object within the outermost HitableList containing it.
+    pub object_id: u32,
 }
 
-impl Display for HitRecord {
+impl Display for HitRecord<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         return write!(f, "HitRecord(t: {}, p: {}, normal: {}", self.t, self.p, self.normal);
     }
 }
 
 trait _Hitable {
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord<'_>>;
 }
 
 #[derive(Clone)]
 pub enum Hitable {
     Sphere(Sphere),
     HitableList(HitableList),
+    Mesh(Mesh),
 }
 
 // Provide constructors for available hitables to clean up the API.
 impl Hitable {
 
     pub fn sphere(centre: Vec3, radius: f64, material: Material) -> Hitable {
-        Hitable::Sphere(Sphere { centre, radius, material })
+        Hitable::Sphere(Sphere { centre, radius, material, material_id: 0 })
     }
 
     pub fn hitable_list(hitables: Vec<Hitable>) -> Hitable {
         Hitable::HitableList(HitableList { hitables })
     }
 
-    pub fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+    // A triangle mesh, with triangles given as indices of their vertices in anticlockwise order
+    // seen from outside.
+    pub fn mesh(positions: Vec<Vec3>, uvs: Vec<(f64, f64)>, triangles: Vec<[usize; 3]>, material: Material) -> Hitable {
+        Hitable::Mesh(Mesh::new(positions, uvs, triangles, material))
+    }
+
+    // A triangle mesh tessellated and displaced along its normals.
+    pub fn displaced_mesh(positions: Vec<Vec3>, uvs: Vec<(f64, f64)>, triangles: Vec<[usize; 3]>, material: Material, displacement: &Displacement) -> Hitable {
+        let tessellation = tessellate_mesh(positions, uvs, triangles, displacement.edge_length);
+        Hitable::Mesh(Mesh::new(displacement.displace(&tessellation), tessellation.uvs, tessellation.triangles, material))
+    }
+
+    // A sphere tessellated into a mesh and displaced along its normals.
+    pub fn displaced_sphere(centre: Vec3, radius: f64, material: Material, displacement: &Displacement) -> Hitable {
+        let tessellation = tessellate_sphere(centre, radius, displacement.edge_length);
+        Hitable::Mesh(Mesh::new(displacement.displace(&tessellation), tessellation.uvs, tessellation.triangles, material))
+    }
+
+    pub fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord<'_>> {
         match *self {
             Hitable::Sphere(ref sphere) => sphere.hit(r, tmin, tmax),
             Hitable::HitableList(ref hitable_list) => hitable_list.hit(r, tmin, tmax),
+            Hitable::Mesh(ref mesh) => mesh.hit(r, tmin, tmax),
         }
     }
 
+    // Give each object the ID of its material, numbering materials from 0 in the order they're
+    // first found, with objects whose materials are identical sharing an ID.
+    pub fn assign_material_ids(&mut self) {
+        self.assign_material_ids_from(&mut vec![]);
+    }
+
+    fn assign_material_ids_from(&mut self, materials: &mut Vec<Material>) {
+        let (material, id) = match *self {
+            Hitable::Sphere(ref mut sphere) => (&sphere.material, &mut sphere.material_id),
+            Hitable::Mesh(ref mut mesh) => (&mesh.material, &mut mesh.material_id),
+            Hitable::HitableList(ref mut hitable_list) => {
+                for hitable in hitable_list.hitables.iter_mut() {
+                    hitable.assign_material_ids_from(materials);
+                }
+                return;
+            }
+        };
+        *id = match materials.iter().position(|m| m == material) {
+            Some(index) => index as u32,
+            None => {
+                materials.push(material.clone());
+                (materials.len() - 1) as u32
+            }
+        };
+    }
+
 }
@@ -1,30 +1,43 @@
+use std::f64::consts::PI;
+
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::hitable::HitRecord;
 use crate::hitable::_Hitable;
 use crate::material::Material;
-use rand::prelude::*;
 
 #[derive(Clone)]
 pub struct Sphere {
     pub centre: Vec3,
     pub radius: f64,
-    pub material: Material
+    pub material: Material,
+    pub material_id: u32,
 }
 
-pub fn random_point_in_unit_sphere() -> Vec3 {
-    let random_vec3 = Vec3 { x: random(), y: random(), z: random() };
-    let p = 2.0 * random_vec3 - Vec3 { x: 1.0, y: 1.0, z: 1.0 };
-    if p.squared_length() >= 1.0 {
-        return random_point_in_unit_sphere();
+impl Sphere {
+    // Coordinates of a point on the sphere, with u going around the y axis from -x and v going up
+    // from the bottom. The sign of the radius doesn't affect them.
+    pub fn uv(&self, p: Vec3) -> (f64, f64) {
+        let d = (p - self.centre) / self.radius.abs();
+        let theta = (-d.y).clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI;
+        return (phi / (2.0 * PI), theta / PI);
     }
-    else {
-        return p;
+
+    // Rates of change of a point on the sphere with u and v, which are the tangent and bitangent
+    // of the surface. Their cross product points out of the sphere.
+    pub fn derivatives(&self, p: Vec3) -> (Vec3, Vec3) {
+        let d = p - self.centre;
+        // Distance from the y axis, which is zero at the poles where u isn't defined.
+        let rho = (d.x * d.x + d.z * d.z).sqrt().max(1e-12);
+        let dpdu = 2.0 * PI * Vec3::new(d.z, 0.0, -d.x);
+        let dpdv = PI * Vec3::new(-d.x * d.y / rho, rho, -d.y * d.z / rho);
+        return (dpdu, dpdv);
     }
 }
 
 impl _Hitable for Sphere {
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.centre;
         let a = r.direction.dot(&r.direction);
         let b =  oc.dot(&r.direction);
@@ -37,11 +50,19 @@ impl _Hitable for Sphere {
             let solution1 = (-b - discriminant.sqrt()) / a;
             if solution1 < tmax && solution1 > tmin {
                 let intersection_point = r.point_at_parameter(solution1);
+                let (dpdu, dpdv) = self.derivatives(intersection_point);
+                let normal = (intersection_point - self.centre) / self.radius;
                 let hit_record = HitRecord {
                     t: solution1,
                     p: intersection_point,
-                    normal: (intersection_point - self.centre) / self.radius,
-                    material: self.material.clone(),
+                    normal,
+                    shading_normal: normal,
+                    uv: self.uv(intersection_point),
+                    dpdu,
+                    dpdv,
+                    material: &self.material,
+                    material_id: self.material_id,
+                    object_id: 0,
                 };
                 return Some(hit_record);
             }
@@ -49,11 +70,19 @@ impl _Hitable for Sphere {
             let solution2 = (-b + discriminant.sqrt()) / a;
             if solution2 < tmax && solution2 > tmin {
                 let intersection_point = r.point_at_parameter(solution2);
+                let (dpdu, dpdv) = self.derivatives(intersection_point);
+                let normal = (intersection_point - self.centre) / self.radius;
                 let hit_record = HitRecord {
                     t: solution2,
                     p: intersection_point,
-                    normal: (intersection_point - self.centre) / self.radius,
-                    material: self.material.clone(),
+                    normal,
+                    shading_normal: normal,
+                    uv: self.uv(intersection_point),
+                    dpdu,
+                    dpdv,
+                    material: &self.material,
+                    material_id: self.material_id,
+                    object_id: 0,
                 };
                 return Some(hit_record);
             }
@@ -67,6 +96,7 @@ impl _Hitable for Sphere {
 mod tests {
     use super::*;
     use crate::material::lambertian::Lambertian;
+    use crate::spectrum::Wavelengths;
 
     #[test]
     fn test_hit_returns_hit_record_if_ray_intersects_sphere() {
@@ -74,14 +104,16 @@ mod tests {
             centre: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
             radius: 1.0,
             material: Material::Lambertian(Lambertian { albedo: Vec3 { x: 1.0, y: 1.0, z: 1.0 }}),
+            material_id: 0,
         };
         let ray = Ray {
             origin: Vec3 { x: 2.0, y: 2.0, z: 2.0 },
             direction: Vec3 { x: -2.0, y: -2.0, z: -2.0 },
+            wavelengths: Wavelengths::Rgb,
         };
         let hit = sphere.hit(&ray, 0.0, 1.0);
 
-        assert_eq!(hit.is_some(), true);
+        assert!(hit.is_some());
     }
 
     #[test]
@@ -90,14 +122,40 @@ mod tests {
             centre: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
             radius: 1.0,
             material: Material::Lambertian(Lambertian { albedo: Vec3 { x: 1.0, y: 1.0, z: 1.0 }}),
+            material_id: 0,
         };
         let ray = Ray {
             origin: Vec3 { x: 2.0, y: 2.0, z: 2.0 },
             direction: Vec3 { x: 2.0, y: 2.0, z: 2.0 },
+            wavelengths: Wavelengths::Rgb,
         };
         let hit = sphere.hit(&ray, 0.0, 1.0);
 
         // TODO - do we need a PartialEq impl on HitRecord instead?
-        assert_eq!(hit.is_none(), true);
+        assert!(hit.is_none());
+    }
+
+    #[test]
+    fn test_uv_goes_around_and_up_the_sphere() {
+        let sphere = Sphere { centre: Vec3::new(1.0, 2.0, 3.0), radius: -2.0, material: Material::lambertian(1.0, 1.0, 1.0), material_id: 0 };
+        let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9;
+        assert!(close(sphere.uv(Vec3::new(3.0, 2.0, 3.0)), (0.5, 0.5)));
+        assert!(close(sphere.uv(Vec3::new(1.0, 2.0, 5.0)), (0.25, 0.5)));
+        assert_eq!(sphere.uv(Vec3::new(1.0, 0.0, 3.0)).1, 0.0);
+        assert_eq!(sphere.uv(Vec3::new(1.0, 4.0, 3.0)).1, 1.0);
+    }
+
+    #[test]
+    fn test_derivatives_are_the_rates_of_change_of_uv() {
+        let sphere = Sphere { centre: Vec3::new(1.0, 2.0, 3.0), radius: 2.0, material: Material::lambertian(1.0, 1.0, 1.0), material_id: 0 };
+        let p = sphere.centre + 2.0 * Vec3::new(0.3, -0.5, 0.4).unit_vector();
+        let (dpdu, dpdv) = sphere.derivatives(p);
+        let (u, v) = sphere.uv(p);
+        let epsilon = 1e-6;
+        let (u1, v1) = sphere.uv(p + epsilon * dpdu);
+        assert!(((u1 - u) / epsilon - 1.0).abs() < 1e-4 && ((v1 - v) / epsilon).abs() < 1e-4);
+        let (u2, v2) = sphere.uv(p + epsilon * dpdv);
+        assert!(((u2 - u) / epsilon).abs() < 1e-4 && ((v2 - v) / epsilon - 1.0).abs() < 1e-4);
+        assert!(dpdu.cross(&dpdv).dot(&(p - sphere.centre)) > 0.0);
     }
 }
@@ -0,0 +1,35 @@
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, facing_normal, PassState, Splat, NEAR_ZERO};
+use crate::ray::Ray;
+use crate::sampler::{bsdf_dimension, Sampler};
+use crate::sampling::sample_unit_sphere;
+use crate::scene::Scene;
+use crate::stats;
+use crate::vec3::Vec3;
+
+// Shows how exposed the first hit is, by tracing a cosine weighted ray from it and checking
+// whether anything lies within the given distance. Averaged over many samples this gives white for
+// open surfaces and darker values in creases and where objects are close together. Rays that miss
+// are unoccluded, so they're white.
+#[derive(Debug, Copy, Clone)]
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl _Integrator for AmbientOcclusion {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        let hit = match first_hit {
+            Some(hit) => hit,
+            None => return Vec3::new(1.0, 1.0, 1.0),
+        };
+
+        sampler.start_dimension(bsdf_dimension(0));
+        let normal = facing_normal(&r, &hit);
+        let direction = (normal + sample_unit_sphere(sampler.get_2d())).unit_vector();
+        stats::record_secondary_ray();
+        return match scene.world.hit(&Ray { origin: hit.p, direction, wavelengths: r.wavelengths }, NEAR_ZERO, self.distance) {
+            Some(_) => Vec3::new(0.0, 0.0, 0.0),
+            None => Vec3::new(1.0, 1.0, 1.0),
+        };
+    }
+}
@@ -0,0 +1,493 @@
+use std::f64::consts::PI;
+
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat, NEAR_ZERO};
+use crate::integrator::path::{max_component, PathSettings};
+use crate::light::{Light, LightPoint};
+use crate::medium::{self, medium_towards, Medium};
+use crate::ray::Ray;
+use crate::sampler::{bsdf_dimension, light_dimension, light_path_dimension, russian_roulette_dimension, Sampler};
+use crate::sampling::sample_unit_sphere;
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::stats::{self, record_termination, Termination};
+use crate::vec3::Vec3;
+
+// Bidirectional path tracing, following Veach's thesis and the description in Physically Based
+// Rendering. For each sample a subpath is traced from the camera and another from a point on one of
+// the lights, and every prefix of one is connected to every prefix of the other. A path with s
+// vertices from the light and t from the camera can be made in several ways, so the contributions
+// are weighted by the balance heuristic over all of the ways the path could have been sampled.
+//
+// Connections to the camera (t = 1) land anywhere in the image, so they're returned as splats.
+// This finds paths that the path tracer struggles with, such as light focused by glass onto a
+// diffuse surface. Light from the background can only be found by the camera subpath, so it's
+// added without weighting.
+//
+// Densities are kept with respect to surface area, with pdf_fwd the density of a vertex being
+// sampled by the subpath it belongs to and pdf_rev the density of it being sampled from the other
+// direction. Only the overall depth limit of the settings applies, along with Russian roulette.
+//
+// Both subpaths start in clear space and keep track of the medium they are in, as the path tracer
+// does, so that the segments inside absorbing glass are absorbed, including the connections.
+#[derive(Debug, Copy, Clone)]
+pub struct Bidirectional {
+    pub settings: PathSettings,
+}
+
+#[derive(Copy, Clone)]
+enum VertexKind<'a> {
+    // A point on the camera's lens.
+    Camera,
+    // The point on a light that a light subpath starts from.
+    Light(&'a Light),
+    // A point where a subpath hit a surface, which may be on a light.
+    Surface(HitRecord<'a>),
+}
+
+#[derive(Copy, Clone)]
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    p: Vec3,
+    normal: Vec3,
+    // The contribution of the subpath up to this vertex, divided by the density of sampling it.
+    beta: Vec3,
+    // Whether the vertex scatters into discrete directions, so that it can't be connected to.
+    delta: bool,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // The wavelengths that the subpath carries, which are the same for both subpaths.
+    wavelengths: Wavelengths,
+    // The medium that the subpath travelled through to reach the vertex.
+    medium: Option<Medium>,
+}
+
+const BLACK: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
+const WHITE: Vec3 = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+
+impl<'a> Vertex<'a> {
+    fn camera(p: Vec3, normal: Vec3, beta: Vec3, wavelengths: Wavelengths) -> Vertex<'a> {
+        return Vertex { kind: VertexKind::Camera, p, normal, beta, delta: false, pdf_fwd: 0.0, pdf_rev: 0.0, wavelengths, medium: None };
+    }
+
+    fn light(light: &'a Light, point: &LightPoint, beta: Vec3, pdf_fwd: f64, wavelengths: Wavelengths) -> Vertex<'a> {
+        return Vertex { kind: VertexKind::Light(light), p: point.p, normal: point.normal, beta, delta: false, pdf_fwd, pdf_rev: 0.0, wavelengths, medium: None };
+    }
+
+    fn surface(hit: HitRecord<'a>, beta: Vec3, wavelengths: Wavelengths, medium: Option<Medium>) -> Vertex<'a> {
+        return Vertex { kind: VertexKind::Surface(hit), p: hit.p, normal: hit.normal, beta, delta: hit.material.is_delta(), pdf_fwd: 0.0, pdf_rev: 0.0, wavelengths, medium };
+    }
+
+    // Points on the lens aren't on a surface, so densities at them have no cosine term.
+    fn is_on_surface(&self) -> bool {
+        !matches!(self.kind, VertexKind::Camera)
+    }
+
+    // Convert a density with respect to solid angle at this vertex, for the direction towards
+    // next, into a density with respect to area at next.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.squared_length();
+        if distance_squared == 0.0 { return 0.0 }
+        let pdf = pdf / distance_squared;
+        return if next.is_on_surface() { pdf * next.normal.dot(&w.unit_vector()).abs() } else { pdf };
+    }
+
+    // Absolute cosine of the angle between the normal and the direction towards a point.
+    fn cosine_towards(&self, p: Vec3) -> f64 {
+        self.normal.dot(&(p - self.p).unit_vector()).abs()
+    }
+
+    // The BSDF for light arriving from the point l and leaving towards the point c.
+    fn f(&self, c: Vec3, l: Vec3) -> Vec3 {
+        match self.kind {
+            VertexKind::Surface(ref hit) => hit.material.bsdf(&Ray { origin: c, direction: self.p - c, wavelengths: self.wavelengths }, hit, l - self.p),
+            _ => BLACK,
+        }
+    }
+
+    // Fraction of the light that survives the connection from this vertex to the point p, through
+    // the medium on that side of the surface, given that its subpath arrived from prev. The camera
+    // and the lights are in clear space.
+    fn transmittance_towards(&self, prev: Vec3, p: Vec3) -> Vec3 {
+        let medium = match self.kind {
+            VertexKind::Surface(ref hit) => medium_towards(self.medium, &Ray { origin: prev, direction: self.p - prev, wavelengths: self.wavelengths }, hit, p - self.p),
+            _ => None,
+        };
+        return medium::transmittance(medium, self.wavelengths, (p - self.p).length());
+    }
+
+    // Light emitted by the vertex towards a point.
+    fn emitted(&self, towards: Vec3) -> Vec3 {
+        match self.kind {
+            VertexKind::Surface(ref hit) => hit.material.emitted(&Ray { origin: towards, direction: self.p - towards, wavelengths: self.wavelengths }, hit),
+            _ => BLACK,
+        }
+    }
+
+    // The light that the vertex is on, if any.
+    fn on_light(&self, scene: &'a Scene) -> Option<&'a Light> {
+        match self.kind {
+            VertexKind::Light(light) => Some(light),
+            VertexKind::Surface(ref hit) => scene.light(hit),
+            VertexKind::Camera => None,
+        }
+    }
+
+    // Density with respect to area of a light subpath starting at this vertex.
+    fn pdf_light_origin(&self, scene: &'a Scene) -> f64 {
+        match self.on_light(scene) {
+            Some(light) => scene.light_selection_pdf() / light.area(),
+            None => 0.0,
+        }
+    }
+
+    // Density with respect to area at next of a light subpath leaving this vertex towards it.
+    // Lights emit with a cosine weighted distribution of directions.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let cosine = self.normal.dot(&(next.p - self.p).unit_vector()).max(0.0);
+        self.convert_density(cosine / PI, next)
+    }
+
+    // Density with respect to area at next of a subpath that arrived at this vertex from prev
+    // continuing to next.
+    fn pdf(&self, scene: &Scene, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf = match self.kind {
+            VertexKind::Camera => scene.camera.pdf_direction(&Ray { origin: self.p, direction: next.p - self.p, wavelengths: self.wavelengths }),
+            VertexKind::Light(_) => return self.pdf_light(next),
+            VertexKind::Surface(ref hit) => match prev {
+                Some(prev) => hit.material.pdf(&Ray { origin: prev.p, direction: self.p - prev.p, wavelengths: self.wavelengths }, hit, next.p - self.p),
+                None => 0.0,
+            },
+        };
+        return self.convert_density(pdf, next);
+    }
+}
+
+// Whether nothing lies between two points.
+fn is_visible(scene: &Scene, from: Vec3, to: Vec3) -> bool {
+    let w = to - from;
+    let distance = w.length();
+    stats::record_secondary_ray();
+    return scene.world.hit(&Ray { origin: from, direction: w / distance, wavelengths: Wavelengths::Rgb }, NEAR_ZERO, distance - NEAR_ZERO).is_none();
+}
+
+impl Bidirectional {
+    // Extend a subpath along the ray r, whose first hit is given, until it leaves the scene, is
+    // absorbed or has max_vertices vertices. pdf is the density, with respect to solid angle, of
+    // the direction of r. Subpaths from the camera carry radiance, so they're scaled by the albedo
+    // of each material, while those from lights carry importance, for which the BSDF is evaluated
+    // with the directions swapped. The dimensions used are mapped through dimension so that light
+    // subpaths don't reuse those of the camera subpath. Returns how the subpath ended, along with
+    // the light it found from the background, which is only used for camera subpaths.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk<'a>(&self, scene: &'a Scene, mut r: Ray, mut hit: Option<HitRecord<'a>>, mut beta: Vec3, mut pdf: f64, from_camera: bool, max_vertices: usize, dimension: fn(u32) -> u32, sampler: &mut Sampler, path: &mut Vec<Vertex<'a>>) -> (Termination, Vec3) {
+        let mut bounce = 0;
+        let mut medium = None;
+        loop {
+            let current = match hit {
+                Some(current) => current,
+                None => return (Termination::Escaped, if from_camera { beta * scene.background.colour(&r) } else { BLACK }),
+            };
+            let prev = path.len() - 1;
+            let mut vertex = Vertex::surface(current, beta, r.wavelengths, medium);
+            vertex.pdf_fwd = path[prev].convert_density(pdf, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                return (Termination::MaxDepth, BLACK);
+            }
+
+            sampler.start_dimension(dimension(bsdf_dimension(bounce)));
+            let scatter = match current.material.scatter(&r, &current, sampler) {
+                Some(scatter) => scatter,
+                None => return (Termination::Absorbed, BLACK),
+            };
+            let direction = scatter.ray.direction;
+            // The ray that would arrive at the vertex from the scattered direction.
+            let reverse = Ray { origin: current.p + direction, direction: -direction, wavelengths: r.wavelengths };
+            let pdf_rev = if vertex.delta {
+                pdf = 0.0;
+                beta = beta * scatter.attenuation;
+                0.0
+            } else {
+                pdf = current.material.pdf(&r, &current, direction);
+                if pdf == 0.0 {
+                    return (Termination::Absorbed, BLACK);
+                }
+                beta = if from_camera { beta * scatter.attenuation }
+                else { beta * current.material.bsdf(&reverse, &current, -r.direction) * direction.unit_vector().dot(&current.normal).abs() / pdf };
+                current.material.pdf(&reverse, &current, -r.direction)
+            };
+            let previous = path[prev];
+            path[prev].pdf_rev = vertex.convert_density(pdf_rev, &previous);
+
+            if bounce >= self.settings.min_bounces {
+                let survival = max_component(beta).min(1.0);
+                sampler.start_dimension(dimension(russian_roulette_dimension(bounce)));
+                if sampler.get_1d() >= survival {
+                    return (Termination::Absorbed, BLACK);
+                }
+                beta = beta / survival;
+            }
+
+            bounce += 1;
+            medium = medium_towards(medium, &r, &current, direction);
+            r = scatter.ray;
+            stats::record_secondary_ray();
+            hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+            if let Some(next) = hit {
+                beta = beta * medium::transmittance(medium, r.wavelengths, next.t * r.direction.length());
+            }
+        }
+    }
+
+    // Trace a subpath carrying the given wavelengths from a point on a randomly chosen light.
+    fn light_subpath<'a>(&self, scene: &'a Scene, wavelengths: Wavelengths, sampler: &mut Sampler, path: &mut Vec<Vertex<'a>>) {
+        sampler.start_dimension(light_path_dimension(0));
+        let light = match scene.choose_light(sampler.get_1d()) {
+            Some(light) => light,
+            None => return,
+        };
+        let point = light.sample_point(sampler.get_2d());
+        let direction = point.normal + sample_unit_sphere(sampler.get_2d());
+        let cosine = point.normal.dot(&direction.unit_vector());
+        let pdf_position = scene.light_selection_pdf() / light.area();
+        let emission = wavelengths.spectrum(light.emission);
+        path.push(Vertex::light(light, &point, emission / pdf_position, pdf_position, wavelengths));
+        if cosine <= 0.0 { return }
+
+        let pdf_direction = cosine / PI;
+        let beta = emission * cosine / (pdf_position * pdf_direction);
+        let r = Ray { origin: point.p, direction, wavelengths };
+        stats::record_secondary_ray();
+        let hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+        self.random_walk(scene, r, hit, beta, pdf_direction, false, self.settings.max_depth as usize + 1, light_path_dimension, sampler, path);
+    }
+
+    // The unweighted contribution of the path made by connecting the first s vertices of the light
+    // subpath to the first t vertices of the camera subpath. Connecting to the camera or to a new
+    // point on a light replaces the end of the subpath with a newly sampled vertex, which is
+    // returned for computing the weight, along with the position of the path in the image when
+    // t = 1.
+    fn connect<'a>(&self, scene: &'a Scene, light_path: &[Vertex<'a>], camera_path: &[Vertex<'a>], s: usize, t: usize, sampler: &mut Sampler) -> (Vec3, Option<Vertex<'a>>, Option<(f64, f64)>) {
+        let none = (BLACK, None, None);
+        if s == 0 {
+            let pt = &camera_path[t - 1];
+            return (pt.beta * pt.emitted(camera_path[t - 2].p), None, None);
+        }
+        if t == 1 {
+            let qs = &light_path[s - 1];
+            if qs.delta { return none }
+            sampler.start_dimension(light_path_dimension(light_dimension(s as u32 - 2)));
+            let lens = match scene.camera.sample_lens_towards(qs.p, sampler.get_2d()) {
+                Some(lens) => lens,
+                None => return none,
+            };
+            let sampled = Vertex::camera(lens.p, scene.camera.forward(), lens.importance / lens.pdf * WHITE, qs.wavelengths);
+            let contribution = qs.beta * qs.f(lens.p, light_path[s - 2].p) * sampled.beta * qs.cosine_towards(lens.p) *
+                qs.transmittance_towards(light_path[s - 2].p, lens.p);
+            if contribution == BLACK || !is_visible(scene, qs.p, lens.p) { return none }
+            return (contribution, Some(sampled), Some((lens.s, lens.t)));
+        }
+
+        let pt = &camera_path[t - 1];
+        if pt.delta { return none }
+        if s == 1 {
+            sampler.start_dimension(light_dimension(t as u32 - 2));
+            let light = match scene.choose_light(sampler.get_1d()) {
+                Some(light) => light,
+                None => return none,
+            };
+            let point = light.sample_point(sampler.get_2d());
+            let emission = pt.wavelengths.spectrum(light.radiance(&point, pt.p - point.p));
+            let cosine = point.normal.dot(&(pt.p - point.p).unit_vector()).abs();
+            if emission == BLACK || cosine == 0.0 { return none }
+            // Density of choosing the point, with respect to solid angle at pt.
+            let pdf = scene.light_selection_pdf() * (point.p - pt.p).squared_length() / (cosine * light.area());
+            let sampled = Vertex::light(light, &point, emission / pdf, scene.light_selection_pdf() / light.area(), pt.wavelengths);
+            let contribution = pt.beta * pt.f(camera_path[t - 2].p, point.p) * sampled.beta * pt.cosine_towards(point.p) *
+                pt.transmittance_towards(camera_path[t - 2].p, point.p);
+            if contribution == BLACK || !is_visible(scene, pt.p, point.p) { return none }
+            return (contribution, Some(sampled), None);
+        }
+
+        let qs = &light_path[s - 1];
+        if qs.delta { return none }
+        let geometry = qs.cosine_towards(pt.p) * pt.cosine_towards(qs.p) / (qs.p - pt.p).squared_length();
+        let contribution = qs.beta * qs.f(pt.p, light_path[s - 2].p) * pt.f(camera_path[t - 2].p, qs.p) * pt.beta * geometry *
+            pt.transmittance_towards(camera_path[t - 2].p, qs.p);
+        if contribution == BLACK || !is_visible(scene, pt.p, qs.p) { return none }
+        return (contribution, None, None);
+    }
+
+    // Balance heuristic weight for the path made by connecting s light vertices to t camera
+    // vertices, with sampled replacing the end of the subpath it was sampled for. The weight is
+    // found from the ratios of the density of sampling the path with each other strategy to the
+    // density with this one, which only depend on the densities around each vertex. Those at the
+    // ends of the subpaths change when they're connected, so they're updated on copies.
+    fn mis_weight<'a>(&self, scene: &'a Scene, light_path: &[Vertex<'a>], camera_path: &[Vertex<'a>], sampled: Option<Vertex<'a>>, s: usize, t: usize) -> f64 {
+        if s + t == 2 { return 1.0 }
+        let mut light: Vec<Vertex> = light_path[..s].to_vec();
+        let mut camera: Vec<Vertex> = camera_path[..t].to_vec();
+        if let Some(sampled) = sampled {
+            if s == 1 { light[0] = sampled } else if t == 1 { camera[0] = sampled }
+        }
+        // A surface that isn't one of the scene's lights can only be found by the camera subpath.
+        if s == 0 && camera[t - 1].on_light(scene).is_none() { return 1.0 }
+
+        // The vertices at the ends of the subpaths are connected, so neither scatters discretely.
+        camera[t - 1].delta = false;
+        if s > 0 { light[s - 1].delta = false }
+
+        let pt = camera[t - 1];
+        camera[t - 1].pdf_rev = if s > 0 { light[s - 1].pdf(scene, if s > 1 { Some(&light[s - 2]) } else { None }, &pt) }
+        else { pt.pdf_light_origin(scene) };
+        if t > 1 {
+            let pt_minus = camera[t - 2];
+            camera[t - 2].pdf_rev = if s > 0 { pt.pdf(scene, Some(&light[s - 1]), &pt_minus) } else { pt.pdf_light(&pt_minus) };
+        }
+        if s > 0 {
+            let qs = light[s - 1];
+            light[s - 1].pdf_rev = pt.pdf(scene, if t > 1 { Some(&camera[t - 2]) } else { None }, &qs);
+            if s > 1 {
+                let qs_minus = light[s - 2];
+                light[s - 2].pdf_rev = qs.pdf(scene, Some(&pt), &qs_minus);
+            }
+        }
+
+        // Delta vertices have a density of zero in both directions, which cancel out.
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+            if !camera[i].delta && !camera[i - 1].delta { sum += ratio }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+            if !light[i].delta && (i == 0 || !light[i - 1].delta) { sum += ratio }
+        }
+        return 1.0 / (1.0 + sum);
+    }
+}
+
+impl _Integrator for Bidirectional {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, sampler: &mut Sampler, splats: &mut Vec<Splat>) -> Vec3 {
+        let max_depth = self.settings.max_depth as usize;
+
+        let mut camera_path = vec![Vertex::camera(r.origin, scene.camera.forward(), WHITE, r.wavelengths)];
+        let pdf = scene.camera.pdf_direction(&r);
+        let (termination, mut radiance) = self.random_walk(scene, r, first_hit, WHITE, pdf, true, max_depth + 2, |d| d, sampler, &mut camera_path);
+        record_termination(termination);
+
+        let mut light_path = vec![];
+        self.light_subpath(scene, r.wavelengths, sampler, &mut light_path);
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                // Connecting a light directly to the camera is left to the camera subpath.
+                if s + t < 2 || s + t - 2 > max_depth || (s == 1 && t == 1) { continue }
+                let (contribution, sampled, position) = self.connect(scene, &light_path, &camera_path, s, t, sampler);
+                if contribution == BLACK { continue }
+                let weighted = self.mis_weight(scene, &light_path, &camera_path, sampled, s, t) * contribution;
+                match position {
+                    Some((s, t)) => splats.push(Splat { s, t, colour: weighted }),
+                    None => radiance = radiance + weighted,
+                }
+            }
+        }
+        return radiance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::film::Film;
+    use crate::filter::Filter;
+    use crate::hitable::Hitable;
+    use crate::integrator::Integrator;
+    use crate::material::Material;
+    use crate::progress::QuietProgress;
+    use crate::render::{render_pass, Background, RenderSettings};
+
+    // A small scene lit by a spherical light, with glass and fuzzy metal for the light to pass
+    // through and reflect off.
+    fn lit_scene() -> Scene {
+        lit_scene_with_glass(Material::dielectric(1.5))
+    }
+
+    fn lit_scene_with_glass(glass: Material) -> Scene {
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::lambertian(0.6, 0.6, 0.6)),
+            Hitable::sphere(Vec3::new(-0.6, 0.0, -1.2), 0.5, Material::lambertian(0.7, 0.3, 0.3)),
+            Hitable::sphere(Vec3::new(0.6, 0.0, -1.2), 0.5, Material::metal(0.8, 0.8, 0.6, 0.3)),
+            Hitable::sphere(Vec3::new(0.0, -0.2, -0.6), 0.3, glass),
+            Hitable::sphere(Vec3::new(0.0, 1.2, -0.8), 0.4, Material::emissive(4.0, 4.0, 4.0)),
+        ]);
+        let camera = Camera::new(Vec3::new(0.0, 0.3, 0.8), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 70.0, 1.0, 0.0, 1.0);
+        return Scene { background: Background::Uniform(Vec3::new(0.05, 0.05, 0.05)), ..Scene::new(world, camera) };
+    }
+
+    fn average_colour(integrator: Integrator, samples: u64, spectral: bool) -> (Vec3, Film) {
+        average_colour_of(&lit_scene(), integrator, samples, spectral)
+    }
+
+    fn average_colour_of(scene: &Scene, integrator: Integrator, samples: u64, spectral: bool) -> (Vec3, Film) {
+        let settings = RenderSettings { sampler: Sampler::independent(0), filter: Filter::box_filter(0.5), adaptive: None, denoiser: None, integrator, spectral };
+        let mut film = Film::new(16, 16);
+        render_pass(scene, &mut film, &settings, samples, &QuietProgress);
+        let colours = film.colours();
+        let total = colours.iter().fold(BLACK, |total, c| total + *c);
+        return (total / colours.len() as f64, film);
+    }
+
+    #[test]
+    fn test_bidirectional_path_tracing_agrees_with_path_tracing() {
+        let (path, _) = average_colour(Integrator::path(PathSettings::default()), 1024, false);
+        let (bidirectional, film) = average_colour(Integrator::bidirectional(PathSettings::default()), 128, false);
+        assert!((bidirectional - path).length() < 0.01 * path.length(), "{} with bidirectional path tracing, {} with path tracing", bidirectional, path);
+        // Some of the light reaches the camera through paths traced from the light.
+        assert!(film.splats.iter().any(|splat| splat.length() > 0.0));
+    }
+
+    #[test]
+    fn test_bidirectional_path_tracing_agrees_with_path_tracing_through_rough_glass() {
+        // Connections to the light evaluate the rough glass's BSDF for directions that weren't
+        // sampled, in both directions through it.
+        let scene = lit_scene_with_glass(Material::rough_dielectric(1.5, 0.3));
+        let (path, _) = average_colour_of(&scene, Integrator::path(PathSettings::default()), 1024, false);
+        let (bidirectional, _) = average_colour_of(&scene, Integrator::bidirectional(PathSettings::default()), 128, false);
+        assert!((bidirectional - path).length() < 0.01 * path.length(), "{} with bidirectional path tracing, {} with path tracing", bidirectional, path);
+    }
+
+    #[test]
+    fn test_spectral_bidirectional_path_tracing_agrees_with_spectral_path_tracing() {
+        // Light subpaths carry the same wavelengths as the camera subpaths they're connected to.
+        let (path, _) = average_colour(Integrator::path(PathSettings::default()), 1024, true);
+        let (bidirectional, _) = average_colour(Integrator::bidirectional(PathSettings::default()), 128, true);
+        assert!((bidirectional - path).length() < 0.02 * path.length(), "{} with bidirectional path tracing, {} with path tracing", bidirectional, path);
+    }
+
+    #[test]
+    fn test_without_lights_to_sample_bidirectional_path_tracing_follows_the_same_paths_as_path_tracing() {
+        // With no lights to sample, the light from the emissive sphere can only be found by hitting
+        // it. The camera subpaths use the same samples as the path tracer, so only the depth limits
+        // for each kind of bounce, which are raised here, could make them differ.
+        let settings = PathSettings { max_diffuse_depth: 50, max_specular_depth: 50, max_transmission_depth: 50, ..PathSettings::default() };
+        let scene = Scene { lights: vec![], ..lit_scene() };
+        let mut sampler = Sampler::independent(0);
+        for i in 0..64 {
+            sampler.start_pixel_sample(i, 0, 0);
+            let r = scene.camera.get_ray(i as f64 / 64.0, 0.5, (0.5, 0.5));
+            let hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+            let path = Integrator::path(settings).radiance(r, hit, &scene, &PassState::new(1), &mut sampler.clone(), &mut vec![]);
+            let mut splats = vec![];
+            let bidirectional = Integrator::bidirectional(settings).radiance(r, hit, &scene, &PassState::new(1), &mut sampler, &mut splats);
+            assert!((path - bidirectional).length() < 1e-12, "{} with bidirectional path tracing, {} with path tracing", bidirectional, path);
+            assert!(splats.is_empty());
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat};
+use crate::integrator::path::{trace_path, PathSettings};
+use crate::output::heat_colour;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+// Traces paths like the path tracer, but shows the number of bounces they took rather than the
+// light they carried, with paths of scale bounces or more shown as white. This shows where paths
+// are long, such as inside glass, and where Russian roulette and the depth limits end them.
+#[derive(Debug, Copy, Clone)]
+pub struct BounceHeatmap {
+    pub settings: PathSettings,
+    pub scale: u32,
+}
+
+impl _Integrator for BounceHeatmap {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        if first_hit.is_none() { return Vec3::new(0.0, 0.0, 0.0) }
+        let (_, bounces) = trace_path(r, first_hit, scene, &self.settings, sampler);
+        return heat_colour(bounces as f64 / self.scale as f64);
+    }
+}
@@ -0,0 +1,23 @@
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+// Shows the distance to the first hit, from white at the camera fading to black at the far
+// distance. Rays that miss are black.
+#[derive(Debug, Copy, Clone)]
+pub struct Depth {
+    pub far: f64,
+}
+
+impl _Integrator for Depth {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, _scene: &Scene, _pass: &PassState, _sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        let brightness = match first_hit {
+            Some(hit) => (1.0 - hit.t * r.direction.length() / self.far).max(0.0),
+            None => 0.0,
+        };
+        return Vec3::new(brightness, brightness, brightness);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat, NEAR_ZERO};
+use crate::ray::Ray;
+use crate::sampler::{bsdf_dimension, light_dimension, Sampler};
+use crate::scene::Scene;
+use crate::stats;
+use crate::vec3::Vec3;
+
+// Only light that reaches the first hit directly from the lights and the background, ignoring
+// light that has bounced off other surfaces. Comparing this with the path tracer shows how much
+// indirect light contributes to a scene.
+//
+// Light from the scene's lights is estimated both by choosing a point on a light and by following
+// the direction chosen by sampling the material, with the two combined using multiple importance
+// sampling so that small lights and sharp reflections are both handled well. The background is
+// only found by sampling the material.
+#[derive(Debug, Copy, Clone)]
+pub struct DirectLighting {}
+
+impl DirectLighting {
+    // Light arriving at the hit from a point chosen on one of the lights, weighted against the
+    // chance that sampling the material would have found the same point.
+    fn sample_light(&self, r: &Ray, hit: &HitRecord, scene: &Scene, sampler: &mut Sampler) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        sampler.start_dimension(light_dimension(0));
+        let light = match scene.choose_light(sampler.get_1d()) {
+            Some(light) => light,
+            None => return black,
+        };
+        let point = light.sample_point(sampler.get_2d());
+        let to_light = point.p - hit.p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let emission = r.wavelengths.spectrum(light.radiance(&point, -direction));
+        let bsdf = hit.material.bsdf(r, hit, direction);
+        if emission == black || bsdf == black { return black }
+
+        stats::record_secondary_ray();
+        if scene.world.hit(&Ray { origin: hit.p, direction, wavelengths: r.wavelengths }, NEAR_ZERO, distance - NEAR_ZERO).is_some() {
+            return black;
+        }
+        let light_pdf = scene.light_selection_pdf() * distance * distance / (point.normal.dot(&direction).abs() * light.area());
+        let weight = power_heuristic(light_pdf, hit.material.pdf(r, hit, direction));
+        return weight * bsdf * emission * direction.dot(&hit.normal).abs() / light_pdf;
+    }
+}
+
+impl _Integrator for DirectLighting {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        let hit = match first_hit {
+            Some(hit) => hit,
+            None => return scene.background.colour(&r),
+        };
+        let mut radiance = hit.material.emitted(&r, &hit);
+        if !hit.material.is_delta() {
+            radiance = radiance + self.sample_light(&r, &hit, scene, sampler);
+        }
+
+        sampler.start_dimension(bsdf_dimension(0));
+        let scatter = match hit.material.scatter(&r, &hit, sampler) {
+            Some(scatter) => scatter,
+            None => return radiance,
+        };
+        stats::record_secondary_ray();
+        let throughput = scatter.attenuation;
+        match scene.world.hit(&scatter.ray, NEAR_ZERO, f64::MAX) {
+            None => radiance + throughput * scene.background.colour(&scatter.ray),
+            Some(next) => {
+                let emission = next.material.emitted(&scatter.ray, &next);
+                // Light from a sharp reflection can't be found by sampling the lights, so it
+                // isn't weighted.
+                let weight = match scene.light(&next) {
+                    Some(light) if !hit.material.is_delta() => {
+                        let distance = next.t * scatter.ray.direction.length();
+                        let cosine = scatter.ray.direction.unit_vector().dot(&next.normal).abs();
+                        let light_pdf = scene.light_selection_pdf() * distance * distance / (cosine * light.area());
+                        power_heuristic(hit.material.pdf(&r, &hit, scatter.ray.direction), light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                radiance + weight * throughput * emission
+            }
+        }
+    }
+}
+
+// Weight for a sample taken with the first of two strategies that have the given densities.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    return if a + b == 0.0 { 0.0 } else { a / (a + b) };
+}
@@ -0,0 +1,246 @@
+use rayon::prelude::*;
+
+use crate::film::luminance;
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat, NEAR_ZERO};
+use crate::integrator::path::{trace_path, PathSettings};
+use crate::random::{hash, Rng};
+use crate::ray::Ray;
+use crate::sampler::metropolis::MetropolisSampler;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::stats;
+use crate::vec3::Vec3;
+
+// Settings for Metropolis light transport.
+#[derive(Debug, Copy, Clone)]
+pub struct MetropolisSettings {
+    // Number of independent paths traced at the start of each pass to estimate the brightness of
+    // the image and to choose where the chains start.
+    pub bootstrap_samples: u32,
+    // Number of Markov chains that the mutations of each pass are shared between.
+    pub chains: u32,
+    // Chance of each mutation being a large step, which can jump anywhere in the image, rather
+    // than a small step, which explores the paths near the current one.
+    pub large_step_probability: f64,
+    // Standard deviation of the offsets that small steps make in primary sample space.
+    pub sigma: f64,
+}
+
+impl Default for MetropolisSettings {
+    fn default() -> Self {
+        MetropolisSettings { bootstrap_samples: 100_000, chains: 256, large_step_probability: 0.3, sigma: 0.01 }
+    }
+}
+
+// Primary sample space Metropolis light transport, following Kelemen et al. Paths are traced by
+// the path tracer, but rather than tracing independent paths for each pixel, Markov chains
+// wander through the space of random numbers that the paths are made from, with each step
+// proposing a mutation of the current path and accepting it with a chance that depends on how
+// much brighter it is. Once a chain finds a path that carries light, such as one through a narrow
+// gap, it explores the paths around it, so light that is hard to find is sampled far more often
+// than by the path tracer.
+//
+// The chains visit each part of the image in proportion to its brightness, so their samples are
+// splatted onto the film, scaled by the average brightness of the image. That is estimated at the
+// start of each pass by tracing a number of independent paths, which are also used to choose where
+// each chain starts in proportion to the light they carry, which avoids start-up bias.
+#[derive(Debug, Copy, Clone)]
+pub struct Metropolis {
+    pub settings: PathSettings,
+    pub metropolis: MetropolisSettings,
+}
+
+// The independent paths traced at the start of a pass.
+pub struct Bootstrap {
+    seed: u64,
+    // Cumulative sum of the brightness of the paths.
+    cdf: Vec<f64>,
+    // Average brightness of the paths, which estimates the average brightness of the image.
+    pub brightness: f64,
+    // Whether the paths of the pass are traced at sampled wavelengths.
+    spectral: bool,
+}
+
+impl Bootstrap {
+    // Choose one of the paths in proportion to its brightness.
+    fn choose(&self, u: f64) -> Option<usize> {
+        let total = *self.cdf.last()?;
+        if total <= 0.0 { return None }
+        let index = self.cdf.partition_point(|c| *c <= u * total);
+        return Some(index.min(self.cdf.len() - 1));
+    }
+
+    // Seed for the sampler that traces one of the paths.
+    fn path_seed(&self, index: usize) -> u64 {
+        hash(&[self.seed, index as u64])
+    }
+}
+
+// The state of one of the Markov chains of a pass.
+pub struct MarkovChain {
+    sampler: Sampler,
+    rng: Rng,
+    // The light carried by the current path and where on the film it lands.
+    colour: Vec3,
+    s: f64,
+    t: f64,
+}
+
+// Offset for the seeds of the chains' own random numbers, so that they differ from the seeds of
+// the bootstrap paths.
+const CHAIN_SEED: u64 = 1 << 32;
+
+impl Metropolis {
+
+    fn sampler(&self, seed: u64) -> Sampler {
+        Sampler::metropolis(seed, self.metropolis.sigma, self.metropolis.large_step_probability)
+    }
+
+    // Trace the path given by the sampler's current point in primary sample space. The pixel
+    // dimensions choose where on the whole film the path lands, rather than a position within a
+    // pixel. Returns the light the path carries, as RGB, along with its position on the film.
+    fn trace(&self, scene: &Scene, spectral: bool, sampler: &mut Sampler) -> (Vec3, f64, f64) {
+        stats::record_primary_ray();
+        let camera_sample = sampler.camera_sample();
+        let (s, t) = camera_sample.pixel;
+        let wavelengths = if spectral { sampler.wavelengths() } else { Wavelengths::Rgb };
+        let r = Ray { wavelengths, ..scene.camera.get_ray(s, t, camera_sample.lens) };
+        let hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+        return (wavelengths.to_rgb(trace_path(r, hit, scene, &self.settings, sampler).0), s, t);
+    }
+
+    // Trace the independent paths for the given pass, at sampled wavelengths if spectral is set.
+    pub fn bootstrap(&self, scene: &Scene, seed: u64, pass: u64, spectral: bool) -> Bootstrap {
+        let seed = hash(&[seed, pass]);
+        let bootstrap = Bootstrap { seed, cdf: vec![], brightness: 0.0, spectral };
+        let brightness: Vec<f64> = (0..self.metropolis.bootstrap_samples as usize).into_par_iter().map(|index| {
+            let mut sampler = self.sampler(bootstrap.path_seed(index));
+            luminance(self.trace(scene, spectral, &mut sampler).0)
+        }).collect();
+
+        let cdf: Vec<f64> = brightness.iter().scan(0.0, |total, b| { *total += b; Some(*total) }).collect();
+        let average = cdf.last().map_or(0.0, |total| total / cdf.len() as f64);
+        return Bootstrap { cdf, brightness: average, ..bootstrap };
+    }
+
+    // Start the chain with the given index from one of the bootstrap paths. There is no chain if
+    // none of the paths carried any light.
+    pub fn start_chain(&self, scene: &Scene, bootstrap: &Bootstrap, chain: u32) -> Option<MarkovChain> {
+        let mut rng = Rng::new(hash(&[bootstrap.seed, CHAIN_SEED + chain as u64]));
+        let index = bootstrap.choose(rng.next_f64())?;
+        // Tracing the path again with the same seed recreates it, leaving the sampler at its point.
+        let mut sampler = self.sampler(bootstrap.path_seed(index));
+        let (colour, s, t) = self.trace(scene, bootstrap.spectral, &mut sampler);
+        return Some(MarkovChain { sampler, rng, colour, s, t });
+    }
+
+    // Propose a mutation of the chain's path, and accept or reject it. Both the current and the
+    // proposed paths are splatted, weighted by the chance of accepting the proposal, which
+    // reduces noise compared to only splatting the path that the chain moves to.
+    pub fn mutate(&self, scene: &Scene, bootstrap: &Bootstrap, chain: &mut MarkovChain, splats: &mut Vec<Splat>) {
+        primary_sample_space(&mut chain.sampler).start_iteration();
+        let (colour, s, t) = self.trace(scene, bootstrap.spectral, &mut chain.sampler);
+
+        let (current, proposed) = (luminance(chain.colour), luminance(colour));
+        let accept = if current > 0.0 { (proposed / current).min(1.0) } else { 1.0 };
+        if accept > 0.0 && proposed > 0.0 {
+            splats.push(Splat { s, t, colour: colour * accept * bootstrap.brightness / proposed });
+        }
+        if accept < 1.0 {
+            splats.push(Splat { s: chain.s, t: chain.t, colour: chain.colour * (1.0 - accept) * bootstrap.brightness / current });
+        }
+
+        if chain.rng.next_f64() < accept {
+            primary_sample_space(&mut chain.sampler).accept();
+            chain.colour = colour;
+            chain.s = s;
+            chain.t = t;
+        }
+        else {
+            primary_sample_space(&mut chain.sampler).reject();
+        }
+    }
+}
+
+fn primary_sample_space(sampler: &mut Sampler) -> &mut MetropolisSampler {
+    match *sampler {
+        Sampler::Metropolis(ref mut sampler) => sampler,
+        _ => unreachable!("Markov chains always use the Metropolis sampler"),
+    }
+}
+
+impl _Integrator for Metropolis {
+    // Metropolis light transport renders the whole film at once, so this is only used when a
+    // single camera ray is traced, which gives the same result as the path tracer.
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        trace_path(r, first_hit, scene, &self.settings, sampler).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::film::Film;
+    use crate::filter::Filter;
+    use crate::hitable::Hitable;
+    use crate::integrator::Integrator;
+    use crate::material::Material;
+    use crate::progress::QuietProgress;
+    use crate::render::{render_pass, Background, RenderSettings};
+
+    // A diffuse floor and sphere lit by a small light behind the camera, which is partly hidden
+    // behind a wall.
+    fn hidden_light_scene() -> Scene {
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::lambertian(0.6, 0.6, 0.6)),
+            Hitable::sphere(Vec3::new(0.0, 0.0, -1.2), 0.5, Material::lambertian(0.7, 0.3, 0.3)),
+            Hitable::sphere(Vec3::new(-101.0, 0.0, -1.0), 100.0, Material::lambertian(0.5, 0.5, 0.5)),
+            Hitable::sphere(Vec3::new(-0.8, 1.0, 1.2), 0.2, Material::emissive(10.0, 10.0, 10.0)),
+        ]);
+        let camera = Camera::new(Vec3::new(0.5, 0.3, 0.8), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 70.0, 1.0, 0.0, 1.0);
+        return Scene { background: Background::Uniform(Vec3::new(0.0, 0.0, 0.0)), ..Scene::new(world, camera) };
+    }
+
+    fn render(integrator: Integrator, samples: u64, seed: u64) -> Vec<Vec3> {
+        let settings = RenderSettings { sampler: Sampler::independent(seed), filter: Filter::box_filter(0.5), adaptive: None, denoiser: None, integrator, spectral: false };
+        let mut film = Film::new(16, 16);
+        render_pass(&hidden_light_scene(), &mut film, &settings, samples, &QuietProgress);
+        return film.colours();
+    }
+
+    fn metropolis() -> Integrator {
+        Integrator::metropolis(PathSettings::default(), MetropolisSettings { bootstrap_samples: 500_000, chains: 64, ..MetropolisSettings::default() })
+    }
+
+    // The average colour of each 4x4 block of pixels.
+    fn blocks(colours: &[Vec3]) -> Vec<Vec3> {
+        (0..16).map(|block| {
+            let (bx, by) = (4 * (block % 4), 4 * (block / 4));
+            let total = (0..16).fold(Vec3::new(0.0, 0.0, 0.0), |total, i| total + colours[(by + i / 4) * 16 + bx + i % 4]);
+            total / 16.0
+        }).collect()
+    }
+
+    #[test]
+    fn test_metropolis_light_transport_agrees_with_path_tracing() {
+        let path = blocks(&render(Integrator::path(PathSettings::default()), 1024, 0));
+        let metropolis = blocks(&render(metropolis(), 1024, 0));
+        let average = |blocks: &[Vec3]| blocks.iter().fold(Vec3::new(0.0, 0.0, 0.0), |total, b| total + *b) / blocks.len() as f64;
+        let (path_average, metropolis_average) = (average(&path), average(&metropolis));
+        assert!((metropolis_average - path_average).length() < 0.1 * path_average.length(), "{} with Metropolis, {} with path tracing", metropolis_average, path_average);
+        // Metropolis spends fewer samples on the darker parts of the image, so they're noisier.
+        for (m, p) in metropolis.iter().zip(path.iter()) {
+            assert!((*m - *p).length() < 0.5 * path_average.length(), "{} with Metropolis, {} with path tracing", m, p);
+        }
+    }
+
+    #[test]
+    fn test_metropolis_light_transport_is_deterministic_for_a_seed() {
+        let first = render(metropolis(), 16, 1);
+        assert!(first == render(metropolis(), 16, 1));
+        assert!(first != render(metropolis(), 16, 2));
+    }
+}
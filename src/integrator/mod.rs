@@ -0,0 +1,316 @@
+use crate::hitable::HitRecord;
+use crate::integrator::ambient_occlusion::AmbientOcclusion;
+use crate::integrator::bidirectional::Bidirectional;
+use crate::integrator::bounce_heatmap::BounceHeatmap;
+use crate::integrator::depth::Depth;
+use crate::integrator::direct_lighting::DirectLighting;
+use crate::integrator::normals::Normals;
+use crate::integrator::metropolis::{Metropolis, MetropolisSettings};
+use crate::integrator::path::{PathSettings, PathTracer};
+use crate::integrator::photon_mapping::{PhotonMapping, PhotonSettings};
+use crate::integrator::whitted::Whitted;
+use crate::photon_map::PhotonMap;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+pub mod path;
+pub mod direct_lighting;
+pub mod whitted;
+pub mod normals;
+pub mod depth;
+pub mod ambient_occlusion;
+pub mod bounce_heatmap;
+pub mod bidirectional;
+pub mod photon_mapping;
+pub mod metropolis;
+
+// Integrators compute the colour seen along each camera ray. Besides the path tracer, which gives
+// the final image, there are simpler integrators that are quick to render and useful for
+// diagnosing problems with scenes, such as missing normals, objects in the wrong place or light
+// leaking through geometry.
+
+pub const NEAR_ZERO: f64 = 0.001; // Treat hits that are less than this value as zero.
+
+// Light that a sample contributes to some other point of the image than the one it was taken for,
+// such as when a path from a light is connected to the camera. The position is in the same
+// coordinates that Camera::get_ray takes.
+#[derive(Debug, Copy, Clone)]
+pub struct Splat {
+    pub s: f64,
+    pub t: f64,
+    pub colour: Vec3,
+}
+
+// State shared by all of the samples in a pass, which integrators can prepare before the pass
+// starts.
+pub struct PassState {
+    // Passes are numbered from 1.
+    pub number: u64,
+    // The caustic photons used by the photon mapping integrators.
+    pub photons: Option<PhotonMap>,
+}
+
+impl PassState {
+    pub fn new(number: u64) -> PassState {
+        return PassState { number, photons: None };
+    }
+}
+
+// Internal trait that defines the API for underlying Integrators.
+// Note that the Integrator enum forms the public API for integrators and wraps these private types.
+trait _Integrator {
+    // Estimate the light arriving along a camera ray, given its first intersection with the world.
+    // Any light that the sample contributes elsewhere in the image is added to splats.
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, pass: &PassState, sampler: &mut Sampler, splats: &mut Vec<Splat>) -> Vec3;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Integrator {
+    Path(PathTracer),
+    DirectLighting(DirectLighting),
+    Whitted(Whitted),
+    Normals(Normals),
+    Depth(Depth),
+    AmbientOcclusion(AmbientOcclusion),
+    BounceHeatmap(BounceHeatmap),
+    Bidirectional(Bidirectional),
+    PhotonMapping(PhotonMapping),
+    Metropolis(Metropolis),
+}
+
+// Names of the integrators, as accepted by Integrator::from_name.
+pub const INTEGRATOR_NAMES: [&str; 11] = ["path", "bdpt", "photon", "ppm", "mlt", "direct", "whitted", "normals", "depth", "ao", "bounces"];
+
+// Distance within which ambient occlusion looks for occluders, and the depth that the depth
+// integrator shows as black, when chosen by name. These suit the built-in scenes.
+const AMBIENT_OCCLUSION_DISTANCE: f64 = 1.0;
+const DEPTH_FAR: f64 = 20.0;
+
+// Number of bounces shown as white by the bounce heatmap when chosen by name.
+const BOUNCE_HEATMAP_SCALE: u32 = 10;
+
+impl Integrator {
+
+    pub fn path(settings: PathSettings) -> Integrator {
+        return Integrator::Path(PathTracer { settings });
+    }
+
+    pub fn bidirectional(settings: PathSettings) -> Integrator {
+        return Integrator::Bidirectional(Bidirectional { settings });
+    }
+
+    pub fn photon_mapping(settings: PathSettings, photons: PhotonSettings) -> Integrator {
+        return Integrator::PhotonMapping(PhotonMapping { settings, photons, progressive: false });
+    }
+
+    pub fn progressive_photon_mapping(settings: PathSettings, photons: PhotonSettings) -> Integrator {
+        return Integrator::PhotonMapping(PhotonMapping { settings, photons, progressive: true });
+    }
+
+    pub fn metropolis(settings: PathSettings, metropolis: MetropolisSettings) -> Integrator {
+        return Integrator::Metropolis(Metropolis { settings, metropolis });
+    }
+
+    pub fn direct_lighting() -> Integrator {
+        return Integrator::DirectLighting(DirectLighting {});
+    }
+
+    pub fn whitted(max_depth: u32) -> Integrator {
+        return Integrator::Whitted(Whitted { max_depth });
+    }
+
+    pub fn normals() -> Integrator {
+        return Integrator::Normals(Normals {});
+    }
+
+    pub fn depth(far: f64) -> Integrator {
+        return Integrator::Depth(Depth { far });
+    }
+
+    pub fn ambient_occlusion(distance: f64) -> Integrator {
+        return Integrator::AmbientOcclusion(AmbientOcclusion { distance });
+    }
+
+    pub fn bounce_heatmap(settings: PathSettings, scale: u32) -> Integrator {
+        return Integrator::BounceHeatmap(BounceHeatmap { settings, scale });
+    }
+
+    // Create one of the integrators listed in INTEGRATOR_NAMES. The path settings are used by the
+    // integrators that trace paths and the photon settings by those that trace photons.
+    pub fn from_name(name: &str, settings: PathSettings, photons: PhotonSettings) -> Option<Integrator> {
+        match name {
+            "path" => Some(Integrator::path(settings)),
+            "bdpt" => Some(Integrator::bidirectional(settings)),
+            "photon" => Some(Integrator::photon_mapping(settings, photons)),
+            "ppm" => Some(Integrator::progressive_photon_mapping(settings, photons)),
+            "mlt" => Some(Integrator::metropolis(settings, MetropolisSettings::default())),
+            "direct" => Some(Integrator::direct_lighting()),
+            "whitted" => Some(Integrator::whitted(settings.max_depth)),
+            "normals" => Some(Integrator::normals()),
+            "depth" => Some(Integrator::depth(DEPTH_FAR)),
+            "ao" => Some(Integrator::ambient_occlusion(AMBIENT_OCCLUSION_DISTANCE)),
+            "bounces" => Some(Integrator::bounce_heatmap(settings, BOUNCE_HEATMAP_SCALE)),
+            _ => None,
+        }
+    }
+
+    // Prepare the state for the pass with the given number, which is shared by all of its samples.
+    pub fn start_pass(&self, scene: &Scene, number: u64) -> PassState {
+        match *self {
+            Integrator::PhotonMapping(ref photon_mapping) => PassState { number, photons: Some(photon_mapping.trace_photons(scene, number)) },
+            _ => PassState::new(number),
+        }
+    }
+
+    pub fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, pass: &PassState, sampler: &mut Sampler, splats: &mut Vec<Splat>) -> Vec3 {
+        match *self {
+            Integrator::Path(ref path) => path.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::DirectLighting(ref direct) => direct.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::Whitted(ref whitted) => whitted.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::Normals(ref normals) => normals.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::Depth(ref depth) => depth.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::AmbientOcclusion(ref ao) => ao.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::BounceHeatmap(ref heatmap) => heatmap.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::Bidirectional(ref bidirectional) => bidirectional.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::PhotonMapping(ref photon_mapping) => photon_mapping.radiance(r, first_hit, scene, pass, sampler, splats),
+            Integrator::Metropolis(ref metropolis) => metropolis.radiance(r, first_hit, scene, pass, sampler, splats),
+        }
+    }
+}
+
+// The normal on the side of the surface that the ray arrived from.
+fn facing_normal(r: &Ray, hit: &HitRecord) -> Vec3 {
+    return if r.direction.dot(&hit.normal) > 0.0 { -hit.normal } else { hit.normal };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hitable::Hitable;
+    use crate::material::Material;
+    use crate::render::Background;
+    use crate::spectrum::Wavelengths;
+
+    const WHITE: Vec3 = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+
+    // A ray looking down the z axis at a sphere, and a ray that misses it.
+    fn rays() -> (Ray, Ray) {
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        (Ray { origin, direction: Vec3::new(0.0, 0.0, -1.0), wavelengths: Wavelengths::Rgb }, Ray { origin, direction: Vec3::new(0.0, 1.0, 0.0), wavelengths: Wavelengths::Rgb })
+    }
+
+    fn radiance(integrator: Integrator, world: &Hitable, r: Ray, background: Background) -> Vec3 {
+        let camera = Camera::new(r.origin, Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 90.0, 1.0, 0.0, 1.0);
+        let scene = Scene { background, ..Scene::new(world.clone(), camera) };
+        let mut sampler = Sampler::independent(0);
+        sampler.start_pixel_sample(0, 0, 0);
+        integrator.radiance(r, world.hit(&r, NEAR_ZERO, f64::MAX), &scene, &PassState::new(1), &mut sampler, &mut vec![])
+    }
+
+    fn sphere(material: Material) -> Hitable {
+        Hitable::hitable_list(vec![Hitable::sphere(Vec3::new(0.0, 0.0, -2.0), 1.0, material)])
+    }
+
+    #[test]
+    fn test_integrator_names_are_recognised() {
+        for name in INTEGRATOR_NAMES.iter() {
+            assert!(Integrator::from_name(name, PathSettings::default(), PhotonSettings::default()).is_some(), "{} isn't recognised", name);
+        }
+        assert!(Integrator::from_name("unknown", PathSettings::default(), PhotonSettings::default()).is_none());
+    }
+
+    #[test]
+    fn test_normals_integrator_maps_normals_to_colours() {
+        let world = sphere(Material::lambertian(0.5, 0.5, 0.5));
+        let (hit, miss) = rays();
+        assert!((radiance(Integrator::normals(), &world, hit, Background::Sky) - Vec3::new(0.5, 0.5, 1.0)).length() < 1e-9);
+        assert_eq!(radiance(Integrator::normals(), &world, miss, Background::Sky), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_depth_integrator_fades_to_black_at_the_far_distance() {
+        let world = sphere(Material::lambertian(0.5, 0.5, 0.5));
+        let (hit, miss) = rays();
+        // The sphere is at a distance of 1.
+        assert!((radiance(Integrator::depth(4.0), &world, hit, Background::Sky) - 0.75 * WHITE).length() < 1e-9);
+        assert_eq!(radiance(Integrator::depth(0.5), &world, hit, Background::Sky), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(radiance(Integrator::depth(4.0), &world, miss, Background::Sky), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_ambient_occlusion_is_only_occluded_by_nearby_surfaces() {
+        // Looking from inside a sphere, every occlusion ray hits the far side, at a distance of at
+        // most 2.
+        let world = Hitable::hitable_list(vec![Hitable::sphere(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::lambertian(0.5, 0.5, 0.5))]);
+        let (r, _) = rays();
+        assert_eq!(radiance(Integrator::ambient_occlusion(2.5), &world, r, Background::Sky), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(radiance(Integrator::ambient_occlusion(0.001), &world, r, Background::Sky), WHITE);
+    }
+
+    #[test]
+    fn test_whitted_integrator_follows_mirrors_and_glass() {
+        let background = Background::Uniform(Vec3::new(0.2, 0.4, 0.6));
+        let (r, _) = rays();
+        let mirror = sphere(Material::metal(0.5, 0.5, 0.5, 0.0));
+        assert!((radiance(Integrator::whitted(10), &mirror, r, background) - Vec3::new(0.1, 0.2, 0.3)).length() < 1e-9);
+        // Clear glass neither absorbs nor emits light, so in a uniform environment it's invisible.
+        let glass = sphere(Material::dielectric(1.5));
+        assert!((radiance(Integrator::whitted(10), &glass, r, background) - Vec3::new(0.2, 0.4, 0.6)).length() < 1e-3);
+    }
+
+    #[test]
+    fn test_lights_embedded_in_coloured_glass_are_absorbed_over_the_distance_to_them() {
+        // A light 1.5 inside glass that doesn't bend or reflect light head on, seen straight
+        // through the glass, along a ray that isn't normalised so that the glass sees it as bent.
+        // The segment inside ends on the light rather than on the glass, so it can only be
+        // absorbed by keeping track of the medium the path is in.
+        let absorption = Vec3::new(0.5, 0.1, 0.8);
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, 0.0, -3.0), 2.0, Material::absorbing_dielectric(1.0, absorption)),
+            Hitable::sphere(Vec3::new(0.0, 0.0, -3.0), 0.5, Material::emissive(4.0, 4.0, 4.0)),
+        ]);
+        let r = Ray { origin: Vec3::new(0.0, 0.0, 0.0), direction: Vec3::new(0.0, 0.0, -2.0), wavelengths: Wavelengths::Rgb };
+        let expected = 4.0 * Vec3::new((-0.75_f64).exp(), (-0.15_f64).exp(), (-1.2_f64).exp());
+        for integrator in [Integrator::path(PathSettings::default()), Integrator::bidirectional(PathSettings::default()), Integrator::whitted(10)] {
+            let colour = radiance(integrator, &world, r, Background::Uniform(Vec3::new(0.0, 0.0, 0.0)));
+            assert!((colour - expected).length() < 1e-9, "{} isn't {}", colour, expected);
+        }
+    }
+
+    #[test]
+    fn test_bounce_heatmap_is_black_for_rays_that_miss() {
+        let world = sphere(Material::lambertian(0.5, 0.5, 0.5));
+        let (hit, miss) = rays();
+        let heatmap = Integrator::bounce_heatmap(PathSettings::default(), 10);
+        assert_eq!(radiance(heatmap, &world, miss, Background::Sky), Vec3::new(0.0, 0.0, 0.0));
+        assert!(radiance(heatmap, &world, hit, Background::Sky).r() > 0.0);
+    }
+
+    #[test]
+    fn test_direct_lighting_agrees_with_a_single_bounce_of_path_tracing() {
+        // A diffuse sphere lit from above by a spherical light, looking at the top of the sphere.
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, 0.0, -2.0), 1.0, Material::lambertian(0.5, 0.5, 0.5)),
+            Hitable::sphere(Vec3::new(0.0, 2.5, -0.5), 0.5, Material::emissive(2.0, 2.0, 2.0)),
+        ]);
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 90.0, 1.0, 0.0, 1.0);
+        let scene = Scene { background: Background::Uniform(Vec3::new(0.1, 0.1, 0.1)), ..Scene::new(world, camera) };
+        let r = Ray { origin: Vec3::new(0.0, 0.0, 0.0), direction: Vec3::new(0.0, 0.7, -2.0), wavelengths: Wavelengths::Rgb };
+        let hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+
+        let average = |integrator: Integrator| {
+            let mut sampler = Sampler::independent(0);
+            let samples = 50000;
+            (0..samples).fold(Vec3::new(0.0, 0.0, 0.0), |total, i| {
+                sampler.start_pixel_sample(0, 0, i);
+                total + integrator.radiance(r, hit, &scene, &PassState::new(1), &mut sampler, &mut vec![])
+            }) / samples as f64
+        };
+        let direct = average(Integrator::direct_lighting());
+        let path = average(Integrator::path(PathSettings { max_depth: 1, min_bounces: 1, ..PathSettings::default() }));
+        assert!((direct - path).length() < 0.03 * path.length(), "{} with direct lighting, {} with path tracing", direct, path);
+    }
+}
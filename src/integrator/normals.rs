@@ -0,0 +1,20 @@
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+// Shows the shading normal at the first hit, mapping each component from [-1, 1] to [0, 1]. Rays
+// that miss are black.
+#[derive(Debug, Copy, Clone)]
+pub struct Normals {}
+
+impl _Integrator for Normals {
+    fn radiance(&self, _r: Ray, first_hit: Option<HitRecord>, _scene: &Scene, _pass: &PassState, _sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        match first_hit {
+            Some(hit) => 0.5 * (hit.shading_normal + Vec3::new(1.0, 1.0, 1.0)),
+            None => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat, NEAR_ZERO};
+use crate::material::Lobe;
+use crate::medium::{self, medium_towards};
+use crate::ray::Ray;
+use crate::sampler::{bsdf_dimension, russian_roulette_dimension, Sampler};
+use crate::scene::Scene;
+use crate::stats::{self, record_termination, Termination};
+use crate::vec3::Vec3;
+
+// Limits on the length of the paths traced by the path tracer.
+#[derive(Debug, Copy, Clone)]
+pub struct PathSettings {
+    // Maximum number of bounces of any kind.
+    pub max_depth: u32,
+    // Maximum number of bounces of each kind of scattering. Light that has been through many
+    // diffuse bounces contributes little, whereas following glass and mirrors for longer avoids
+    // darkening them.
+    pub max_diffuse_depth: u32,
+    pub max_specular_depth: u32,
+    pub max_transmission_depth: u32,
+    // Number of bounces before Russian roulette may end a path.
+    pub min_bounces: u32,
+}
+
+impl PathSettings {
+    // Has a path with these numbers of bounces of each kind exceeded the limits?
+    pub fn is_too_deep(&self, depth: &PathDepth) -> bool {
+        depth.diffuse > self.max_diffuse_depth || depth.specular > self.max_specular_depth ||
+            depth.transmission > self.max_transmission_depth
+    }
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings { max_depth: 50, max_diffuse_depth: 16, max_specular_depth: 32, max_transmission_depth: 32, min_bounces: 3 }
+    }
+}
+
+// Number of bounces of each kind taken by a path so far.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PathDepth {
+    pub diffuse: u32,
+    pub specular: u32,
+    pub transmission: u32,
+}
+
+impl PathDepth {
+    pub fn add(&mut self, lobe: Lobe) {
+        match lobe {
+            Lobe::Diffuse => self.diffuse += 1,
+            Lobe::Specular => self.specular += 1,
+            Lobe::Transmission => self.transmission += 1,
+        }
+    }
+}
+
+// Estimate the light arriving along a ray, given its first intersection with the world, returning
+// it along with the number of bounces the path took. The path is followed iteratively, keeping the
+// throughput (the fraction of light carried back along the path to the camera) separately from the
+// radiance gathered so far, which is the light emitted by the surfaces the path hits.
+//
+// After settings.min_bounces bounces, Russian roulette ends paths with a probability that grows as
+// their throughput falls, and the throughput of the paths that survive is divided by their chance
+// of surviving. This keeps the estimate unbiased while spending less time on paths that can only
+// contribute a little.
+//
+// Paths start in clear space, and keep track of the medium they are in as they pass into and out
+// of objects, so that every segment travelled inside absorbing glass is absorbed.
+pub fn trace_path(mut r: Ray, first_hit: Option<HitRecord>, scene: &Scene, settings: &PathSettings, sampler: &mut Sampler) -> (Vec3, u32) {
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+    let mut depth = PathDepth::default();
+    let mut hit = first_hit;
+    let mut medium = None;
+
+    for bounce in 0..settings.max_depth {
+        let current = match hit {
+            Some(current) => current,
+            None => {
+                record_termination(Termination::Escaped);
+                return (radiance + throughput * scene.background.colour(&r), bounce);
+            }
+        };
+        radiance = radiance + throughput * current.material.emitted(&r, &current);
+
+        sampler.start_dimension(bsdf_dimension(bounce));
+        let scatter = match current.material.scatter(&r, &current, sampler) {
+            Some(scatter) => scatter,
+            None => {
+                record_termination(Termination::Absorbed);
+                return (radiance, bounce);
+            }
+        };
+        depth.add(scatter.lobe);
+        if settings.is_too_deep(&depth) {
+            record_termination(Termination::MaxDepth);
+            return (radiance, bounce);
+        }
+        throughput = throughput * scatter.attenuation;
+
+        if bounce >= settings.min_bounces {
+            let survival = max_component(throughput).min(1.0);
+            sampler.start_dimension(russian_roulette_dimension(bounce));
+            if sampler.get_1d() >= survival {
+                record_termination(Termination::Absorbed);
+                return (radiance, bounce);
+            }
+            throughput = throughput / survival;
+        }
+
+        medium = medium_towards(medium, &r, &current, scatter.ray.direction);
+        r = scatter.ray;
+        stats::record_secondary_ray();
+        hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+        if let Some(next) = hit {
+            throughput = throughput * medium::transmittance(medium, r.wavelengths, next.t * r.direction.length());
+        }
+    }
+
+    // Rays that leave the scene on the last bounce still see the background, and those that hit a
+    // light still see its emission.
+    match hit {
+        Some(last) => {
+            record_termination(Termination::MaxDepth);
+            return (radiance + throughput * last.material.emitted(&r, &last), settings.max_depth);
+        }
+        None => {
+            record_termination(Termination::Escaped);
+            return (radiance + throughput * scene.background.colour(&r), settings.max_depth);
+        }
+    }
+}
+
+// The largest of the red, green and blue components.
+pub fn max_component(v: Vec3) -> f64 {
+    v.x.max(v.y).max(v.z)
+}
+
+// Unidirectional path tracing, which follows a single path from the camera, choosing each bounce by
+// sampling the material it hits.
+#[derive(Debug, Copy, Clone)]
+pub struct PathTracer {
+    pub settings: PathSettings,
+}
+
+impl _Integrator for PathTracer {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        trace_path(r, first_hit, scene, &self.settings, sampler).0
+    }
+}
@@ -0,0 +1,275 @@
+use std::f64::consts::PI;
+
+use crate::hitable::HitRecord;
+use crate::integrator::{_Integrator, PassState, Splat, NEAR_ZERO};
+use crate::integrator::path::{max_component, trace_path, PathDepth, PathSettings};
+use crate::medium::{self, medium_towards};
+use crate::photon_map::{trace_photons, Photon, PhotonMap};
+use crate::ray::Ray;
+use crate::sampler::{bsdf_dimension, russian_roulette_dimension, Sampler};
+use crate::scene::Scene;
+use crate::stats::{self, record_termination, Termination};
+use crate::vec3::Vec3;
+
+// Settings for the photons traced by the photon mapping integrators.
+#[derive(Debug, Copy, Clone)]
+pub struct PhotonSettings {
+    // Number of photons traced from the lights and the background for each pass. Only the ones
+    // that form caustics are kept.
+    pub photons_per_pass: usize,
+    // Radius around each point within which photons are gathered. Progressive photon mapping
+    // starts with this radius and shrinks it with each pass.
+    pub radius: f64,
+    // Number of photons gathered by photon mapping, which only searches as far as it needs to find
+    // them, so that the radius is smaller where photons are dense.
+    pub nearest: usize,
+    // Fraction of the photons gathered in previous passes that progressive photon mapping keeps
+    // for each new pass, between 0 and 1. Smaller values shrink the radius faster.
+    pub alpha: f64,
+}
+
+impl Default for PhotonSettings {
+    fn default() -> Self {
+        PhotonSettings { photons_per_pass: 200_000, radius: 0.1, nearest: 50, alpha: 0.7 }
+    }
+}
+
+// Photons whose surface normal differs more than this from the normal at the point being shaded
+// are on some other surface, such as the far side of a thin object, so they're ignored.
+const MINIMUM_NORMAL_ALIGNMENT: f64 = 0.5;
+
+// Path tracing with caustics estimated from photon maps. Caustics are light that reaches diffuse
+// surfaces through glass or off mirrors, which the path tracer can only find by chance. Instead,
+// photons are traced from the lights and the background each pass, and at each diffuse surface
+// that a camera path hits, the light from the photons around the hit is added. Paths that find
+// light through glass or mirrors after a diffuse bounce don't add it, as the photons have counted
+// it already.
+//
+// Gathering photons over an area blurs the caustics, which makes the result biased. Photon mapping
+// gathers a fixed number of the nearest photons each pass, so the blurring doesn't go away with
+// more passes. Progressive photon mapping, following Knaus and Zwicker, gathers the photons within
+// a radius that shrinks with each pass, so that the result converges to the correct image.
+#[derive(Debug, Copy, Clone)]
+pub struct PhotonMapping {
+    pub settings: PathSettings,
+    pub photons: PhotonSettings,
+    pub progressive: bool,
+}
+
+impl PhotonMapping {
+
+    // Trace the photons for the given pass.
+    pub fn trace_photons(&self, scene: &Scene, pass: u64) -> PhotonMap {
+        trace_photons(scene, self.photons.photons_per_pass, pass, self.settings.max_depth)
+    }
+
+    // The radius within which photons are gathered in the given pass, which starts from 1. Each
+    // pass of progressive photon mapping keeps a fraction alpha of the photons gathered so far, so
+    // the area the photons are gathered from shrinks by (n - 1 + alpha) / n in pass n.
+    pub fn radius(&self, pass: u64) -> f64 {
+        if !self.progressive { return self.photons.radius }
+        let shrinkage: f64 = (2..=pass).map(|n| (n as f64 - 1.0 + self.photons.alpha) / n as f64).product();
+        return self.photons.radius * shrinkage.sqrt();
+    }
+
+    // Light from the caustic photons around the hit that is reflected back along the ray.
+    fn caustics(&self, r: &Ray, hit: &HitRecord, photons: &PhotonMap, radius: f64) -> Vec3 {
+        let mut total = Vec3::new(0.0, 0.0, 0.0);
+        let mut gather = |photon: &Photon| {
+            if photon.normal.dot(&hit.normal) > MINIMUM_NORMAL_ALIGNMENT {
+                total = total + hit.material.bsdf(r, hit, -photon.direction) * r.wavelengths.spectrum(photon.power);
+            }
+        };
+        let radius_squared = if self.progressive {
+            photons.within(hit.p, radius, &mut gather);
+            radius * radius
+        }
+        else {
+            let (nearest, radius_squared) = photons.nearest(hit.p, self.photons.nearest, radius);
+            nearest.into_iter().for_each(&mut gather);
+            radius_squared
+        };
+        return total / (PI * radius_squared);
+    }
+}
+
+impl _Integrator for PhotonMapping {
+    fn radiance(&self, mut r: Ray, first_hit: Option<HitRecord>, scene: &Scene, pass: &PassState, sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        let photons = match pass.photons {
+            Some(ref photons) => photons,
+            None => return trace_path(r, first_hit, scene, &self.settings, sampler).0,
+        };
+        let radius = self.radius(pass.number);
+        let settings = &self.settings;
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+        let mut depth = PathDepth::default();
+        let mut hit = first_hit;
+        let mut medium = None;
+        // Whether the path has only been through glass and mirrors since its last diffuse bounce,
+        // and the last of them, in which case the photons have counted the light it finds.
+        let mut after_diffuse = false;
+        let mut caustic = None;
+
+        // Light from the background that the photons haven't already counted.
+        let background = |r: &Ray, caustic: Option<u32>| match caustic {
+            Some(object_id) if photons.background_targets.contains(&object_id) => Vec3::new(0.0, 0.0, 0.0),
+            _ => scene.background.colour(r),
+        };
+        // Light emitted by the surface hit that the photons haven't already counted.
+        let emitted = |r: &Ray, hit: &HitRecord, caustic: Option<u32>| match (caustic, scene.light(hit)) {
+            (Some(_), Some(_)) => Vec3::new(0.0, 0.0, 0.0),
+            _ => hit.material.emitted(r, hit),
+        };
+
+        for bounce in 0..settings.max_depth {
+            let current = match hit {
+                Some(current) => current,
+                None => {
+                    record_termination(Termination::Escaped);
+                    return radiance + throughput * background(&r, caustic);
+                }
+            };
+            radiance = radiance + throughput * emitted(&r, &current, caustic);
+            if current.material.is_delta() {
+                caustic = if after_diffuse { Some(current.object_id) } else { None };
+            }
+            else {
+                radiance = radiance + throughput * self.caustics(&r, &current, photons, radius);
+                after_diffuse = true;
+                caustic = None;
+            }
+
+            sampler.start_dimension(bsdf_dimension(bounce));
+            let scatter = match current.material.scatter(&r, &current, sampler) {
+                Some(scatter) => scatter,
+                None => {
+                    record_termination(Termination::Absorbed);
+                    return radiance;
+                }
+            };
+            depth.add(scatter.lobe);
+            if settings.is_too_deep(&depth) {
+                record_termination(Termination::MaxDepth);
+                return radiance;
+            }
+            throughput = throughput * scatter.attenuation;
+
+            if bounce >= settings.min_bounces {
+                let survival = max_component(throughput).min(1.0);
+                sampler.start_dimension(russian_roulette_dimension(bounce));
+                if sampler.get_1d() >= survival {
+                    record_termination(Termination::Absorbed);
+                    return radiance;
+                }
+                throughput = throughput / survival;
+            }
+
+            medium = medium_towards(medium, &r, &current, scatter.ray.direction);
+            r = scatter.ray;
+            stats::record_secondary_ray();
+            hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+            if let Some(next) = hit {
+                throughput = throughput * medium::transmittance(medium, r.wavelengths, next.t * r.direction.length());
+            }
+        }
+
+        match hit {
+            Some(last) => {
+                record_termination(Termination::MaxDepth);
+                return radiance + throughput * emitted(&r, &last, caustic);
+            }
+            None => {
+                record_termination(Termination::Escaped);
+                return radiance + throughput * background(&r, caustic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::film::Film;
+    use crate::filter::Filter;
+    use crate::hitable::Hitable;
+    use crate::integrator::Integrator;
+    use crate::material::Material;
+    use crate::progress::QuietProgress;
+    use crate::render::{render_pass, render_progressive, Background, ProgressiveSettings, RenderSettings};
+
+    const BLACK: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
+
+    // A diffuse floor lit by a spherical light through a glass sphere, which focuses the light into
+    // a caustic, with the given sphere in place of the glass one.
+    fn caustic_scene(lens: Material) -> Scene {
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::lambertian(0.6, 0.6, 0.6)),
+            Hitable::sphere(Vec3::new(0.0, 0.1, -1.0), 0.4, lens),
+            Hitable::sphere(Vec3::new(0.0, 1.5, -1.0), 0.2, Material::emissive(20.0, 20.0, 20.0)),
+        ]);
+        let camera = Camera::new(Vec3::new(0.0, 0.8, 0.2), Vec3::new(0.0, -0.5, -1.0), Vec3::new(0.0, 1.0, 0.0), 70.0, 1.0, 0.0, 1.0);
+        return Scene { background: Background::Uniform(Vec3::new(0.05, 0.05, 0.05)), ..Scene::new(world, camera) };
+    }
+
+    fn settings(integrator: Integrator) -> RenderSettings {
+        RenderSettings { sampler: Sampler::independent(0), filter: Filter::box_filter(0.5), adaptive: None, denoiser: None, integrator, spectral: false }
+    }
+
+    fn average(film: &Film) -> Vec3 {
+        let colours = film.colours();
+        return colours.iter().fold(BLACK, |total, c| total + *c) / colours.len() as f64;
+    }
+
+    #[test]
+    fn test_progressive_photon_mapping_shrinks_the_radius_each_pass() {
+        let photons = PhotonSettings::default();
+        let progressive = PhotonMapping { settings: PathSettings::default(), photons, progressive: true };
+        assert_eq!(progressive.radius(1), photons.radius);
+        assert!(progressive.radius(2) < progressive.radius(1));
+        assert!(progressive.radius(100) < progressive.radius(10));
+        // The radius shrinks slowly enough that the number of photons gathered keeps growing.
+        let gathered = |pass: u64| pass as f64 * progressive.radius(pass).powi(2);
+        assert!(gathered(100) > gathered(10));
+
+        let fixed = PhotonMapping { progressive: false, ..progressive };
+        assert_eq!(fixed.radius(100), photons.radius);
+    }
+
+    #[test]
+    fn test_progressive_photon_mapping_agrees_with_path_tracing() {
+        let scene = caustic_scene(Material::dielectric(1.5));
+        let mut path = Film::new(16, 16);
+        render_pass(&scene, &mut path, &settings(Integrator::path(PathSettings::default())), 1024, &QuietProgress);
+
+        let photons = PhotonSettings { photons_per_pass: 20_000, radius: 0.05, ..PhotonSettings::default() };
+        let progressive = ProgressiveSettings { time_budget: None, target_samples: 1024, noise_threshold: None, samples_per_pass: 16 };
+        let mut ppm = Film::new(16, 16);
+        let integrator = Integrator::progressive_photon_mapping(PathSettings::default(), photons);
+        render_progressive(&scene, &mut ppm, &settings(integrator), &progressive, &QuietProgress, |_, _| {});
+
+        let (path, ppm) = (average(&path), average(&ppm));
+        assert!((ppm - path).length() < 0.01 * path.length(), "{} with progressive photon mapping, {} with path tracing", ppm, path);
+    }
+
+    #[test]
+    fn test_without_caustics_photon_mapping_follows_the_same_paths_as_path_tracing() {
+        // Light only reaches the floor directly or off the rough metal, so no photons are stored
+        // and no light is left for them to count.
+        let scene = caustic_scene(Material::metal(0.8, 0.8, 0.8, 0.5));
+        let integrator = Integrator::photon_mapping(PathSettings::default(), PhotonSettings::default());
+        let pass = integrator.start_pass(&scene, 1);
+        assert!(pass.photons.as_ref().is_some_and(|photons| photons.is_empty()));
+
+        let mut sampler = Sampler::independent(0);
+        for i in 0..64 {
+            sampler.start_pixel_sample(i, 0, 0);
+            let r = scene.camera.get_ray(i as f64 / 64.0, 0.5, (0.5, 0.5));
+            let hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+            let path = Integrator::path(PathSettings::default()).radiance(r, hit, &scene, &pass, &mut sampler.clone(), &mut vec![]);
+            let photon_mapping = integrator.radiance(r, hit, &scene, &pass, &mut sampler, &mut vec![]);
+            assert_eq!(path, photon_mapping);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::hitable::{HitRecord, Hitable};
+use crate::integrator::{_Integrator, facing_normal, PassState, Splat, NEAR_ZERO};
+use crate::material::{reflect, Material};
+use crate::medium::{self, medium_towards, Medium};
+use crate::ray::Ray;
+use crate::render::Background;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::stats;
+use crate::vec3::Vec3;
+
+// Whitted-style recursive ray tracing. Mirrors and glass are followed deterministically, with glass
+// tracing both the reflected and refracted rays and weighting them by the Fresnel reflectance,
+// while lights show their emission and other surfaces are lit by the background in the direction of
+// their normal, unless something is in the way. Fuzzy metals are treated as perfect mirrors.
+// Without any random sampling the image is noise free, which makes it quick to check the layout of
+// a scene. Rays keep track of the medium they are in, so that coloured glass absorbs the light
+// travelling through it.
+#[derive(Debug, Copy, Clone)]
+pub struct Whitted {
+    pub max_depth: u32,
+}
+
+impl Whitted {
+    fn trace(&self, r: Ray, medium: Option<Medium>, world: &Hitable, background: &Background, depth: u32) -> Vec3 {
+        stats::record_secondary_ray();
+        let hit = world.hit(&r, NEAR_ZERO, f64::MAX);
+        let transmittance = match hit {
+            Some(ref hit) => medium::transmittance(medium, r.wavelengths, hit.t * r.direction.length()),
+            None => Vec3::new(1.0, 1.0, 1.0),
+        };
+        transmittance * self.shade(r, medium, hit, world, background, depth)
+    }
+
+    fn shade(&self, r: Ray, medium: Option<Medium>, hit: Option<HitRecord>, world: &Hitable, background: &Background, depth: u32) -> Vec3 {
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return background.colour(&r),
+        };
+        if depth >= self.max_depth { return Vec3::new(0.0, 0.0, 0.0) }
+
+        match hit.material {
+            Material::Metal(ref metal) => {
+                let reflected = Ray { origin: hit.p, direction: reflect(r.direction.unit_vector(), hit.normal), wavelengths: r.wavelengths };
+                r.wavelengths.spectrum(metal.albedo) * self.trace(reflected, medium, world, background, depth + 1)
+            }
+            Material::Conductor(ref conductor) => {
+                let reflected = Ray { origin: hit.p, direction: reflect(r.direction.unit_vector(), hit.normal), wavelengths: r.wavelengths };
+                let cosine = r.direction.unit_vector().dot(&hit.normal).abs();
+                r.wavelengths.spectrum(conductor.ior.fresnel(cosine)) * self.trace(reflected, medium, world, background, depth + 1)
+            }
+            Material::Emissive(_) => hit.material.emitted(&r, &hit),
+            Material::RoughDielectric(ref rough) => {
+                let smooth = Material::Dielectric(rough.smooth());
+                let smooth = HitRecord { material: &smooth, ..hit };
+                self.shade(r, medium, Some(smooth), world, background, depth)
+            }
+            Material::NormalMapped(ref mapped) => self.shade(r, medium, Some(mapped.shading(&r, &hit)), world, background, depth),
+            Material::Dielectric(ref dielectric) => {
+                let (reflected, refracted, reflectance) = dielectric.split(&r, &hit);
+                let reflected_colour = reflectance * self.trace(reflected, medium, world, background, depth + 1);
+                match refracted {
+                    Some(refracted) => {
+                        let inside = medium_towards(medium, &r, &hit, refracted.direction);
+                        reflected_colour + (1.0 - reflectance) * self.trace(refracted, inside, world, background, depth + 1)
+                    }
+                    None => reflected_colour,
+                }
+            }
+            _ => {
+                let normal = Ray { origin: hit.p, direction: facing_normal(&r, &hit), wavelengths: r.wavelengths };
+                stats::record_secondary_ray();
+                match world.hit(&normal, NEAR_ZERO, f64::MAX) {
+                    Some(_) => Vec3::new(0.0, 0.0, 0.0),
+                    None => r.wavelengths.spectrum(hit.material.albedo(&hit)) * background.colour(&normal),
+                }
+            }
+        }
+    }
+}
+
+impl _Integrator for Whitted {
+    fn radiance(&self, r: Ray, first_hit: Option<HitRecord>, scene: &Scene, _pass: &PassState, _sampler: &mut Sampler, _splats: &mut Vec<Splat>) -> Vec3 {
+        self.shade(r, None, first_hit, &scene.world, &scene.background, 0)
+    }
+}
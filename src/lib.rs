@@ -1,6 +1,29 @@
+#![allow(clippy::needless_return)]
+
 pub mod vec3;
+pub mod aov;
 pub mod ray;
 pub mod hitable;
 pub mod camera;
 pub mod material;
-pub mod scene;
\ No newline at end of file
+pub mod medium;
+pub mod texture;
+pub mod light;
+pub mod photon_map;
+pub mod integrator;
+pub mod scene;
+pub mod scene_file;
+pub mod film;
+pub mod filter;
+pub mod render;
+pub mod progress;
+pub mod denoise;
+pub mod compare;
+pub mod output;
+pub mod random;
+pub mod sampling;
+pub mod spectrum;
+pub mod sampler;
+pub mod stats;
+#[cfg(test)]
+mod stat_tests;
@@ -0,0 +1,105 @@
+use std::f64::consts::PI;
+
+use crate::sampling::sample_unit_sphere;
+use crate::vec3::Vec3;
+
+// Lights are the emissive objects in a scene, which integrators can sample directly rather than
+// waiting for paths to hit them by chance. Each light refers back to the object in the world that
+// it was created from, so that hits on that object can be matched up with the light.
+
+// A point chosen on the surface of a light.
+#[derive(Debug, Copy, Clone)]
+pub struct LightPoint {
+    pub p: Vec3,
+    pub normal: Vec3,
+}
+
+// Internal trait that defines the API for underlying Lights.
+// Note that the Light enum forms the public API for lights and wraps these private types.
+trait _Light {
+    fn sample_point(&self, u: (f64, f64)) -> LightPoint;
+    fn area(&self) -> f64;
+}
+
+// A sphere that emits light from its outside surface.
+#[derive(Debug, Copy, Clone)]
+pub struct SphereLight {
+    pub centre: Vec3,
+    pub radius: f64,
+}
+
+impl _Light for SphereLight {
+    fn sample_point(&self, u: (f64, f64)) -> LightPoint {
+        let normal = sample_unit_sphere(u);
+        return LightPoint { p: self.centre + self.radius * normal, normal };
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LightShape {
+    Sphere(SphereLight),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub shape: LightShape,
+    // Radiance emitted from every point of the surface, in every direction away from it.
+    pub emission: Vec3,
+    // The object_id of hits on the light's object.
+    pub object_id: u32,
+}
+
+impl Light {
+
+    pub fn sphere(centre: Vec3, radius: f64, emission: Vec3, object_id: u32) -> Light {
+        return Light { shape: LightShape::Sphere(SphereLight { centre, radius }), emission, object_id };
+    }
+
+    fn shape(&self) -> &dyn _Light {
+        match self.shape {
+            LightShape::Sphere(ref sphere) => sphere,
+        }
+    }
+
+    // Choose a point uniformly distributed over the surface of the light.
+    pub fn sample_point(&self, u: (f64, f64)) -> LightPoint {
+        self.shape().sample_point(u)
+    }
+
+    pub fn area(&self) -> f64 {
+        self.shape().area()
+    }
+
+    // Light leaving the point on the light in the given direction.
+    pub fn radiance(&self, point: &LightPoint, direction: Vec3) -> Vec3 {
+        return if direction.dot(&point.normal) > 0.0 { self.emission } else { Vec3::new(0.0, 0.0, 0.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_light_points_are_on_its_surface() {
+        let light = Light::sphere(Vec3::new(1.0, 2.0, 3.0), 0.5, Vec3::new(4.0, 4.0, 4.0), 0);
+        for i in 0..10 {
+            let point = light.sample_point((i as f64 / 10.0, 0.3));
+            assert!(((point.p - Vec3::new(1.0, 2.0, 3.0)).length() - 0.5).abs() < 1e-12);
+            assert!((point.normal - (point.p - Vec3::new(1.0, 2.0, 3.0)) / 0.5).length() < 1e-12);
+        }
+        assert!((light.area() - PI).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_lights_only_emit_away_from_their_surface() {
+        let light = Light::sphere(Vec3::new(0.0, 0.0, 0.0), 1.0, Vec3::new(4.0, 4.0, 4.0), 0);
+        let point = LightPoint { p: Vec3::new(0.0, 1.0, 0.0), normal: Vec3::new(0.0, 1.0, 0.0) };
+        assert_eq!(light.radiance(&point, Vec3::new(0.0, 1.0, 1.0)), Vec3::new(4.0, 4.0, 4.0));
+        assert_eq!(light.radiance(&point, Vec3::new(0.0, -1.0, 1.0)), Vec3::new(0.0, 0.0, 0.0));
+    }
+}
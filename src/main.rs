@@ -1,118 +1,360 @@
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{stdout, BufWriter};
-use std::sync::atomic::{AtomicI64, Ordering};
+#![allow(clippy::needless_return)]
 
-use rand::prelude::*;
-use rayon::prelude::*;
+use std::convert::TryFrom;
+use std::io::stdout;
+use std::path::Path;
+use std::time::Duration;
 
-use raytracer::camera::Camera;
-use raytracer::hitable::Hitable;
-use raytracer::ray::Ray;
-use raytracer::scene::final_scene;
-use raytracer::vec3::Vec3;
-
-const MAXIMUM_RECURSION_DEPTH: i8 = 50;
-const NEAR_ZERO: f64 = 0.001; // Treat hits that are less than this value as zero.
+use raytracer::denoise::Denoiser;
+use raytracer::film::Film;
+use raytracer::filter::Filter;
+use raytracer::progress::{CancelToken, CancellableProgress, JsonLinesProgress, ProgressReporter, QuietProgress, TerminalProgress};
+use raytracer::output::{write_aovs, write_image, write_sample_heatmap};
+use raytracer::integrator::path::PathSettings;
+use raytracer::integrator::photon_mapping::PhotonSettings;
+use raytracer::integrator::Integrator;
+use raytracer::render::{render_pass, render_progressive, AdaptiveSettings, ProgressiveSettings, RenderSettings};
+use raytracer::sampler::Sampler;
+use raytracer::scene::{scene, Scene, SCENE_NAMES};
+use raytracer::scene_file;
+use raytracer::stats::RenderStats;
 
 const WIDTH: i64 = 1200; // Image width - pixels
 const HEIGHT: i64 = 800; // Image height - pixels
 const SAMPLES: i64 = 10; // Samples per pixel
 
-fn colour(r: Ray, world: &Hitable, accumulator: Vec3, depth: i8) -> Vec3 {
-    match world.hit(&r, NEAR_ZERO, std::f64::MAX) {
-        Some(ref hit) if depth < MAXIMUM_RECURSION_DEPTH => {
-            let scattered = hit.material.scatter(&r, &hit);
-            colour(scattered, world, hit.material.albedo() * accumulator, depth + 1)
+// Samples per pixel taken by each pass of a progressive render.
+const SAMPLES_PER_PASS: u64 = 4;
+
+// Defaults for adaptive sampling.
+const ADAPTIVE_MIN_SAMPLES: u64 = 8;
+const ADAPTIVE_THRESHOLD: f64 = 0.02;
+
+struct Options {
+    file_name: String,
+    samples: u64,
+    // Samples per pixel at which a progressive render stops.
+    target_samples: u64,
+    progressive: bool,
+    time_budget: Option<Duration>,
+    noise_threshold: Option<f64>,
+    adaptive: bool,
+    min_samples: u64,
+    adaptive_threshold: f64,
+    heatmap_file_name: Option<String>,
+    sampler: String,
+    seed: u64,
+    filter: String,
+    filter_radius: Option<f64>,
+    aovs: bool,
+    denoise: bool,
+    spectral: bool,
+    scene: String,
+    progress: String,
+    integrator: String,
+    path: PathSettings,
+    photons: PhotonSettings,
+}
+
+fn usage() -> String {
+    return [
+        "Usage: raytracer [options]",
+        "",
+        "Options:",
+        "  --output <file>             PNG file to write (default image.png)",
+        "  --samples <n>               Samples per pixel, or the target for progressive renders (default",
+        "                              10, or unlimited with a time budget or noise threshold)",
+        "  --progressive               Render successive passes, updating the output after each",
+        "  --time-budget <seconds>     Stop a progressive render after this much time",
+        "  --noise-threshold <error>   Stop a progressive render once the noise falls below this",
+        "  --adaptive                  Send more samples to noisy pixels, up to --samples",
+        "  --min-samples <n>           Samples every pixel receives when sampling adaptively",
+        "  --adaptive-threshold <err>  Pixel error below which adaptive sampling stops",
+        "  --heatmap <file>            Also write an image of the samples taken per pixel",
+        "  --sampler <name>            independent, stratified, halton or sobol (default sobol)",
+        "  --seed <n>                  Seed for the sampler and for randomly generated scenes",
+        "  --filter <name>             Pixel filter: box, tent, gaussian, mitchell or lanczos (default box)",
+        "  --filter-radius <pixels>    Radius of the pixel filter",
+        "  --aovs                      Also write depth, position, normal and albedo as float PFM images,",
+        "                              and object and material ID mattes as PNGs",
+        "  --denoise                   Denoise the image, guided by the albedo, normal and depth",
+        "  --spectral                  Trace light at sampled wavelengths rather than as RGB",
+        "  --scene <name>              Scene to render: final, simple, caustic, materials or the name of",
+        "                              a scene file (default final)",
+        "  --progress <style>          bar, quiet or json for one JSON object per line (default bar)",
+        "  --integrator <name>         path, bdpt, photon, ppm, mlt, direct, whitted, normals, depth, ao or",
+        "                              bounces (default path)",
+        "  --max-depth <n>             Maximum number of bounces in a path (default 50)",
+        "  --diffuse-depth <n>         Maximum number of diffuse bounces (default 16)",
+        "  --specular-depth <n>        Maximum number of specular reflections (default 32)",
+        "  --transmission-depth <n>    Maximum number of refractions (default 32)",
+        "  --min-bounces <n>           Bounces before Russian roulette can end a path (default 3)",
+        "  --photons <n>               Photons traced each pass by photon and ppm (default 200000)",
+        "  --photon-radius <distance>  Radius photons are gathered from, shrunk each pass by ppm (default 0.1)",
+        "  --help                      Show this message",
+    ].join("\n");
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        file_name: String::from("image.png"),
+        samples: SAMPLES as u64,
+        target_samples: SAMPLES as u64,
+        progressive: false,
+        time_budget: None,
+        noise_threshold: None,
+        adaptive: false,
+        min_samples: ADAPTIVE_MIN_SAMPLES,
+        adaptive_threshold: ADAPTIVE_THRESHOLD,
+        heatmap_file_name: None,
+        sampler: String::from("sobol"),
+        seed: 0,
+        filter: String::from("box"),
+        filter_radius: None,
+        aovs: false,
+        denoise: false,
+        spectral: false,
+        scene: String::from("final"),
+        progress: String::from("bar"),
+        integrator: String::from("path"),
+        path: PathSettings::default(),
+        photons: PhotonSettings::default(),
+    };
+
+    let mut samples_given = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--output" => options.file_name = value()?,
+            "--samples" => {
+                options.samples = value()?.parse().map_err(|e| format!("Invalid sample count: {}", e))?;
+                samples_given = true;
+            }
+            "--progressive" => options.progressive = true,
+            "--time-budget" => {
+                let seconds: f64 = value()?.parse().map_err(|e| format!("Invalid time budget: {}", e))?;
+                options.time_budget = Some(Duration::from_secs_f64(seconds));
+            }
+            "--noise-threshold" => {
+                options.noise_threshold = Some(value()?.parse().map_err(|e| format!("Invalid noise threshold: {}", e))?);
+            }
+            "--adaptive" => options.adaptive = true,
+            "--min-samples" => {
+                options.min_samples = value()?.parse().map_err(|e| format!("Invalid sample count: {}", e))?;
+                options.adaptive = true;
+            }
+            "--adaptive-threshold" => {
+                options.adaptive_threshold = value()?.parse().map_err(|e| format!("Invalid threshold: {}", e))?;
+                options.adaptive = true;
+            }
+            "--heatmap" => options.heatmap_file_name = Some(value()?),
+            "--sampler" => options.sampler = value()?,
+            "--seed" => options.seed = value()?.parse().map_err(|e| format!("Invalid seed: {}", e))?,
+            "--filter" => options.filter = value()?,
+            "--filter-radius" => {
+                options.filter_radius = Some(value()?.parse().map_err(|e| format!("Invalid filter radius: {}", e))?);
+            }
+            "--aovs" => options.aovs = true,
+            "--denoise" => options.denoise = true,
+            "--spectral" => options.spectral = true,
+            "--scene" => options.scene = value()?,
+            "--progress" => options.progress = value()?,
+            "--integrator" => options.integrator = value()?,
+            "--max-depth" => options.path.max_depth = value()?.parse().map_err(|e| format!("Invalid depth: {}", e))?,
+            "--diffuse-depth" => options.path.max_diffuse_depth = value()?.parse().map_err(|e| format!("Invalid depth: {}", e))?,
+            "--specular-depth" => options.path.max_specular_depth = value()?.parse().map_err(|e| format!("Invalid depth: {}", e))?,
+            "--transmission-depth" => {
+                options.path.max_transmission_depth = value()?.parse().map_err(|e| format!("Invalid depth: {}", e))?;
+            }
+            "--min-bounces" => options.path.min_bounces = value()?.parse().map_err(|e| format!("Invalid bounce count: {}", e))?,
+            "--photons" => options.photons.photons_per_pass = value()?.parse().map_err(|e| format!("Invalid photon count: {}", e))?,
+            "--photon-radius" => options.photons.radius = value()?.parse().map_err(|e| format!("Invalid photon radius: {}", e))?,
+            "--help" => return Err(String::new()),
+            _ => return Err(format!("Unknown option {}", arg)),
         }
-        _ => accumulator
+    }
+
+    // Time and noise limits only make sense when rendering in passes. Adaptive sampling also works
+    // in passes, taking a few more samples each time from the pixels that are still noisy.
+    if options.time_budget.is_some() || options.noise_threshold.is_some() || options.adaptive {
+        options.progressive = true;
+    }
+
+    // A time or noise limit is what ends the render unless a sample count is given as well.
+    options.target_samples = if !samples_given && (options.time_budget.is_some() || options.noise_threshold.is_some()) { u64::MAX }
+    else { options.samples };
+
+    sampler(&options)?;
+    filter(&options)?;
+    if !SCENE_NAMES.contains(&options.scene.as_str()) && !Path::new(&options.scene).is_file() {
+        return Err(format!("Unknown scene {}", options.scene));
+    }
+    progress_reporter(&options)?;
+    integrator(&options)?;
+
+    return Ok(options);
+}
+
+fn integrator(options: &Options) -> Result<Integrator, String> {
+    Integrator::from_name(&options.integrator, options.path, options.photons).ok_or(format!("Unknown integrator {}", options.integrator))
+}
+
+fn sampler(options: &Options) -> Result<Sampler, String> {
+    match options.sampler.as_str() {
+        "independent" => Ok(Sampler::independent(options.seed)),
+        // The strata are planned for the sample count, so it has to be a positive u32.
+        "stratified" => match u32::try_from(options.samples) {
+            Ok(samples) if samples > 0 => Ok(Sampler::stratified(samples, options.seed)),
+            _ => Err(format!("Invalid sample count for the stratified sampler: {}", options.samples)),
+        },
+        "halton" => Ok(Sampler::halton(options.seed)),
+        "sobol" => Ok(Sampler::sobol(options.seed)),
+        _ => Err(format!("Unknown sampler {}", options.sampler)),
+    }
+}
+
+// Each filter has a default radius that suits it, which can be overridden.
+fn filter(options: &Options) -> Result<Filter, String> {
+    let radius = |default: f64| options.filter_radius.unwrap_or(default);
+    match options.filter.as_str() {
+        "box" => Ok(Filter::box_filter(radius(0.5))),
+        "tent" => Ok(Filter::tent(radius(1.0))),
+        "gaussian" => Ok(Filter::gaussian(radius(1.5))),
+        "mitchell" => Ok(Filter::mitchell(radius(2.0))),
+        "lanczos" => Ok(Filter::lanczos(radius(3.0))),
+        _ => Err(format!("Unknown filter {}", options.filter)),
+    }
+}
+
+fn progress_reporter(options: &Options) -> Result<Box<dyn ProgressReporter>, String> {
+    match options.progress.as_str() {
+        "bar" => Ok(Box::new(TerminalProgress::new())),
+        "quiet" => Ok(Box::new(QuietProgress)),
+        "json" => Ok(Box::new(JsonLinesProgress::new(stdout()))),
+        _ => Err(format!("Unknown progress style {}", options.progress)),
     }
 }
 
-// Compute a linear blend between white and blue depending on the value of the y coordinate.
-fn background_colour(ray: &Ray) -> Vec3 {
-    let unit_direction = ray.direction.unit_vector();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+// Only the progress bar style prints messages other than progress updates, so that the output of
+// the other styles stays easy to parse.
+fn is_verbose(options: &Options) -> bool {
+    options.progress == "bar"
 }
 
-fn render_pixel(x: i64, y: i64, world: &Hitable, camera: &Camera) -> Vec3 {
-    // Sample the pixel a number of times with a random offset and average the result to
-    // antialias the overall image.
-    let colour: Vec3 = (0..SAMPLES).map(|_| {
-        let u = (x as f64 + random::<f64>()) / WIDTH as f64;
-        let v = (y as f64 + random::<f64>()) / HEIGHT as f64;
-        let r = camera.get_ray(u, v);
-        colour(r, world, background_colour(&r), 0)
-    }).fold(
-        Vec3 { x: 0.0, y: 0.0, z: 0.0},
-        |sum, v| sum + v
-    ) / SAMPLES as f64;
-
-    // Apply simple square root gamma correction to generated values.
-    return Vec3::new(colour.x.sqrt(), colour.y.sqrt(), colour.z.sqrt());
+fn render(scene: &Scene, options: &Options, token: &CancelToken) -> std::io::Result<()> {
+    // The denoiser is guided by the AOVs, so they're needed even if they aren't written out.
+    let mut film = if options.aovs || options.denoise { Film::with_aovs(WIDTH as usize, HEIGHT as usize) }
+    else { Film::new(WIDTH as usize, HEIGHT as usize) };
+
+    let adaptive = AdaptiveSettings {
+        min_samples: options.min_samples.min(options.samples),
+        max_samples: options.samples,
+        threshold: options.adaptive_threshold,
+    };
+    let settings = RenderSettings {
+        sampler: sampler(options).expect("sampler is validated when parsing options"),
+        filter: filter(options).expect("filter is validated when parsing options"),
+        adaptive: if options.adaptive { Some(adaptive) } else { None },
+        denoiser: if options.denoise { Some(Denoiser::a_trous()) } else { None },
+        integrator: integrator(options).expect("integrator is validated when parsing options"),
+        spectral: options.spectral,
+    };
+    let reporter = progress_reporter(options).expect("progress style is validated when parsing options");
+    let progress = CancellableProgress::new(reporter.as_ref(), token.clone());
+
+    let stats = if options.progressive {
+        let progressive = ProgressiveSettings {
+            time_budget: options.time_budget,
+            target_samples: options.target_samples,
+            noise_threshold: options.noise_threshold,
+            samples_per_pass: SAMPLES_PER_PASS,
+        };
+
+        let mut result = Ok(());
+        let mut stats = RenderStats::default();
+        let reason = render_progressive(scene, &mut film, &settings, &progressive, &progress, |film, summary| {
+            if result.is_ok() {
+                result = write_image(&options.file_name, film.width, film.height, &settings.image(film));
+            }
+            stats = summary.stats.clone();
+        });
+        if is_verbose(options) {
+            println!("Stopped: {:?}", reason);
+        }
+        result?;
+        stats
+    }
+    else {
+        let stats = render_pass(scene, &mut film, &settings, options.samples, &progress);
+        write_image(&options.file_name, film.width, film.height, &settings.image(&film))?;
+        stats
+    };
+
+    if token.is_cancelled() && is_verbose(options) {
+        println!("\nCancelled, wrote the samples taken so far to {}", options.file_name);
+    }
+
+    if is_verbose(options) {
+        print!("{}", stats);
+    }
+
+    if options.aovs {
+        write_aovs(&film, &options.file_name)?;
+    }
+
+    if let Some(ref heatmap_file_name) = options.heatmap_file_name {
+        write_sample_heatmap(&film, heatmap_file_name)?;
+    }
+
+    return Ok(());
 }
 
 fn main() -> std::io::Result<()> {
-    let look_from = Vec3::new(13.0, 2.0, 3.0);
-    let look_at = Vec3::new(0.0, 0.0, 0.0);
-    let focus_distance = 10.0;
-
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
-        20.0,
-        WIDTH as f64 / HEIGHT as f64,
-        0.1,
-        focus_distance
-    );
-
-    let world = Hitable::hitable_list(final_scene());
-
-    let file_name = "image.png";
-
-    println!("Rendering scene to {}", file_name);
-
-    static PROGRESS: AtomicI64 = AtomicI64::new(0);
-
-    let image_data: Vec<Vec<Vec3>> = (0..HEIGHT).into_par_iter().map(|j| {
-        let line =  (0..WIDTH).map(|i| return render_pixel(i, j, &world, &camera)).collect();
-        PROGRESS.fetch_add(1, Ordering::Relaxed);
-        let percent_complete = (PROGRESS.load(Ordering::Relaxed) as f64 / HEIGHT as f64) * 100.0;
-        print!("\r{percent:>4}% complete ", percent = percent_complete.round());
-        stdout().flush().expect("failed to flush stdout");
-        return line;
-    }).collect();
-    
-    let png_file = File::create(file_name).expect("Unable to open PNG file for writing");
-    let w = BufWriter::new(png_file);
-    let mut encoder = png::Encoder::new(w, WIDTH as u32, HEIGHT as u32);
-
-    encoder.set_color(png::ColorType::RGBA);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_compression(png::Compression::Best);
-
-    let mut png_writer = encoder.write_header().unwrap();
-
-    // TODO - better expressed as an extension method?
-    fn component_value(v: f64) -> u8 { (v * 255.99) as u8 }
-
-    // Convert image data into RGBA
-    let rgba_data: Vec<Vec<u8>> = image_data.into_iter().rev().flatten().map(|pixel| {
-        return vec!(
-            component_value(pixel.r()),
-            component_value(pixel.g()),
-            component_value(pixel.b()),
-            255,
-        );
-    }).collect();
-
-    let png_data: Vec<u8> = rgba_data.into_iter().flatten().collect();
-
-    png_writer.write_image_data(&png_data).expect("Failed to write image data");
-
-    println!("\nFinished");
+    let options = match parse_options(std::env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(ref message) if message.is_empty() => {
+            println!("{}", usage());
+            return Ok(());
+        }
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, usage());
+            std::process::exit(1);
+        }
+    };
+
+    let aspect_ratio = WIDTH as f64 / HEIGHT as f64;
+    let scene = match scene(&options.scene, aspect_ratio, options.seed) {
+        Some(scene) => scene,
+        None => scene_file::load(&options.scene, aspect_ratio).unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }),
+    };
+
+    if is_verbose(&options) {
+        println!("Rendering scene to {}", options.file_name);
+    }
+
+    // The first Ctrl-C stops the render and writes out what has been rendered so far. A second one
+    // exits straight away, in case writing the partial image is taking too long.
+    let token = CancelToken::new();
+    let handler_token = token.clone();
+    let handler = ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            std::process::exit(130);
+        }
+        handler_token.cancel();
+    });
+    if let Err(e) = handler {
+        eprintln!("Unable to handle Ctrl-C, renders can't be cancelled: {}", e);
+    }
+
+    render(&scene, &options, &token)?;
+
+    if is_verbose(&options) {
+        println!("Finished");
+    }
 
     Ok(())
 }
@@ -0,0 +1,147 @@
+use crate::vec3::Vec3;
+use crate::material::{_Material, Lobe, Material, Scatter};
+use crate::material::microfacet::{Frame, Ggx};
+use crate::material::rough_dielectric::fresnel_dielectric;
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+
+// Smallest GGX alpha of the coat, which keeps it from becoming a perfect mirror. The coat then
+// never only scatters into discrete directions, so its BSDF can always be evaluated towards lights.
+pub const MINIMUM_ALPHA: f64 = 1e-3;
+
+// A clear dielectric coat over another material, such as varnished wood or lacquered paint. At
+// the top of the coat the light is split by the Fresnel reflectance for the angle it arrives at:
+// that fraction is reflected off the coat's GGX microfacets and the rest passes through to the
+// base. Light the base scatters back out passes through the coat again, losing the fraction that
+// the coat reflects back in. The same reflectance is used for the coat's reflection as for the
+// split, so the coat and base together never reflect more light than arrives.
+//
+// Light arriving from inside the base, such as within coated glass, doesn't meet the coat and is
+// scattered by the base alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coated {
+    pub base: Box<Material>,
+    pub refractive_index: f64,
+    pub distribution: Ggx,
+}
+
+impl Coated {
+
+    // Fraction of light arriving from outside along ray_in that the coat reflects, along with the
+    // frame of the surface, or None for light arriving from inside.
+    fn split(&self, ray_in: &Ray, hit: &HitRecord) -> Option<(Frame, f64)> {
+        let cosine = -ray_in.direction.unit_vector().dot(&hit.normal);
+        if cosine <= 0.0 { return None }
+        return Some((Frame::from_normal(hit.normal), fresnel_dielectric(cosine, self.refractive_index)));
+    }
+
+    // Fraction of the light scattered by the base in the given direction that leaves through the
+    // coat. Light scattered into the base doesn't pass through the coat again.
+    fn transmitted(&self, hit: &HitRecord, direction: Vec3) -> f64 {
+        let cosine = direction.unit_vector().dot(&hit.normal);
+        return if cosine > 0.0 { 1.0 - fresnel_dielectric(cosine, self.refractive_index) } else { 1.0 };
+    }
+
+    // The BSDF and the density of sampling direction, given the frame and reflectance of the coat.
+    fn evaluate(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3, frame: &Frame, reflectance: f64) -> (Vec3, f64) {
+        let base_bsdf = (1.0 - reflectance) * self.transmitted(hit, direction) * self.base.rgb_bsdf(ray_in, hit, direction);
+        let base_pdf = (1.0 - reflectance) * self.base.pdf(ray_in, hit, direction);
+
+        let wo = frame.to_local(-ray_in.direction.unit_vector());
+        let wi = frame.to_local(direction.unit_vector());
+        if wi.z <= 0.0 { return (base_bsdf, base_pdf) }
+        let h = (wo + wi).unit_vector();
+        let coat_bsdf = reflectance * self.distribution.d(h) * self.distribution.g2(wo, wi) / (4.0 * wo.z * wi.z);
+        let coat_pdf = reflectance * self.distribution.visible_normal_pdf(wo, h) / (4.0 * wo.dot(&h));
+        return (base_bsdf + Vec3::new(coat_bsdf, coat_bsdf, coat_bsdf), base_pdf + coat_pdf);
+    }
+}
+
+impl _Material for Coated {
+    // Reflect off the coat with the probability that it reflects, and otherwise scatter off the
+    // base. Unless the base only scatters into discrete directions, the direction is weighted by the
+    // BSDF and density of the coat and base together.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let (frame, reflectance) = match self.split(ray_in, hit) {
+            Some(split) => split,
+            None => return self.base.scatter(ray_in, hit, sampler),
+        };
+
+        let (direction, lobe) = if sampler.get_1d() < reflectance {
+            let wo = frame.to_local(-ray_in.direction.unit_vector());
+            let m = self.distribution.sample_visible_normal(wo, sampler.get_2d());
+            (frame.to_world(2.0 * wo.dot(&m) * m - wo), Lobe::Specular)
+        }
+        else {
+            let scatter = self.base.scatter(ray_in, hit, sampler)?;
+            if self.base.is_delta() {
+                return Some(Scatter { attenuation: self.transmitted(hit, scatter.ray.direction) * scatter.attenuation, ..scatter });
+            }
+            (scatter.ray.direction, scatter.lobe)
+        };
+
+        let (bsdf, pdf) = self.evaluate(ray_in, hit, direction, &frame, reflectance);
+        if pdf <= 0.0 { return None }
+        let cosine = direction.unit_vector().dot(&hit.normal).abs();
+        let ray = Ray { origin: hit.p, direction, wavelengths: ray_in.wavelengths };
+        return Some(Scatter { ray, lobe, attenuation: ray_in.wavelengths.spectrum(bsdf * cosine / pdf) });
+    }
+    fn albedo(&self, hit: &HitRecord) -> Vec3 { self.base.albedo(hit) }
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        match self.split(ray_in, hit) {
+            Some((frame, reflectance)) => self.evaluate(ray_in, hit, direction, &frame, reflectance).1,
+            None => self.base.pdf(ray_in, hit, direction),
+        }
+    }
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        match self.split(ray_in, hit) {
+            Some((frame, reflectance)) => self.evaluate(ray_in, hit, direction, &frame, reflectance).0,
+            None => self.base.rgb_bsdf(ray_in, hit, direction),
+        }
+    }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::principled::Principled;
+    use crate::texture::Texture;
+    use crate::stat_tests::{average_attenuation, check_scattering_matches_pdf, hit, integrate_bsdf, SphereHistogram};
+
+    #[test]
+    fn test_coated_scattering_matches_its_pdf() {
+        for material in [Material::coated(Material::lambertian(0.5, 0.5, 0.5), 1.5, 0.1), Material::coated(Material::metal(0.8, 0.8, 0.8, 0.5), 1.5, 0.3)].iter() {
+            check_scattering_matches_pdf(material, SphereHistogram { theta_bins: 40, phi_bins: 80 });
+        }
+    }
+
+    #[test]
+    fn test_coated_scattering_leaves_what_its_bsdf_does() {
+        let principled = Principled { transmission: Texture::scalar(0.5), ..Principled::default() };
+        for material in [Material::coated(Material::lambertian(1.0, 1.0, 1.0), 1.5, 0.2), Material::coated(Material::principled(principled), 1.5, 0.1)].iter() {
+            let (ray, hit) = hit(material);
+            let scattered = average_attenuation(material, &ray, &hit);
+            let bsdf = integrate_bsdf(material, &ray, &hit);
+            assert!((scattered - bsdf).length() < 0.02, "{:?} scatters {} but its bsdf leaves {}", material, scattered, bsdf);
+            assert!(scattered.x <= 1.0, "{:?} scatters {}", material, scattered);
+        }
+    }
+
+    #[test]
+    fn test_coat_splits_light_by_its_fresnel_reflectance() {
+        let reflectance = fresnel_dielectric(1.0 / 2.0_f64.sqrt(), 1.5);
+        // Over a black base only the coat reflects.
+        let black = Material::coated(Material::lambertian(0.0, 0.0, 0.0), 1.5, 0.0);
+        // Over a mirror, light that passes through the coat is reflected back out through it.
+        let mirror = Material::coated(Material::metal(0.5, 0.5, 0.5, 0.0), 1.5, 0.0);
+        let expected = [reflectance, reflectance + (1.0 - reflectance) * 0.5 * (1.0 - reflectance)];
+        for (material, expected) in [black, mirror].iter().zip(expected.iter()) {
+            let (ray, hit) = hit(material);
+            let scattered = average_attenuation(material, &ray, &hit).x;
+            assert!((scattered - expected).abs() < 0.01, "{:?} scatters {} rather than {}", material, scattered, expected);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::vec3::Vec3;
+use crate::material::{_Material, Lobe, Scatter};
+use crate::material::microfacet::{Frame, Ggx};
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+
+// The complex refractive index of a metal, eta + i k, for red, green and blue light. The
+// extinction coefficient k is what makes metals opaque and gives them their colour.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ComplexIor {
+    pub eta: Vec3,
+    pub k: Vec3,
+}
+
+impl ComplexIor {
+
+    pub fn gold() -> ComplexIor {
+        ComplexIor { eta: Vec3::new(0.18299, 0.42108, 1.37340), k: Vec3::new(3.42420, 2.34590, 1.77040) }
+    }
+
+    pub fn copper() -> ComplexIor {
+        ComplexIor { eta: Vec3::new(0.27105, 0.67693, 1.31640), k: Vec3::new(3.60920, 2.62480, 2.29210) }
+    }
+
+    pub fn silver() -> ComplexIor {
+        ComplexIor { eta: Vec3::new(0.15943, 0.14512, 0.13547), k: Vec3::new(3.92910, 3.19000, 2.38080) }
+    }
+
+    pub fn aluminium() -> ComplexIor {
+        ComplexIor { eta: Vec3::new(1.65740, 0.88036, 0.52123), k: Vec3::new(9.22380, 6.26950, 4.83700) }
+    }
+
+    // Fraction of unpolarised light reflected at an angle with the given cosine to the normal, for
+    // each colour.
+    pub fn fresnel(&self, cosine: f64) -> Vec3 {
+        let channel = |eta: f64, k: f64| fresnel_conductor(cosine, eta, k);
+        return Vec3::new(channel(self.eta.x, self.k.x), channel(self.eta.y, self.k.y), channel(self.eta.z, self.k.z));
+    }
+}
+
+// The exact Fresnel equations for a conductor, averaged over both polarisations, following pbrt.
+fn fresnel_conductor(cosine: f64, eta: f64, k: f64) -> f64 {
+    let cos_squared = (cosine * cosine).min(1.0);
+    let sin_squared = 1.0 - cos_squared;
+    let t0 = eta * eta - k * k - sin_squared;
+    let a_squared_plus_b_squared = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a_squared_plus_b_squared + cos_squared;
+    let a = (0.5 * (a_squared_plus_b_squared + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cosine * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos_squared * a_squared_plus_b_squared + sin_squared * sin_squared;
+    let t4 = t2 * sin_squared;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    return 0.5 * (rp + rs);
+}
+
+// A physically based metal, reflecting light off GGX distributed microfacets with the Fresnel
+// reflectance of its complex refractive index. Unlike Metal's fuzz, no light is scattered below
+// the surface and none is created, although the light that would have bounced between
+// microfacets more than once is lost.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Conductor {
+    pub ior: ComplexIor,
+    pub distribution: Ggx,
+}
+
+impl Conductor {
+
+    // The local frame at the hit, with the normal on the side that ray_in arrives from.
+    fn frame(&self, ray_in: &Ray, hit: &HitRecord) -> Frame {
+        return Frame::from_tangent(if ray_in.direction.dot(&hit.normal) > 0.0 { -hit.normal } else { hit.normal }, hit.dpdu);
+    }
+
+    // The directions back along ray_in and towards direction, in the local frame.
+    fn local(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> (Vec3, Vec3) {
+        let frame = self.frame(ray_in, hit);
+        return (frame.to_local(-ray_in.direction.unit_vector()), frame.to_local(direction.unit_vector()));
+    }
+}
+
+impl _Material for Conductor {
+    // Reflecting off a normal sampled from those visible from ray_in leaves the light weighted by
+    // the Fresnel reflectance and the fraction of microfacets that don't shadow the reflection.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let frame = self.frame(ray_in, hit);
+        let wo = frame.to_local(-ray_in.direction.unit_vector());
+        if wo.z <= 0.0 { return None }
+
+        let u = sampler.get_2d();
+        let (wi, weight) = if self.distribution.is_smooth() {
+            (Vec3::new(-wo.x, -wo.y, wo.z), self.ior.fresnel(wo.z))
+        }
+        else {
+            let m = self.distribution.sample_visible_normal(wo, u);
+            let wi = 2.0 * wo.dot(&m) * m - wo;
+            if wi.z <= 0.0 { return None }
+            (wi, self.ior.fresnel(wo.dot(&m)) * self.distribution.g2(wo, wi) / self.distribution.g1(wo))
+        };
+        let ray = Ray { origin: hit.p, direction: frame.to_world(wi), wavelengths: ray_in.wavelengths };
+        return Some(Scatter { ray, lobe: Lobe::Specular, attenuation: ray_in.wavelengths.spectrum(weight) });
+    }
+    // Reflectance at normal incidence.
+    fn albedo(&self, _hit: &HitRecord) -> Vec3 { self.ior.fresnel(1.0) }
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        if self.distribution.is_smooth() { return 0.0 }
+        let (wo, wi) = self.local(ray_in, hit, direction);
+        if wo.z <= 0.0 || wi.z <= 0.0 { return 0.0 }
+        let m = (wo + wi).unit_vector();
+        return self.distribution.visible_normal_pdf(wo, m) / (4.0 * wo.dot(&m));
+    }
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        if self.distribution.is_smooth() { return Vec3::new(0.0, 0.0, 0.0) }
+        let (wo, wi) = self.local(ray_in, hit, direction);
+        if wo.z <= 0.0 || wi.z <= 0.0 { return Vec3::new(0.0, 0.0, 0.0) }
+        let m = (wo + wi).unit_vector();
+        let d_g = self.distribution.d(m) * self.distribution.g2(wo, wi);
+        return self.ior.fresnel(wo.dot(&m)) * d_g / (4.0 * wo.z * wi.z);
+    }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { self.distribution.is_smooth() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::sampler::bsdf_dimension;
+    use crate::stat_tests::{average_attenuation, check_scattering_matches_pdf, hit, integrate_bsdf, SphereHistogram};
+
+    #[test]
+    fn test_conductor_scattering_matches_its_pdf() {
+        for material in [Material::conductor(ComplexIor::gold(), 0.3), Material::anisotropic_conductor(ComplexIor::copper(), 0.1, 0.5)].iter() {
+            check_scattering_matches_pdf(material, SphereHistogram { theta_bins: 40, phi_bins: 80 });
+        }
+    }
+
+    #[test]
+    fn test_conductor_scattering_reflects_what_its_bsdf_does() {
+        // With no extinction and an index of zero the surface reflects all the light, so the light
+        // that's lost is only the light masked and shadowed by the microfacets.
+        let mirror = ComplexIor { eta: Vec3::new(0.0, 0.0, 0.0), k: Vec3::new(1.0, 1.0, 1.0) };
+        for material in [Material::conductor(mirror, 0.2), Material::conductor(ComplexIor::gold(), 0.6), Material::anisotropic_conductor(mirror, 0.05, 0.8)].iter() {
+            let (ray, hit) = hit(material);
+            let scattered = average_attenuation(material, &ray, &hit);
+            let reflected = integrate_bsdf(material, &ray, &hit);
+            assert!((scattered - reflected).length() < 0.01, "{:?} scatters {} but its bsdf reflects {}", material, scattered, reflected);
+            assert!(scattered.x <= 1.0 && scattered.y <= 1.0 && scattered.z <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_conductor_fresnel_matches_normal_incidence_and_grazing_limits() {
+        for ior in [ComplexIor::gold(), ComplexIor::copper(), ComplexIor::silver(), ComplexIor::aluminium()].iter() {
+            let normal = ior.fresnel(1.0);
+            let expected = |eta: f64, k: f64| ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+            let expected = Vec3::new(expected(ior.eta.x, ior.k.x), expected(ior.eta.y, ior.k.y), expected(ior.eta.z, ior.k.z));
+            assert!((normal - expected).length() < 1e-9, "{} isn't {}", normal, expected);
+            assert!((ior.fresnel(1e-6) - Vec3::new(1.0, 1.0, 1.0)).length() < 1e-3);
+        }
+        // Gold reflects more red than blue.
+        let gold = ComplexIor::gold().fresnel(1.0);
+        assert!(gold.x > gold.y && gold.y > gold.z);
+    }
+
+    #[test]
+    fn test_smooth_conductor_is_a_mirror() {
+        let material = Material::conductor(ComplexIor::silver(), 0.0);
+        assert!(material.is_delta());
+        let (ray, hit) = hit(&material);
+        let mut sampler = Sampler::independent(1);
+        sampler.start_pixel_sample(0, 0, 0);
+        sampler.start_dimension(bsdf_dimension(0));
+        let scatter = material.scatter(&ray, &hit, &mut sampler).unwrap();
+        assert!((scatter.ray.direction - Vec3::new(1.0, 0.0, 1.0).unit_vector()).length() < 1e-9);
+        assert_eq!(scatter.attenuation, ComplexIor::silver().fresnel(1.0 / 2.0_f64.sqrt()));
+    }
+}
@@ -1,12 +1,65 @@
 use crate::vec3::Vec3;
-use crate::material::{_Material, reflect};
+use crate::material::{_Material, reflect, Lobe, Scatter};
 use crate::ray::Ray;
 use crate::hitable::HitRecord;
-use rand::random;
+use crate::medium::Medium;
+use crate::sampler::Sampler;
+use crate::spectrum::Wavelengths;
 
-#[derive(Copy, Clone)]
+// Wavelength of the sodium D line, in nanometres, at which refractive indices are usually quoted.
+// Dispersive dielectrics use their index at this wavelength when rendering in RGB.
+pub const D_LINE: f64 = 587.6;
+
+// How the refractive index of a dielectric varies with wavelength, which splits white light into
+// its colours as it refracts. Wavelengths are in micrometres, as the coefficients are usually
+// given.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    // Cauchy's equation, n = a + b / λ², which is a good fit for glass over visible wavelengths.
+    Cauchy { a: f64, b: f64 },
+    // The Sellmeier equation, n² = 1 + Σ b λ² / (λ² - c), with c in square micrometres.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+
+    // Schott N-BK7, the most common optical glass.
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier { b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653] }
+    }
+
+    // Fused silica, from Malitson's measurements.
+    pub fn fused_silica() -> Dispersion {
+        Dispersion::Sellmeier { b: [0.6961663, 0.4079426, 0.8974794], c: [0.0684043 * 0.0684043, 0.1162414 * 0.1162414, 9.896161 * 9.896161] }
+    }
+
+    // Diamond, which is far more dispersive than glass and gives gems their fire.
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.1750 * 0.1750, 0.1060 * 0.1060, 0.0] }
+    }
+
+    // The refractive index at the wavelength lambda, in nanometres.
+    pub fn refractive_index(&self, lambda: f64) -> f64 {
+        let lambda = lambda / 1000.0;
+        let lambda_squared = lambda * lambda;
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / lambda_squared,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c.iter()).map(|(b, c)| b * lambda_squared / (lambda_squared - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Dielectric {
-    pub refractive_index: f64
+    pub refractive_index: f64,
+    // When set, the refractive index depends on the wavelength in spectral renders.
+    pub dispersion: Option<Dispersion>,
+    // Fraction of the light absorbed per unit distance travelled inside, for each colour. Zero
+    // for clear glass.
+    pub absorption: Vec3,
 }
 
 impl Dielectric {
@@ -24,11 +77,12 @@ impl Dielectric {
         let r0_squared = r0 * r0;
         return r0_squared + (1.0 - r0_squared) * (1.0 - cosine).powf(5.0);
     }
-}
 
-impl _Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Ray {
-        let reflected = reflect(ray_in.direction.unit_vector(), hit.normal);
+    // Split light arriving along ray_in into reflected and refracted rays, returning them with the
+    // fraction of the light that is reflected. There is no refracted ray under total internal
+    // reflection.
+    pub fn split(&self, ray_in: &Ray, hit: &HitRecord) -> (Ray, Option<Ray>, f64) {
+        let reflected = Ray { origin: hit.p, direction: reflect(ray_in.direction.unit_vector(), hit.normal), wavelengths: ray_in.wavelengths };
         let (outward_normal, ni_over_nt, cosine) = if ray_in.direction.dot(&hit.normal) > 0.0 {
             (-hit.normal, self.refractive_index, self.refractive_index * ray_in.direction.dot(&hit.normal) / ray_in.direction.length())
         }
@@ -37,13 +91,162 @@ impl _Material for Dielectric {
         };
 
         let refracted = self.refract(ray_in.direction, outward_normal, ni_over_nt);
+        return if refracted == ray_in.direction { (reflected, None, 1.0) }
+        else { (reflected, Some(Ray { origin: hit.p, direction: refracted, wavelengths: ray_in.wavelengths }), self.schlick(cosine)) };
+    }
 
-        let reflection_probability = if refracted == ray_in.direction { 1.0 }
-        else  { self.schlick(cosine) };
+    // The medium inside, which absorbs the light travelling through it, or None for clear glass.
+    // Integrators apply it to every segment of a path inside the glass; see Medium.
+    pub fn medium(&self) -> Option<Medium> {
+        return if self.absorption == Vec3::new(0.0, 0.0, 0.0) { None } else { Some(Medium { absorption: self.absorption }) };
+    }
 
-        return if random::<f64>() < reflection_probability { Ray { origin: hit.p, direction: reflected } }
-        else { Ray { origin: hit.p, direction: refracted } };
+    // A dispersive dielectric bends each wavelength differently, so a ray can only follow one of
+    // them. One of the wavelengths is chosen uniformly and the scattered ray carries only that
+    // one, with its light tripled to make up for the others being dropped. Rays that already
+    // carry a single wavelength keep it.
+    fn choose_wavelength(&self, ray_in: &Ray, sampler: &mut Sampler) -> (Dielectric, Wavelengths, Vec3) {
+        let ones = Vec3::new(1.0, 1.0, 1.0);
+        let (dispersion, lambda) = match (self.dispersion, ray_in.wavelengths) {
+            (Some(dispersion), Wavelengths::Spectral(lambda)) => (dispersion, lambda),
+            _ => return (*self, ray_in.wavelengths, ones),
+        };
+        let (index, attenuation) = if lambda.iter().all(|l| *l == lambda[0]) { (0, ones) }
+        else {
+            let index = ((sampler.get_1d() * lambda.len() as f64) as usize).min(lambda.len() - 1);
+            let mut attenuation = [0.0; 3];
+            attenuation[index] = lambda.len() as f64;
+            (index, Vec3::new(attenuation[0], attenuation[1], attenuation[2]))
+        };
+        let dielectric = Dielectric { refractive_index: dispersion.refractive_index(lambda[index]), dispersion: None, ..*self };
+        return (dielectric, Wavelengths::Spectral([lambda[index]; 3]), attenuation);
     }
+}
 
-    fn albedo(&self) -> Vec3 { Vec3 { x: 1.0, y: 1.0, z: 1.0} }
+impl _Material for Dielectric {
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let u = sampler.get_1d();
+        let (dielectric, wavelengths, attenuation) = self.choose_wavelength(ray_in, sampler);
+        let (reflected, refracted, reflectance) = dielectric.split(ray_in, hit);
+        let ray = match refracted {
+            Some(refracted) if u >= reflectance => Scatter { ray: refracted, lobe: Lobe::Transmission, attenuation },
+            _ => Scatter { ray: reflected, lobe: Lobe::Specular, attenuation },
+        };
+        return Some(Scatter { ray: Ray { wavelengths, ..ray.ray }, ..ray });
+    }
+
+    fn albedo(&self, _hit: &HitRecord) -> Vec3 { Vec3 { x: 1.0, y: 1.0, z: 1.0} }
+    fn pdf(&self, _ray_in: &Ray, _hit: &HitRecord, _direction: Vec3) -> f64 { 0.0 }
+    fn bsdf(&self, _ray_in: &Ray, _hit: &HitRecord, _direction: Vec3) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { true }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::sampler::bsdf_dimension;
+    use crate::spectrum::{LAMBDA_MAX, LAMBDA_MIN};
+    use crate::stat_tests::{chi_square_test, hit, scattered_directions, SAMPLES, SIGNIFICANCE};
+
+    #[test]
+    fn test_dielectric_reflects_according_to_the_schlick_approximation() {
+        let dielectric = Dielectric { refractive_index: 1.5, dispersion: None, absorption: Vec3::new(0.0, 0.0, 0.0) };
+        let directions = scattered_directions(&Material::Dielectric(dielectric));
+        let reflected = directions.iter().filter(|d| d.z > 0.0).count() as f64;
+
+        let probability = dielectric.schlick(1.0 / 2.0_f64.sqrt());
+        let observed = [reflected, SAMPLES as f64 - reflected];
+        let expected = [probability * SAMPLES as f64, (1.0 - probability) * SAMPLES as f64];
+        assert!(chi_square_test(&observed, &expected, SIGNIFICANCE).is_ok(), "reflected {} of {} rays", reflected, SAMPLES);
+    }
+
+    #[test]
+    fn test_dielectric_reports_reflection_as_specular_and_refraction_as_transmission() {
+        let material = Material::dielectric(1.5);
+        let (ray, hit) = hit(&material);
+        let mut sampler = Sampler::independent(1);
+        for i in 0..100 {
+            sampler.start_pixel_sample(0, 0, i);
+            sampler.start_dimension(bsdf_dimension(0));
+            let scatter = material.scatter(&ray, &hit, &mut sampler).unwrap();
+            let expected = if scatter.ray.direction.z > 0.0 { Lobe::Specular } else { Lobe::Transmission };
+            assert_eq!(scatter.lobe, expected);
+        }
+    }
+
+    // Scatter rays through a dielectric with the given wavelengths, returning the directions of
+    // those that refract, with the wavelengths they carry on with.
+    fn refracted_directions(material: &Material, wavelengths: Wavelengths) -> Vec<(Vec3, Wavelengths, Vec3)> {
+        let (ray, hit) = hit(material);
+        let ray = Ray { wavelengths, ..ray };
+        let mut sampler = Sampler::independent(1);
+        (0..1000).filter_map(|i| {
+            sampler.start_pixel_sample(0, 0, i);
+            sampler.start_dimension(bsdf_dimension(0));
+            let scatter = material.scatter(&ray, &hit, &mut sampler).unwrap();
+            if scatter.lobe == Lobe::Transmission { Some((scatter.ray.direction.unit_vector(), scatter.ray.wavelengths, scatter.attenuation)) } else { None }
+        }).collect()
+    }
+
+    #[test]
+    fn test_dispersion_presets_match_their_published_indices() {
+        assert!((Dispersion::bk7().refractive_index(D_LINE) - 1.5168).abs() < 1e-3);
+        assert!((Dispersion::fused_silica().refractive_index(D_LINE) - 1.4585).abs() < 1e-3);
+        assert!((Dispersion::diamond().refractive_index(D_LINE) - 2.417).abs() < 2e-3);
+        assert!((Dispersion::Cauchy { a: 1.5046, b: 0.0042 }.refractive_index(D_LINE) - 1.5168).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_refractive_index_decreases_with_wavelength() {
+        for dispersion in [Dispersion::bk7(), Dispersion::fused_silica(), Dispersion::diamond(), Dispersion::Cauchy { a: 1.5, b: 0.004 }].iter() {
+            let indices: Vec<f64> = (0..10).map(|i| dispersion.refractive_index(LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * i as f64 / 9.0)).collect();
+            assert!(indices.windows(2).all(|pair| pair[0] > pair[1]), "{:?} gives {:?}", dispersion, indices);
+        }
+    }
+
+    #[test]
+    fn test_dispersion_refracts_each_wavelength_in_its_own_direction() {
+        let material = Material::dispersive_dielectric(Dispersion::diamond());
+        let lambda = [450.0, 550.0, 650.0];
+        let refracted = refracted_directions(&material, Wavelengths::Spectral(lambda));
+        assert!(!refracted.is_empty());
+        for (direction, wavelengths, attenuation) in refracted.iter() {
+            // The ray carries on at one of the wavelengths, in the direction that wavelength bends.
+            let channel = lambda.iter().position(|l| *wavelengths == Wavelengths::Spectral([*l; 3])).unwrap();
+            let mut expected = [0.0; 3];
+            expected[channel] = 3.0;
+            assert_eq!(*attenuation, Vec3::new(expected[0], expected[1], expected[2]));
+            let single = refracted_directions(&material, *wavelengths);
+            assert!((single[0].0 - *direction).length() < 1e-12);
+            assert_eq!(single[0].2, Vec3::new(1.0, 1.0, 1.0));
+        }
+        // Blue light is bent more sharply towards the normal than red.
+        let direction = |l: f64| refracted_directions(&material, Wavelengths::Spectral([l; 3]))[0].0;
+        assert!(direction(450.0).x < direction(650.0).x);
+    }
+
+    #[test]
+    fn test_dispersive_dielectric_refracts_rgb_at_its_d_line_index() {
+        let dispersive = refracted_directions(&Material::dispersive_dielectric(Dispersion::bk7()), Wavelengths::Rgb);
+        let plain = refracted_directions(&Material::dielectric(Dispersion::bk7().refractive_index(D_LINE)), Wavelengths::Rgb);
+        assert_eq!(dispersive, plain);
+    }
+
+    #[test]
+    fn test_absorbing_dielectric_leaves_the_absorption_to_its_medium() {
+        let absorption = Vec3::new(0.5, 0.1, 0.8);
+        let material = Material::absorbing_dielectric(1.5, absorption);
+        let (ray, hit) = hit(&material);
+        let mut sampler = Sampler::independent(1);
+        sampler.start_pixel_sample(0, 0, 0);
+        sampler.start_dimension(bsdf_dimension(0));
+
+        // The surface itself absorbs nothing; the integrators absorb the light along the path inside.
+        assert_eq!(material.scatter(&ray, &hit, &mut sampler).unwrap().attenuation, Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(material.interior_medium(), Some(Medium { absorption }));
+        assert_eq!(Material::smooth_shaded(material).interior_medium(), Some(Medium { absorption }));
+        assert_eq!(Material::dielectric(1.5).interior_medium(), None);
+    }
 }
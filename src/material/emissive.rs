@@ -0,0 +1,25 @@
+use crate::vec3::Vec3;
+use crate::material::{_Material, Scatter};
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+
+// A light source, which emits light evenly in every direction from the outside of the surface and
+// absorbs any light that arrives at it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Emissive {
+    pub emission: Vec3
+}
+
+impl _Material for Emissive {
+    fn scatter(&self, _ray_in: &Ray, _hit: &HitRecord, _sampler: &mut Sampler) -> Option<Scatter> { None }
+    // Lights don't reflect anything, but their colour is what the denoiser should preserve, so
+    // they're treated as white.
+    fn albedo(&self, _hit: &HitRecord) -> Vec3 { Vec3::new(1.0, 1.0, 1.0) }
+    fn pdf(&self, _ray_in: &Ray, _hit: &HitRecord, _direction: Vec3) -> f64 { 0.0 }
+    fn bsdf(&self, _ray_in: &Ray, _hit: &HitRecord, _direction: Vec3) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 {
+        return if ray_in.direction.dot(&hit.normal) < 0.0 { self.emission } else { Vec3::new(0.0, 0.0, 0.0) };
+    }
+    fn is_delta(&self) -> bool { false }
+}
@@ -1,18 +1,33 @@
+use std::f64::consts::PI;
+
 use crate::vec3::Vec3;
-use crate::material::_Material;
+use crate::material::{_Material, Lobe, Scatter};
 use crate::ray::Ray;
 use crate::hitable::HitRecord;
-use crate::hitable::sphere::random_point_in_unit_sphere;
+use crate::sampler::Sampler;
+use crate::sampling::sample_unit_sphere;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Lambertian {
     pub albedo: Vec3
 }
 
 impl _Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, hit: &HitRecord) -> Ray {
-        let target = hit.p + hit.normal + random_point_in_unit_sphere();
-        return Ray { origin: hit.p, direction: target - hit.p };
+    // Offsetting the normal by a random unit vector gives a cosine weighted distribution of
+    // scattered directions.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let direction = hit.normal + sample_unit_sphere(sampler.get_2d());
+        // Guard against the unlikely case that the random vector cancels out the normal.
+        let direction = if direction.squared_length() < 1e-12 { hit.normal } else { direction };
+        return Some(Scatter { ray: Ray { origin: hit.p, direction, wavelengths: ray_in.wavelengths }, lobe: Lobe::Diffuse, attenuation: ray_in.wavelengths.spectrum(self.albedo) });
+    }
+    fn albedo(&self, _hit: &HitRecord) -> Vec3 { self.albedo }
+    fn pdf(&self, _ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        direction.unit_vector().dot(&hit.normal).max(0.0) / PI
+    }
+    fn bsdf(&self, _ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        return if direction.dot(&hit.normal) > 0.0 { self.albedo / PI } else { Vec3::new(0.0, 0.0, 0.0) };
     }
-    fn albedo(&self) -> Vec3 { self.albedo }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { false }
 }
@@ -1,19 +1,50 @@
+use std::f64::consts::PI;
+
 use crate::vec3::Vec3;
-use crate::material::{_Material, reflect};
+use crate::material::{_Material, reflect, Lobe, Scatter};
 use crate::ray::Ray;
 use crate::hitable::HitRecord;
-use crate::hitable::sphere::random_point_in_unit_sphere;
+use crate::sampler::Sampler;
+use crate::sampling::sample_unit_ball;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Metal {
     pub albedo: Vec3,
     pub fuzziness: f64
 }
 
 impl _Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Ray {
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let reflected = reflect(ray_in.direction.unit_vector(), hit.normal);
+        let fuzz = sample_unit_ball(sampler.get_2d(), sampler.get_1d());
+        return Some(Scatter { ray: Ray { origin: hit.p, direction: reflected + self.fuzziness * fuzz, wavelengths: ray_in.wavelengths }, lobe: Lobe::Specular, attenuation: ray_in.wavelengths.spectrum(self.albedo) });
+    }
+    fn albedo(&self, _hit: &HitRecord) -> Vec3 { self.albedo }
+    // Scattered directions are the directions from the hit point to points uniformly distributed
+    // within a ball of radius fuzziness centred on the end of the unit reflected vector. The density
+    // of a direction is the volume of the ball along that direction, which is found by integrating
+    // r^2 dr over the chord where the direction passes through the ball.
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        if self.fuzziness <= 0.0 { return 0.0 }
+
         let reflected = reflect(ray_in.direction.unit_vector(), hit.normal);
-        return Ray { origin: hit.p, direction: reflected + self.fuzziness * random_point_in_unit_sphere()};
+        let cosine = direction.unit_vector().dot(&reflected);
+        let discriminant = self.fuzziness * self.fuzziness - (1.0 - cosine * cosine);
+        if discriminant <= 0.0 { return 0.0 }
+
+        let near = (cosine - discriminant.sqrt()).max(0.0);
+        let far = cosine + discriminant.sqrt();
+        if far <= 0.0 { return 0.0 }
+
+        let volume = 4.0 / 3.0 * PI * self.fuzziness.powi(3);
+        return (far.powi(3) - near.powi(3)) / (3.0 * volume);
+    }
+    // Scattering always reflects albedo of the light, so the BSDF is whatever gives that for the
+    // density of the scattered directions.
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let cosine = direction.unit_vector().dot(&hit.normal).abs();
+        return if cosine > 0.0 { self.albedo * self.pdf(ray_in, hit, direction) / cosine } else { Vec3::new(0.0, 0.0, 0.0) };
     }
-    fn albedo(&self) -> Vec3 { self.albedo }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { self.fuzziness <= 0.0 }
 }
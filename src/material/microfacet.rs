@@ -0,0 +1,177 @@
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+// Microfacet models treat a rough surface as a mass of tiny mirrors, whose normals are spread
+// around the surface normal according to a distribution. They're evaluated in a local frame where
+// the surface normal is the z axis.
+
+// Roughness below which a surface is treated as perfectly smooth, where the distribution is too
+// sharp to evaluate reliably.
+const SMOOTH_ROUGHNESS: f64 = 1e-3;
+
+// An orthonormal basis around a normal, for moving directions into and out of the local frame of
+// a surface.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame {
+    pub s: Vec3,
+    pub t: Vec3,
+    pub n: Vec3,
+}
+
+impl Frame {
+
+    // A frame with tangents chosen from the normal alone, using the construction of Duff et al.,
+    // which is continuous everywhere except where the normal crosses the xy plane.
+    pub fn from_normal(n: Vec3) -> Frame {
+        let sign = 1.0_f64.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        let s = Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+        let t = Vec3::new(b, sign + n.y * n.y * a, -n.y);
+        return Frame { s, t, n };
+    }
+
+    // A frame whose first tangent follows the given tangent of the surface, such as its dpdu, so
+    // that anisotropic materials line up with the surface's coordinates. Falls back to from_normal
+    // where the tangent is degenerate, such as at the poles of a sphere.
+    pub fn from_tangent(n: Vec3, tangent: Vec3) -> Frame {
+        let s = tangent - tangent.dot(&n) * n;
+        if s.squared_length() < 1e-18 { return Frame::from_normal(n) }
+        let s = s.unit_vector();
+        return Frame { s, t: n.cross(&s), n };
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.s + v.y * self.t + v.z * self.n
+    }
+}
+
+// The GGX, or Trowbridge-Reitz, distribution of microfacet normals, with Smith's
+// masking-shadowing function. The roughnesses along the two tangents are the alpha parameters of
+// the distribution, and differ for anisotropic surfaces such as brushed metal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+
+    pub fn isotropic(alpha: f64) -> Ggx {
+        Ggx { alpha_x: alpha, alpha_y: alpha }
+    }
+
+    // Whether the surface is smooth enough to treat as a perfect mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ROUGHNESS
+    }
+
+    // Density of microfacet normals m, per unit of projected area of the surface.
+    pub fn d(&self, m: Vec3) -> f64 {
+        if m.z <= 0.0 { return 0.0 }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        return 1.0 / (PI * self.alpha_x * self.alpha_y * e * e);
+    }
+
+    // Smith's auxiliary function, the ratio of the area of microfacets facing away from w to
+    // those facing towards it.
+    fn lambda(&self, w: Vec3) -> f64 {
+        let tan_squared = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        return ((1.0 + tan_squared).sqrt() - 1.0) / 2.0;
+    }
+
+    // Fraction of the microfacets facing w that are visible from w.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction of the microfacets that are visible from both directions, using the height
+    // correlated form.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Sample a microfacet normal from those visible from wo, which must be above the surface,
+    // using Heitz's method: stretch the view into the configuration where the surface has a
+    // roughness of 1, sample the projected area of a hemisphere there and unstretch the normal.
+    pub fn sample_visible_normal(&self, wo: Vec3, u: (f64, f64)) -> Vec3 {
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / length_squared.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t2 = vh.cross(&t1);
+
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        return Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector();
+    }
+
+    // Density with which sample_visible_normal chooses the normal m, with respect to solid angle.
+    pub fn visible_normal_pdf(&self, wo: Vec3, m: Vec3) -> f64 {
+        self.g1(wo) * wo.dot(&m).max(0.0) * self.d(m) / wo.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::stat_tests::{chi_square_test, SphereHistogram};
+
+    #[test]
+    fn test_frame_is_orthonormal() {
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            let n = crate::sampling::sample_unit_sphere((rng.next_f64(), rng.next_f64()));
+            let frame = Frame::from_normal(n);
+            for (a, b) in [(frame.s, frame.t), (frame.t, frame.n), (frame.n, frame.s)].iter() {
+                assert!(a.dot(b).abs() < 1e-9 && (a.length() - 1.0).abs() < 1e-9);
+            }
+            let tangent = crate::sampling::sample_unit_sphere((rng.next_f64(), rng.next_f64()));
+            let tangent_frame = Frame::from_tangent(n, tangent);
+            for (a, b) in [(tangent_frame.s, tangent_frame.t), (tangent_frame.t, tangent_frame.n), (tangent_frame.n, tangent_frame.s)].iter() {
+                assert!(a.dot(b).abs() < 1e-9 && (a.length() - 1.0).abs() < 1e-9);
+            }
+            // Both frames are right handed, and the tangent frame follows the tangent.
+            assert!((frame.s.cross(&frame.t) - n).length() < 1e-9 && (tangent_frame.s.cross(&tangent_frame.t) - n).length() < 1e-9);
+            assert!(tangent_frame.s.dot(&tangent) > 0.0 && tangent_frame.t.dot(&tangent).abs() < 1e-9);
+            let v = Vec3::new(0.3, -0.5, 0.8);
+            assert!((frame.to_world(frame.to_local(v)) - v).length() < 1e-9);
+            assert!((frame.to_local(n) - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_projected_normal_distribution_integrates_to_one() {
+        let histogram = SphereHistogram { theta_bins: 200, phi_bins: 200 };
+        for ggx in [Ggx::isotropic(0.2), Ggx::isotropic(0.8), Ggx { alpha_x: 0.1, alpha_y: 0.5 }].iter() {
+            let total: f64 = histogram.expected(1, |m| ggx.d(m) * m.z.max(0.0)).iter().sum();
+            assert!((total - 1.0).abs() < 1e-2, "{:?} integrates to {}", ggx, total);
+        }
+    }
+
+    #[test]
+    fn test_visible_normal_sampling_matches_its_pdf() {
+        let samples = 100000;
+        let histogram = SphereHistogram { theta_bins: 20, phi_bins: 40 };
+        let wo = Vec3::new(0.6, 0.2, 0.5).unit_vector();
+        let mut rng = Rng::new(1);
+        for ggx in [Ggx::isotropic(0.4), Ggx { alpha_x: 0.2, alpha_y: 0.7 }].iter() {
+            let normals: Vec<Vec3> = (0..samples).map(|_| ggx.sample_visible_normal(wo, (rng.next_f64(), rng.next_f64()))).collect();
+            let observed = histogram.observed(&normals);
+            let expected = histogram.expected(samples, |m| ggx.visible_normal_pdf(wo, m));
+            if let Err(message) = chi_square_test(&observed, &expected, 1e-3) {
+                panic!("{:?} doesn't sample visible normals according to their pdf: {}", ggx, message);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::vec3::Vec3;
+use crate::material::{_Material, Material, Scatter};
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
+
+// A blend of two materials, such as rust over metal, where amount is the fraction of the second
+// material at each point of the surface. Light is scattered by one of the materials, chosen with
+// the probability of its fraction, and the BSDF is the blend of the two BSDFs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mix {
+    pub first: Box<Material>,
+    pub second: Box<Material>,
+    pub amount: Texture,
+}
+
+impl Mix {
+
+    fn amount(&self, hit: &HitRecord) -> f64 {
+        self.amount.scalar_value(hit).clamp(0.0, 1.0)
+    }
+}
+
+impl _Material for Mix {
+    // When neither material only scatters into discrete directions the direction is weighted by
+    // the blended BSDF and density, which is less noisy than using those of the chosen material
+    // alone. Otherwise the chosen material's scatter is used as it is, which is still correct on
+    // average as each material is chosen in proportion to its fraction.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let amount = self.amount(hit);
+        let chosen = if sampler.get_1d() < amount { &self.second } else { &self.first };
+        let scatter = chosen.scatter(ray_in, hit, sampler)?;
+        if self.first.is_delta() || self.second.is_delta() { return Some(scatter) }
+
+        let direction = scatter.ray.direction;
+        let pdf = self.pdf(ray_in, hit, direction);
+        if pdf <= 0.0 { return None }
+        let cosine = direction.unit_vector().dot(&hit.normal).abs();
+        return Some(Scatter { attenuation: ray_in.wavelengths.spectrum(self.bsdf(ray_in, hit, direction) * cosine / pdf), ..scatter });
+    }
+    fn albedo(&self, hit: &HitRecord) -> Vec3 {
+        let amount = self.amount(hit);
+        return (1.0 - amount) * self.first.albedo(hit) + amount * self.second.albedo(hit);
+    }
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let amount = self.amount(hit);
+        return (1.0 - amount) * self.first.pdf(ray_in, hit, direction) + amount * self.second.pdf(ray_in, hit, direction);
+    }
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let amount = self.amount(hit);
+        return (1.0 - amount) * self.first.rgb_bsdf(ray_in, hit, direction) + amount * self.second.rgb_bsdf(ray_in, hit, direction);
+    }
+    fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 {
+        let amount = self.amount(hit);
+        return (1.0 - amount) * self.first.rgb_emitted(ray_in, hit) + amount * self.second.rgb_emitted(ray_in, hit);
+    }
+    // Only when both materials only scatter into discrete directions. Integrators that connect
+    // paths to lights only see the other material of a blend with one that does.
+    fn is_delta(&self) -> bool { self.first.is_delta() && self.second.is_delta() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::conductor::ComplexIor;
+    use crate::stat_tests::{average_attenuation, check_scattering_matches_pdf, hit, integrate_bsdf, SphereHistogram};
+
+    #[test]
+    fn test_mix_scattering_matches_its_pdf() {
+        let material = Material::mix(Material::lambertian(0.8, 0.2, 0.2), Material::metal(0.8, 0.8, 0.8, 0.5), 0.3);
+        check_scattering_matches_pdf(&material, SphereHistogram { theta_bins: 40, phi_bins: 80 });
+    }
+
+    #[test]
+    fn test_mix_scattering_leaves_what_its_bsdf_does() {
+        let materials = [
+            Material::mix(Material::lambertian(0.8, 0.2, 0.2), Material::conductor(ComplexIor::gold(), 0.3), 0.6),
+            // A smooth metal can't be evaluated, so only the fraction of its light it scatters is known.
+            Material::mix(Material::lambertian(0.8, 0.8, 0.8), Material::metal(0.5, 0.5, 0.5, 0.0), 0.25),
+        ];
+        let expected = [None, Some(Vec3::new(0.75 * 0.8 + 0.25 * 0.5, 0.75 * 0.8 + 0.25 * 0.5, 0.75 * 0.8 + 0.25 * 0.5))];
+        for (material, expected) in materials.iter().zip(expected.iter()) {
+            let (ray, hit) = hit(material);
+            let scattered = average_attenuation(material, &ray, &hit);
+            let expected = expected.unwrap_or_else(|| integrate_bsdf(material, &ray, &hit));
+            assert!((scattered - expected).length() < 0.01, "{:?} scatters {} rather than {}", material, scattered, expected);
+        }
+    }
+
+    #[test]
+    fn test_textured_mix_follows_its_texture() {
+        let (white, black) = (Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0));
+        let material = Material::textured_mix(Material::lambertian(1.0, 0.0, 0.0), Material::lambertian(0.0, 0.0, 1.0), Texture::checker(white, black, 2.0));
+        let (_, hit) = hit(&material);
+        assert_eq!(material.albedo(&HitRecord { uv: (0.1, 0.1), ..hit }), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(material.albedo(&HitRecord { uv: (0.6, 0.1), ..hit }), Vec3::new(1.0, 0.0, 0.0));
+    }
+}
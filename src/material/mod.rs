@@ -1,36 +1,152 @@
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::hitable::HitRecord;
+use crate::medium::Medium;
 use crate::material::lambertian::Lambertian;
-use crate::material::dielectric::Dielectric;
+use crate::material::dielectric::{Dielectric, Dispersion, D_LINE};
 use crate::material::metal::Metal;
+use crate::material::conductor::{ComplexIor, Conductor};
+use crate::material::microfacet::Ggx;
+use crate::material::rough_dielectric::RoughDielectric;
+use crate::material::principled::Principled;
+use crate::material::mix::Mix;
+use crate::material::coated::{Coated, MINIMUM_ALPHA};
+use crate::material::normal_map::{NormalMap, NormalMapped};
+use crate::material::emissive::Emissive;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
 
 pub mod lambertian;
 pub mod metal;
+pub mod conductor;
+pub mod microfacet;
 pub mod dielectric;
+pub mod rough_dielectric;
+pub mod principled;
+pub mod mix;
+pub mod coated;
+pub mod normal_map;
+pub mod emissive;
 
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - (2.0 * v.dot(&n) * n)
 }
 
+// The kinds of scattering a material can do. The integrator limits the number of bounces of each
+// kind separately.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    // Reflection in or around the mirror direction.
+    Specular,
+    // Refraction through the surface.
+    Transmission,
+}
+
+// A ray scattered by a material, and the kind of scattering that produced it.
+#[derive(Debug, Copy, Clone)]
+pub struct Scatter {
+    pub ray: Ray,
+    pub lobe: Lobe,
+    // Fraction of the light that leaves along the ray, at the wavelengths it carries.
+    pub attenuation: Vec3,
+}
+
 // Internal trait that defines the API for underlying Materials.
 // Note that the Material enum forms the public API for materials and wraps these private types.
 trait _Material {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Ray;
-    fn albedo(&self) -> Vec3;
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter>;
+    fn albedo(&self, hit: &HitRecord) -> Vec3;
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64;
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3;
+    fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3;
+    fn is_delta(&self) -> bool;
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
+    Conductor(Conductor),
     Dielectric(Dielectric),
+    RoughDielectric(RoughDielectric),
+    Principled(Box<Principled>),
+    Mix(Mix),
+    Coated(Coated),
+    NormalMapped(NormalMapped),
+    Emissive(Emissive),
 }
 
 impl Material {
 
     pub fn dielectric(refractive_index: f64) -> Material {
-        return Material::Dielectric(Dielectric { refractive_index });
+        return Material::Dielectric(Dielectric { refractive_index, dispersion: None, absorption: Vec3::new(0.0, 0.0, 0.0) });
+    }
+
+    // A dielectric whose refractive index varies with wavelength, such as Dispersion::bk7(). RGB
+    // renders use its index at the sodium D line.
+    pub fn dispersive_dielectric(dispersion: Dispersion) -> Material {
+        return Material::Dielectric(Dielectric { refractive_index: dispersion.refractive_index(D_LINE), dispersion: Some(dispersion), absorption: Vec3::new(0.0, 0.0, 0.0) });
+    }
+
+    // Coloured glass, which absorbs the given fraction of each colour per unit distance travelled
+    // inside it, so thick glass is darker than thin.
+    pub fn absorbing_dielectric(refractive_index: f64, absorption: Vec3) -> Material {
+        return Material::Dielectric(Dielectric { refractive_index, dispersion: None, absorption });
+    }
+
+    // Frosted glass, with the roughness as the GGX alpha of its surface.
+    pub fn rough_dielectric(refractive_index: f64, roughness: f64) -> Material {
+        return Material::RoughDielectric(RoughDielectric { refractive_index, distribution: Ggx::isotropic(roughness) });
+    }
+
+    // A principled material, such as
+    // Material::principled(Principled { metallic: Texture::scalar(1.0), ..Principled::default() }).
+    pub fn principled(principled: Principled) -> Material {
+        return Material::Principled(Box::new(principled));
+    }
+
+    // A blend of two materials, with amount the fraction of the second.
+    pub fn mix(first: Material, second: Material, amount: f64) -> Material {
+        return Material::textured_mix(first, second, Texture::scalar(amount));
+    }
+
+    // A blend of two materials that varies over the surface, such as patches of rust on a metal.
+    pub fn textured_mix(first: Material, second: Material, amount: Texture) -> Material {
+        return Material::Mix(Mix { first: Box::new(first), second: Box::new(second), amount });
+    }
+
+    // A clear coat over the base, such as varnish. The roughness is the GGX alpha of the coat,
+    // where 0 is as glossy as the coat can be.
+    pub fn coated(base: Material, refractive_index: f64, roughness: f64) -> Material {
+        return Material::Coated(Coated { base: Box::new(base), refractive_index, distribution: Ggx::isotropic(roughness.max(MINIMUM_ALPHA)) });
+    }
+
+    // The base material with its shading normals taken from a tangent space normal map.
+    pub fn normal_mapped(base: Material, normals: Texture) -> Material {
+        return Material::NormalMapped(NormalMapped { base: Box::new(base), map: NormalMap::TangentSpace(normals) });
+    }
+
+    // The base material with its shading normals tilted by the slopes of a height map, whose
+    // values are multiplied by scale.
+    pub fn bump_mapped(base: Material, height: Texture, scale: f64) -> Material {
+        return Material::NormalMapped(NormalMapped { base: Box::new(base), map: NormalMap::Bump { height, scale } });
+    }
+
+    // Shade base with the shading normal of the hit rather than the geometric normal, which is
+    // how meshes smooth their facets. Normal mapped materials already start from the shading
+    // normal and emission doesn't depend on it, so those are left as they are.
+    pub fn smooth_shaded(base: Material) -> Material {
+        return match base {
+            Material::NormalMapped(_) | Material::Emissive(_) => base,
+            _ => Material::NormalMapped(NormalMapped { base: Box::new(base), map: NormalMap::Smooth }),
+        };
+    }
+
+    pub fn emissive(r: f64, g: f64, b: f64) -> Material {
+        return Material::Emissive(Emissive {
+            emission: Vec3::new(r, g, b)
+        })
     }
 
     pub fn lambertian(r: f64, g: f64, b: f64) -> Material {
@@ -46,20 +162,172 @@ impl Material {
         })
     }
 
-    pub fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Ray {
+    // A microfacet metal such as ComplexIor::gold(). The roughness is the GGX alpha, from 0 for a
+    // mirror to around 1 for a very rough surface.
+    pub fn conductor(ior: ComplexIor, roughness: f64) -> Material {
+        return Material::Conductor(Conductor { ior, distribution: Ggx::isotropic(roughness) })
+    }
+
+    // A microfacet metal with different roughnesses along the two tangents of the surface, like
+    // brushed metal.
+    pub fn anisotropic_conductor(ior: ComplexIor, roughness_x: f64, roughness_y: f64) -> Material {
+        return Material::Conductor(Conductor { ior, distribution: Ggx { alpha_x: roughness_x, alpha_y: roughness_y } })
+    }
+
+    // Choose a direction for light arriving along ray_in to leave in, in proportion to the
+    // material's BSDF. The fraction of the light that leaves is given by the scatter's attenuation,
+    // which is usually the albedo. Returns None if the material absorbs all of the light.
+    pub fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
         match *self {
-            Material::Lambertian(ref lambertian) => lambertian.scatter(ray_in, hit),
-            Material::Metal(ref metal) => metal.scatter(ray_in, hit),
-            Material::Dielectric(ref dielectric) => dielectric.scatter(ray_in, hit),
+            Material::Lambertian(ref lambertian) => lambertian.scatter(ray_in, hit, sampler),
+            Material::Metal(ref metal) => metal.scatter(ray_in, hit, sampler),
+            Material::Conductor(ref conductor) => conductor.scatter(ray_in, hit, sampler),
+            Material::Dielectric(ref dielectric) => dielectric.scatter(ray_in, hit, sampler),
+            Material::RoughDielectric(ref rough) => rough.scatter(ray_in, hit, sampler),
+            Material::Principled(ref principled) => principled.scatter(ray_in, hit, sampler),
+            Material::Mix(ref mix) => mix.scatter(ray_in, hit, sampler),
+            Material::Coated(ref coated) => coated.scatter(ray_in, hit, sampler),
+            Material::NormalMapped(ref mapped) => mapped.scatter(ray_in, hit, sampler),
+            Material::Emissive(ref emissive) => emissive.scatter(ray_in, hit, sampler),
         }
     }
 
-    pub fn albedo(&self) -> Vec3 {
+    // Fraction of the light arriving at the surface that scatter passes on, as an RGB colour.
+    pub fn albedo(&self, hit: &HitRecord) -> Vec3 {
         match *self {
-            Material::Lambertian(ref lambertian) => lambertian.albedo(),
-            Material::Metal(ref metal) => metal.albedo(),
-            Material::Dielectric(ref dielectric) => dielectric.albedo(),
+            Material::Lambertian(ref lambertian) => lambertian.albedo(hit),
+            Material::Metal(ref metal) => metal.albedo(hit),
+            Material::Conductor(ref conductor) => conductor.albedo(hit),
+            Material::Dielectric(ref dielectric) => dielectric.albedo(hit),
+            Material::RoughDielectric(ref rough) => rough.albedo(hit),
+            Material::Principled(ref principled) => principled.albedo(hit),
+            Material::Mix(ref mix) => mix.albedo(hit),
+            Material::Coated(ref coated) => coated.albedo(hit),
+            Material::NormalMapped(ref mapped) => mapped.albedo(hit),
+            Material::Emissive(ref emissive) => emissive.albedo(hit),
         }
     }
 
+    // Probability density, with respect to solid angle, of scatter choosing the given direction.
+    // Materials that only scatter into a few discrete directions, such as Dielectric and smooth
+    // Metal, have no density and return zero.
+    pub fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        match *self {
+            Material::Lambertian(ref lambertian) => lambertian.pdf(ray_in, hit, direction),
+            Material::Metal(ref metal) => metal.pdf(ray_in, hit, direction),
+            Material::Conductor(ref conductor) => conductor.pdf(ray_in, hit, direction),
+            Material::Dielectric(ref dielectric) => dielectric.pdf(ray_in, hit, direction),
+            Material::RoughDielectric(ref rough) => rough.pdf(ray_in, hit, direction),
+            Material::Principled(ref principled) => principled.pdf(ray_in, hit, direction),
+            Material::Mix(ref mix) => mix.pdf(ray_in, hit, direction),
+            Material::Coated(ref coated) => coated.pdf(ray_in, hit, direction),
+            Material::NormalMapped(ref mapped) => mapped.pdf(ray_in, hit, direction),
+            Material::Emissive(ref emissive) => emissive.pdf(ray_in, hit, direction),
+        }
+    }
+
+    // Value of the BSDF for light arriving from the given direction and leaving back along
+    // ray_in, without the cosine term. Like pdf, this is zero for materials that only scatter into
+    // discrete directions. Scattering reflects albedo of the light arriving, so for the other
+    // materials bsdf * |cos| / pdf is albedo. The value is given at the wavelengths that ray_in
+    // carries.
+    pub fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        return ray_in.wavelengths.spectrum(self.rgb_bsdf(ray_in, hit, direction));
+    }
+
+    // The BSDF as an RGB colour, for materials made of other materials to blend.
+    fn rgb_bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        match *self {
+            Material::Lambertian(ref lambertian) => lambertian.bsdf(ray_in, hit, direction),
+            Material::Metal(ref metal) => metal.bsdf(ray_in, hit, direction),
+            Material::Conductor(ref conductor) => conductor.bsdf(ray_in, hit, direction),
+            Material::Dielectric(ref dielectric) => dielectric.bsdf(ray_in, hit, direction),
+            Material::RoughDielectric(ref rough) => rough.bsdf(ray_in, hit, direction),
+            Material::Principled(ref principled) => principled.bsdf(ray_in, hit, direction),
+            Material::Mix(ref mix) => mix.bsdf(ray_in, hit, direction),
+            Material::Coated(ref coated) => coated.bsdf(ray_in, hit, direction),
+            Material::NormalMapped(ref mapped) => mapped.bsdf(ray_in, hit, direction),
+            Material::Emissive(ref emissive) => emissive.bsdf(ray_in, hit, direction),
+        }
+    }
+
+    // Light emitted by the surface back along ray_in, at the wavelengths that it carries.
+    pub fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 {
+        return ray_in.wavelengths.spectrum(self.rgb_emitted(ray_in, hit));
+    }
+
+    fn rgb_emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 {
+        match *self {
+            Material::Lambertian(ref lambertian) => lambertian.emitted(ray_in, hit),
+            Material::Metal(ref metal) => metal.emitted(ray_in, hit),
+            Material::Conductor(ref conductor) => conductor.emitted(ray_in, hit),
+            Material::Dielectric(ref dielectric) => dielectric.emitted(ray_in, hit),
+            Material::RoughDielectric(ref rough) => rough.emitted(ray_in, hit),
+            Material::Principled(ref principled) => principled.emitted(ray_in, hit),
+            Material::Mix(ref mix) => mix.emitted(ray_in, hit),
+            Material::Coated(ref coated) => coated.emitted(ray_in, hit),
+            Material::NormalMapped(ref mapped) => mapped.emitted(ray_in, hit),
+            Material::Emissive(ref emissive) => emissive.emitted(ray_in, hit),
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        matches!(*self, Material::Emissive(_))
+    }
+
+    // Whether the material only scatters into discrete directions, so that its BSDF can't be
+    // evaluated for a direction chosen some other way, such as towards a light.
+    pub fn is_delta(&self) -> bool {
+        match *self {
+            Material::Lambertian(ref lambertian) => lambertian.is_delta(),
+            Material::Metal(ref metal) => metal.is_delta(),
+            Material::Conductor(ref conductor) => conductor.is_delta(),
+            Material::Dielectric(ref dielectric) => dielectric.is_delta(),
+            Material::RoughDielectric(ref rough) => rough.is_delta(),
+            Material::Principled(ref principled) => principled.is_delta(),
+            Material::Mix(ref mix) => mix.is_delta(),
+            Material::Coated(ref coated) => coated.is_delta(),
+            Material::NormalMapped(ref mapped) => mapped.is_delta(),
+            Material::Emissive(ref emissive) => emissive.is_delta(),
+        }
+    }
+
+    // The medium inside the material that light passing into it travels through, if it absorbs
+    // that light. Only glass has one.
+    pub fn interior_medium(&self) -> Option<Medium> {
+        match *self {
+            Material::Dielectric(ref dielectric) => dielectric.medium(),
+            Material::NormalMapped(ref mapped) => mapped.base.interior_medium(),
+            _ => None,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stat_tests::{check_scattering_matches_pdf, hit, SphereHistogram};
+
+    #[test]
+    fn test_lambertian_scattering_matches_its_pdf() {
+        check_scattering_matches_pdf(&Material::lambertian(0.5, 0.5, 0.5), SphereHistogram { theta_bins: 20, phi_bins: 40 });
+    }
+
+    #[test]
+    fn test_fuzzy_metal_scattering_matches_its_pdf() {
+        for fuzziness in [0.3, 1.0, 1.5].iter() {
+            check_scattering_matches_pdf(&Material::metal(0.8, 0.8, 0.8, *fuzziness), SphereHistogram { theta_bins: 40, phi_bins: 80 });
+        }
+    }
+
+    #[test]
+    fn test_pdfs_integrate_to_one() {
+        let histogram = SphereHistogram { theta_bins: 100, phi_bins: 200 };
+        for material in [Material::lambertian(0.5, 0.5, 0.5), Material::metal(0.8, 0.8, 0.8, 0.5)].iter() {
+            let (ray, hit) = hit(material);
+            let total: f64 = histogram.expected(1, |direction| material.pdf(&ray, &hit, direction)).iter().sum();
+            assert!((total - 1.0).abs() < 1e-3, "{:?} pdf integrates to {}", material, total);
+        }
+    }
 }
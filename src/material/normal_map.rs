@@ -0,0 +1,206 @@
+use crate::vec3::Vec3;
+use crate::material::{_Material, reflect, Material, Scatter};
+use crate::material::microfacet::Frame;
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
+
+// Step in u and v used to find the slope of a height map.
+const BUMP_DELTA: f64 = 5e-4;
+// Smallest cosine allowed between the shading normal and the incoming direction, or between the
+// geometric normal and the incoming direction's reflection about the shading normal.
+const MINIMUM_COSINE: f64 = 1e-3;
+
+// A way of perturbing the normal used for shading, which adds detail to a surface without adding
+// geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NormalMap {
+    // Normals in the frame of the surface's tangent, bitangent and normal, encoded as colours with
+    // each component mapped from [-1, 1] to [0, 1], so that (0.5, 0.5, 1) leaves the normal as it
+    // is. Images of normals should be read with Texture::data_image.
+    TangentSpace(Texture),
+    // Heights above the surface, multiplied by scale, whose slopes tilt the normal.
+    Bump { height: Texture, scale: f64 },
+    // The shading normal the surface already has, which on meshes is interpolated from the
+    // vertex normals to smooth the shading of the facets.
+    Smooth,
+}
+
+impl NormalMap {
+
+    // The perturbed normal at the hit, on the same side of the surface as hit.normal. Maps
+    // perturb the shading normal, so they follow the smoothed surface of a mesh.
+    pub fn normal(&self, hit: &HitRecord) -> Vec3 {
+        let n = hit.shading_normal;
+        let perturbed = match *self {
+            NormalMap::TangentSpace(ref texture) => {
+                let frame = Frame::from_tangent(n, hit.dpdu);
+                // Keep the bitangent pointing along dpdv, even where the normal points into the
+                // surface, as it does inside hollow spheres.
+                let t = if frame.t.dot(&hit.dpdv) < 0.0 { -frame.t } else { frame.t };
+                let m = 2.0 * texture.value(hit) - Vec3::new(1.0, 1.0, 1.0);
+                m.x * frame.s + m.y * t + m.z * n
+            }
+            NormalMap::Bump { ref height, scale } => {
+                let height_at = |du: f64, dv: f64| scale * height.scalar_value(&HitRecord { uv: (hit.uv.0 + du, hit.uv.1 + dv), ..*hit });
+                let h = height_at(0.0, 0.0);
+                let dhdu = (height_at(BUMP_DELTA, 0.0) - h) / BUMP_DELTA;
+                let dhdv = (height_at(0.0, BUMP_DELTA) - h) / BUMP_DELTA;
+                // The displaced surface p + h n has these derivatives, ignoring the change in n.
+                (hit.dpdu + dhdu * n).cross(&(hit.dpdv + dhdv * n))
+            }
+            NormalMap::Smooth => n,
+        };
+        if perturbed.squared_length() < 1e-18 { return n }
+        let perturbed = perturbed.unit_vector();
+        return if perturbed.dot(&n) < 0.0 { -perturbed } else { perturbed };
+    }
+}
+
+// Tilt the shading normal ns towards the geometric normal ng until the incoming direction wo is
+// above it and its reflection about it stays above the surface, as otherwise mirror-like
+// reflections would go into the surface and come out black. All three are on the same side.
+fn valid_shading_normal(ng: Vec3, ns: Vec3, wo: Vec3) -> Vec3 {
+    let valid = |n: Vec3| wo.dot(&n) >= MINIMUM_COSINE && reflect(-wo, n).dot(&ng) >= MINIMUM_COSINE;
+    if valid(ns) { return ns }
+    if !valid(ng) { return ng }
+
+    let blend = |t: f64| ((1.0 - t) * ns + t * ng).unit_vector();
+    let (mut invalid, mut valid_t) = (0.0, 1.0);
+    for _ in 0..20 {
+        let t = 0.5 * (invalid + valid_t);
+        if valid(blend(t)) { valid_t = t } else { invalid = t }
+    }
+    return blend(valid_t);
+}
+
+// A material whose shading normal is perturbed by a normal map, such as scratches on metal or the
+// grain of wood. The base material sees the hit with the shading normal in place of the geometric
+// one.
+//
+// The two normals can disagree about which side of the surface a direction is on. Light is only
+// scattered into directions that both agree on, so it never leaks through the surface, and the
+// shading normal is tilted back towards the geometric normal where it faces away from the
+// incoming light. The BSDF includes the ratio of the cosines to the two normals, so integrators
+// that weight by the geometric cosine get the light that the base material scatters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalMapped {
+    pub base: Box<Material>,
+    pub map: NormalMap,
+}
+
+impl NormalMapped {
+
+    // The hit as the base material sees it, with the shading normal for light arriving along
+    // ray_in.
+    pub fn shading(&self, ray_in: &Ray, hit: &HitRecord) -> HitRecord<'_> {
+        let ng = hit.normal;
+        let side = if ray_in.direction.dot(&ng) > 0.0 { -1.0 } else { 1.0 };
+        let ns = valid_shading_normal(side * ng, side * self.map.normal(hit), -ray_in.direction.unit_vector());
+        return HitRecord { t: hit.t, p: hit.p, normal: side * ns, shading_normal: side * ns, uv: hit.uv, dpdu: hit.dpdu, dpdv: hit.dpdv, material: &self.base, material_id: hit.material_id, object_id: hit.object_id };
+    }
+}
+
+// Whether the geometric and shading normals agree about which side of the surface direction is on.
+fn same_side(hit: &HitRecord, shading: &HitRecord, direction: Vec3) -> bool {
+    direction.dot(&hit.normal) * direction.dot(&shading.normal) > 0.0
+}
+
+impl _Material for NormalMapped {
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let shading = self.shading(ray_in, hit);
+        let scatter = self.base.scatter(ray_in, &shading, sampler)?;
+        return if same_side(hit, &shading, scatter.ray.direction) { Some(scatter) } else { None };
+    }
+    fn albedo(&self, hit: &HitRecord) -> Vec3 { self.base.albedo(hit) }
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let shading = self.shading(ray_in, hit);
+        return if same_side(hit, &shading, direction) { self.base.pdf(ray_in, &shading, direction) } else { 0.0 };
+    }
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let shading = self.shading(ray_in, hit);
+        if !same_side(hit, &shading, direction) { return Vec3::new(0.0, 0.0, 0.0) }
+        let cosines = direction.dot(&shading.normal).abs() / direction.dot(&hit.normal).abs();
+        return cosines * self.base.rgb_bsdf(ray_in, &shading, direction);
+    }
+    fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 { self.base.rgb_emitted(ray_in, hit) }
+    fn is_delta(&self) -> bool { self.base.is_delta() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stat_tests::{average_attenuation, check_scattering_matches_pdf, hit, integrate_bsdf, scattered_directions, SphereHistogram, SAMPLES};
+    use crate::texture::ImageTexture;
+
+    // A normal map tilting the normal by the given angle towards the tangent, which is the x axis
+    // for hits from hit().
+    fn tilted_normals(angle: f64) -> Texture {
+        Texture::constant(0.5 + 0.5 * angle.sin(), 0.5, 0.5 + 0.5 * angle.cos())
+    }
+
+    #[test]
+    fn test_flat_normal_map_leaves_the_material_unchanged() {
+        let lambertian = Material::lambertian(0.5, 0.5, 0.5);
+        let mapped = Material::normal_mapped(lambertian.clone(), Texture::constant(0.5, 0.5, 1.0));
+        let (ray, hit) = hit(&mapped);
+        for direction in [Vec3::new(0.3, 0.2, 0.9), Vec3::new(-0.7, 0.1, 0.2), Vec3::new(0.0, 0.0, -1.0)].iter() {
+            assert!((mapped.bsdf(&ray, &hit, *direction) - lambertian.bsdf(&ray, &hit, *direction)).length() < 1e-12);
+            assert!((mapped.pdf(&ray, &hit, *direction) - lambertian.pdf(&ray, &hit, *direction)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_normal_map_tilts_the_shading_normal_along_the_tangent() {
+        let mapped = Material::normal_mapped(Material::lambertian(0.5, 0.5, 0.5), tilted_normals(0.3));
+        let (ray, hit) = hit(&mapped);
+        match mapped {
+            Material::NormalMapped(ref mapped) => {
+                let normal = mapped.shading(&ray, &hit).normal;
+                assert!((normal - Vec3::new(0.3_f64.sin(), 0.0, 0.3_f64.cos())).length() < 1e-9, "shading normal is {}", normal);
+            }
+            _ => panic!("not normal mapped"),
+        }
+    }
+
+    #[test]
+    fn test_bump_map_tilts_the_normal_down_its_slope() {
+        // Heights rising from 0 to 1 between the texel centres at u = 0.25 and u = 0.75.
+        let heights = ImageTexture { name: "ramp".to_string(), width: 2, height: 1, texels: vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)] };
+        let mapped = Material::bump_mapped(Material::lambertian(0.5, 0.5, 0.5), Texture::Image(std::sync::Arc::new(heights)), 0.25);
+        let (ray, hit) = hit(&mapped);
+        match mapped {
+            Material::NormalMapped(ref mapped) => {
+                let normal = mapped.shading(&ray, &hit).normal;
+                assert!((normal - Vec3::new(-0.5, 0.0, 1.0).unit_vector()).length() < 1e-6, "shading normal is {}", normal);
+            }
+            _ => panic!("not normal mapped"),
+        }
+    }
+
+    #[test]
+    fn test_normal_mapped_scattering_matches_its_pdf_without_leaking_light() {
+        for angle in [0.4, -1.2].iter() {
+            let material = Material::normal_mapped(Material::lambertian(0.5, 0.5, 0.5), tilted_normals(*angle));
+            check_scattering_matches_pdf(&material, SphereHistogram { theta_bins: 20, phi_bins: 40 });
+            assert!(scattered_directions(&material).iter().all(|d| d.z > 0.0));
+
+            let (ray, hit) = hit(&material);
+            let scattered = average_attenuation(&material, &ray, &hit);
+            let bsdf = integrate_bsdf(&material, &ray, &hit);
+            assert!((scattered - bsdf).length() < 0.01, "{:?} scatters {} but its bsdf leaves {}", material, scattered, bsdf);
+            assert!(scattered.x <= 0.5);
+        }
+    }
+
+    #[test]
+    fn test_normal_mapped_mirror_reflects_above_the_surface() {
+        // Tilted away from the incoming ray, the shading normal would face away from it and reflect
+        // it into the surface.
+        let material = Material::normal_mapped(Material::metal(0.8, 0.8, 0.8, 0.0), tilted_normals(1.0));
+        let directions = scattered_directions(&material);
+        assert_eq!(directions.len(), SAMPLES);
+        assert!(directions.iter().all(|d| d.z > 0.0));
+    }
+}
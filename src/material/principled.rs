@@ -0,0 +1,262 @@
+use std::f64::consts::PI;
+
+use crate::film::luminance;
+use crate::vec3::Vec3;
+use crate::material::{_Material, Lobe, Scatter};
+use crate::material::microfacet::{Frame, Ggx};
+use crate::material::rough_dielectric::RoughDielectric;
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+use crate::sampling::sample_unit_sphere;
+use crate::texture::Texture;
+
+// How far the sheen is tinted towards the base colour.
+const SHEEN_TINT: f64 = 0.5;
+// Roughness of the clear coat, which is always fairly glossy.
+const CLEARCOAT_ROUGHNESS: f64 = 0.05;
+// Reflectance of the clear coat at normal incidence, which is that of a refractive index of 1.5.
+const CLEARCOAT_F0: f64 = 0.04;
+// Smallest GGX alpha used, which keeps the specular lobes from becoming too sharp to sample.
+const MINIMUM_ALPHA: f64 = 1e-3;
+
+// A material with intuitive parameters that covers most real surfaces, following the principled
+// BSDF of Burley's Disney model. It blends a diffuse base with sheen, a specular reflection, a
+// clear coat over the top and transmission through the surface. Every parameter is in [0, 1]
+// apart from the refractive index, and every one is a texture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principled {
+    pub base_colour: Texture,
+    // Blends from a dielectric to a metal, whose specular reflection takes the base colour.
+    pub metallic: Texture,
+    pub roughness: Texture,
+    // Strength of the specular reflection of dielectrics, where 0.5 is a refractive index of 1.5.
+    pub specular: Texture,
+    // Tints the specular reflection of dielectrics towards the base colour.
+    pub specular_tint: Texture,
+    // Soft reflection at grazing angles, as seen on cloth.
+    pub sheen: Texture,
+    pub clearcoat: Texture,
+    // Fraction of the light that refracts through the surface rather than being diffused.
+    pub transmission: Texture,
+    pub ior: Texture,
+    // Stretches the specular highlights along the direction u increases in over the surface.
+    pub anisotropy: Texture,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_colour: Texture::scalar(0.8),
+            metallic: Texture::scalar(0.0),
+            roughness: Texture::scalar(0.5),
+            specular: Texture::scalar(0.5),
+            specular_tint: Texture::scalar(0.0),
+            sheen: Texture::scalar(0.0),
+            clearcoat: Texture::scalar(0.0),
+            transmission: Texture::scalar(0.0),
+            ior: Texture::scalar(1.5),
+            anisotropy: Texture::scalar(0.0),
+        }
+    }
+}
+
+// The parameters at a hit, turned into the weights and distributions of the lobes.
+struct Lobes {
+    frame: Frame,
+    base_colour: Vec3,
+    roughness: f64,
+    specular_f0: Vec3,
+    sheen: Vec3,
+    clearcoat: f64,
+    distribution: Ggx,
+    dielectric: RoughDielectric,
+    diffuse_weight: f64,
+    transmission_weight: f64,
+    // Probabilities of sampling the diffuse, specular, clear coat and transmission lobes.
+    probabilities: [f64; 4],
+    // Light arriving from inside can only refract out or reflect back in.
+    inside: bool,
+}
+
+fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+fn schlick(f0: Vec3, cosine: f64) -> Vec3 {
+    f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+fn sqrt(v: Vec3) -> Vec3 {
+    Vec3::new(v.x.max(0.0).sqrt(), v.y.max(0.0).sqrt(), v.z.max(0.0).sqrt())
+}
+
+impl Principled {
+
+    fn lobes(&self, ray_in: &Ray, hit: &HitRecord) -> Lobes {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let base_colour = self.base_colour.value(hit);
+        let metallic = self.metallic.scalar_value(hit).clamp(0.0, 1.0);
+        let roughness = self.roughness.scalar_value(hit).clamp(0.0, 1.0);
+        let transmission = self.transmission.scalar_value(hit).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.scalar_value(hit).max(0.0);
+
+        let tint = if luminance(base_colour) > 0.0 { base_colour / luminance(base_colour) } else { white };
+        let dielectric_f0 = self.specular.scalar_value(hit) * 0.08 * lerp(self.specular_tint.scalar_value(hit), white, tint);
+        let specular_f0 = lerp(metallic, dielectric_f0, base_colour);
+        let sheen = self.sheen.scalar_value(hit) * lerp(SHEEN_TINT, white, tint);
+
+        let aspect = (1.0 - 0.9 * self.anisotropy.scalar_value(hit).clamp(0.0, 1.0)).sqrt();
+        let alpha = roughness * roughness;
+        let distribution = Ggx { alpha_x: (alpha / aspect).max(MINIMUM_ALPHA), alpha_y: (alpha * aspect).max(MINIMUM_ALPHA) };
+        let dielectric = RoughDielectric { refractive_index: self.ior.scalar_value(hit), distribution };
+
+        let inside = ray_in.direction.dot(&hit.normal) > 0.0;
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        let weights = if inside { [0.0, 0.0, 0.0, 1.0] } else { [diffuse_weight, 1.0, 0.25 * clearcoat, transmission_weight] };
+        let total: f64 = weights.iter().sum();
+        let probabilities = [weights[0] / total, weights[1] / total, weights[2] / total, weights[3] / total];
+
+        return Lobes {
+            frame: Frame::from_tangent(if inside { -hit.normal } else { hit.normal }, hit.dpdu),
+            base_colour, roughness, specular_f0, sheen, clearcoat, distribution, dielectric,
+            diffuse_weight, transmission_weight, probabilities, inside,
+        };
+    }
+
+    // The BSDF and the density of sampling direction, from the lobes at the hit.
+    fn evaluate(&self, lobes: &Lobes, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> (Vec3, f64) {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let wo = lobes.frame.to_local(-ray_in.direction.unit_vector());
+        let wi = lobes.frame.to_local(direction.unit_vector());
+        if wo.z <= 0.0 || wi.z == 0.0 { return (black, 0.0) }
+
+        // Refraction through the surface, tinted on the way in and on the way out so that light
+        // passing through an object takes the base colour.
+        let dielectric_bsdf = lobes.dielectric.bsdf(ray_in, hit, direction);
+        let dielectric_bsdf = if wi.z < 0.0 { dielectric_bsdf * sqrt(lobes.base_colour) } else { dielectric_bsdf };
+        let dielectric_pdf = lobes.dielectric.pdf(ray_in, hit, direction);
+        if lobes.inside { return (dielectric_bsdf, dielectric_pdf) }
+
+        let mut bsdf = lobes.transmission_weight * dielectric_bsdf;
+        let mut pdf = lobes.probabilities[3] * dielectric_pdf;
+        if wi.z < 0.0 { return (bsdf, pdf) }
+
+        let h = (wo + wi).unit_vector();
+        let cosine_d = wi.dot(&h);
+
+        // Burley's diffuse, which darkens or brightens at grazing angles depending on the
+        // roughness, plus the sheen.
+        let fd90 = 0.5 + 2.0 * lobes.roughness * cosine_d * cosine_d;
+        let fresnel_weight = |cosine: f64| 1.0 + (fd90 - 1.0) * (1.0 - cosine).powi(5);
+        let diffuse = lobes.base_colour / PI * fresnel_weight(wi.z) * fresnel_weight(wo.z);
+        let sheen = lobes.sheen * (1.0 - cosine_d).powi(5);
+        bsdf = bsdf + lobes.diffuse_weight * (diffuse + sheen);
+        pdf += lobes.probabilities[0] * wi.z / PI;
+
+        let specular_d_g = lobes.distribution.d(h) * lobes.distribution.g2(wo, wi) / (4.0 * wo.z * wi.z);
+        bsdf = bsdf + schlick(lobes.specular_f0, wo.dot(&h)) * specular_d_g;
+        pdf += lobes.probabilities[1] * lobes.distribution.visible_normal_pdf(wo, h) / (4.0 * wo.dot(&h));
+
+        if lobes.clearcoat > 0.0 {
+            let coat = Ggx::isotropic(CLEARCOAT_ROUGHNESS);
+            let coat_d_g = coat.d(h) * coat.g2(wo, wi) / (4.0 * wo.z * wi.z);
+            bsdf = bsdf + 0.25 * lobes.clearcoat * schlick(Vec3::new(CLEARCOAT_F0, CLEARCOAT_F0, CLEARCOAT_F0), wo.dot(&h)) * coat_d_g;
+            pdf += lobes.probabilities[2] * coat.visible_normal_pdf(wo, h) / (4.0 * wo.dot(&h));
+        }
+        return (bsdf, pdf);
+    }
+}
+
+impl _Material for Principled {
+    // Choose one of the lobes and sample a direction from it, and then weight the direction by the
+    // BSDF and density of all of the lobes together, so that the lobes are blended smoothly.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let lobes = self.lobes(ray_in, hit);
+        let wo = lobes.frame.to_local(-ray_in.direction.unit_vector());
+        if wo.z <= 0.0 { return None }
+
+        let u = sampler.get_1d();
+        let (direction, lobe) = if u < lobes.probabilities[0] {
+            let direction = lobes.frame.n + sample_unit_sphere(sampler.get_2d());
+            (if direction.squared_length() < 1e-12 { lobes.frame.n } else { direction }, Lobe::Diffuse)
+        }
+        else if u < lobes.probabilities[0] + lobes.probabilities[1] + lobes.probabilities[2] {
+            let coat = u >= lobes.probabilities[0] + lobes.probabilities[1];
+            let distribution = if coat { Ggx::isotropic(CLEARCOAT_ROUGHNESS) } else { lobes.distribution };
+            let m = distribution.sample_visible_normal(wo, sampler.get_2d());
+            (lobes.frame.to_world(2.0 * wo.dot(&m) * m - wo), Lobe::Specular)
+        }
+        else {
+            let scatter = lobes.dielectric.scatter(ray_in, hit, sampler)?;
+            (scatter.ray.direction, scatter.lobe)
+        };
+
+        let (bsdf, pdf) = self.evaluate(&lobes, ray_in, hit, direction);
+        if pdf <= 0.0 { return None }
+        let cosine = direction.unit_vector().dot(&hit.normal).abs();
+        let ray = Ray { origin: hit.p, direction, wavelengths: ray_in.wavelengths };
+        return Some(Scatter { ray, lobe, attenuation: ray_in.wavelengths.spectrum(bsdf * cosine / pdf) });
+    }
+    fn albedo(&self, hit: &HitRecord) -> Vec3 { self.base_colour.value(hit) }
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let lobes = self.lobes(ray_in, hit);
+        return self.evaluate(&lobes, ray_in, hit, direction).1;
+    }
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let lobes = self.lobes(ray_in, hit);
+        return self.evaluate(&lobes, ray_in, hit, direction).0;
+    }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::stat_tests::{average_attenuation, check_scattering_matches_pdf, hit, integrate_bsdf, SphereHistogram};
+
+    fn principled_materials() -> Vec<Material> {
+        vec![
+            Material::principled(Principled::default()),
+            Material::principled(Principled { metallic: Texture::scalar(1.0), roughness: Texture::scalar(0.3), ..Principled::default() }),
+            Material::principled(Principled { sheen: Texture::scalar(1.0), clearcoat: Texture::scalar(1.0), roughness: Texture::scalar(0.8), ..Principled::default() }),
+            Material::principled(Principled { anisotropy: Texture::scalar(0.8), specular_tint: Texture::scalar(1.0), ..Principled::default() }),
+            Material::principled(Principled { transmission: Texture::scalar(0.7), roughness: Texture::scalar(0.3), ..Principled::default() }),
+        ]
+    }
+
+    #[test]
+    fn test_principled_scattering_matches_its_pdf() {
+        for material in principled_materials().iter() {
+            check_scattering_matches_pdf(material, SphereHistogram { theta_bins: 40, phi_bins: 80 });
+        }
+    }
+
+    #[test]
+    fn test_principled_scattering_leaves_what_its_bsdf_does() {
+        for material in principled_materials().iter() {
+            let (ray, hit) = hit(material);
+            let scattered = average_attenuation(material, &ray, &hit);
+            let bsdf = integrate_bsdf(material, &ray, &hit);
+            assert!((scattered - bsdf).length() < 0.02, "{:?} scatters {} but its bsdf leaves {}", material, scattered, bsdf);
+        }
+    }
+
+    #[test]
+    fn test_principled_base_colour_follows_its_texture() {
+        let (white, black) = (Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0));
+        let material = Material::principled(Principled { base_colour: Texture::checker(white, black, 2.0), ..Principled::default() });
+        let (ray, hit) = hit(&material);
+        let direction = Vec3::new(0.0, 1.0, 1.0);
+        let even = HitRecord { uv: (0.1, 0.1), ..hit };
+        let odd = HitRecord { uv: (0.6, 0.1), ..hit };
+        assert_eq!(material.albedo(&even), white);
+        assert_eq!(material.albedo(&odd), black);
+        // Only the specular reflection is left on the black squares.
+        assert!(material.bsdf(&ray, &even, direction).x > 10.0 * material.bsdf(&ray, &odd, direction).x);
+        assert!(material.bsdf(&ray, &odd, direction).x > 0.0);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::vec3::Vec3;
+use crate::material::{_Material, Lobe, Scatter};
+use crate::material::dielectric::Dielectric;
+use crate::material::microfacet::{Frame, Ggx};
+use crate::ray::Ray;
+use crate::hitable::HitRecord;
+use crate::sampler::Sampler;
+
+// Fraction of unpolarised light reflected at an interface, arriving at an angle with the given
+// cosine to the normal, where eta is the ratio of the refractive index on the far side to that
+// on the near side. Returns 1 under total internal reflection.
+pub fn fresnel_dielectric(cosine: f64, eta: f64) -> f64 {
+    let sin_squared_t = (1.0 - cosine * cosine).max(0.0) / (eta * eta);
+    if sin_squared_t >= 1.0 { return 1.0 }
+    let cos_t = (1.0 - sin_squared_t).sqrt();
+    let rs = (cosine - eta * cos_t) / (cosine + eta * cos_t);
+    let rp = (eta * cosine - cos_t) / (eta * cosine + cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+// Glass with a rough surface, such as frosted glass or ice, which reflects and refracts light off
+// GGX distributed microfacets following Walter et al. Each microfacet is a smooth interface, so
+// the light is split between reflection and refraction by its Fresnel reflectance.
+//
+// Like Dielectric, refraction doesn't scale the light by the squared ratio of the refractive
+// indices, since the scaling cancels out for light that enters the glass and then leaves it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RoughDielectric {
+    pub refractive_index: f64,
+    pub distribution: Ggx,
+}
+
+// The geometry of an interaction in the local frame, where the normal faces the direction wo that
+// the light leaves in, along with the refractive indices on that side and on the other.
+struct Local {
+    frame: Frame,
+    wo: Vec3,
+    eta_o: f64,
+    eta_t: f64,
+}
+
+impl RoughDielectric {
+
+    fn local(&self, ray_in: &Ray, hit: &HitRecord) -> Local {
+        let entering = ray_in.direction.dot(&hit.normal) < 0.0;
+        let frame = Frame::from_tangent(if entering { hit.normal } else { -hit.normal }, hit.dpdu);
+        let (eta_o, eta_t) = if entering { (1.0, self.refractive_index) } else { (self.refractive_index, 1.0) };
+        return Local { frame, wo: frame.to_local(-ray_in.direction.unit_vector()), eta_o, eta_t };
+    }
+
+    // The microfacet normal that would scatter wo into wi, facing wo's side, and whether the
+    // scattering is a refraction.
+    fn half_vector(local: &Local, wi: Vec3) -> (Vec3, bool) {
+        let refracted = wi.z < 0.0;
+        let m = if refracted { -(local.eta_o * local.wo + local.eta_t * wi) } else { local.wo + wi };
+        let m = m.unit_vector();
+        return (if m.z < 0.0 { -m } else { m }, refracted);
+    }
+
+    // The smooth dielectric with the same refractive index, which integrators that can't handle
+    // rough surfaces fall back to.
+    pub fn smooth(&self) -> Dielectric {
+        Dielectric { refractive_index: self.refractive_index, dispersion: None, absorption: Vec3::new(0.0, 0.0, 0.0) }
+    }
+}
+
+impl _Material for RoughDielectric {
+    // Sample a microfacet normal from those visible from ray_in, and then reflect or refract off it
+    // in proportion to its Fresnel reflectance. Either way the light left is the fraction of
+    // microfacets that don't shadow the scattered ray.
+    fn scatter(&self, ray_in: &Ray, hit: &HitRecord, sampler: &mut Sampler) -> Option<Scatter> {
+        let u = sampler.get_1d();
+        let local = self.local(ray_in, hit);
+        let wo = local.wo;
+        if wo.z <= 0.0 { return None }
+
+        let m = if self.distribution.is_smooth() { Vec3::new(0.0, 0.0, 1.0) } else { self.distribution.sample_visible_normal(wo, sampler.get_2d()) };
+        let cosine = wo.dot(&m);
+        let (wi, lobe) = if u < fresnel_dielectric(cosine, local.eta_t / local.eta_o) {
+            let wi = 2.0 * cosine * m - wo;
+            if wi.z <= 0.0 { return None }
+            (wi, Lobe::Specular)
+        }
+        else {
+            let eta = local.eta_o / local.eta_t;
+            let wi = (eta * cosine - (1.0 - eta * eta * (1.0 - cosine * cosine)).sqrt()) * m - eta * wo;
+            if wi.z >= 0.0 { return None }
+            (wi, Lobe::Transmission)
+        };
+        let attenuation = if self.distribution.is_smooth() { 1.0 } else { self.distribution.g2(wo, wi) / self.distribution.g1(wo) };
+        let ray = Ray { origin: hit.p, direction: local.frame.to_world(wi), wavelengths: ray_in.wavelengths };
+        return Some(Scatter { ray, lobe, attenuation: Vec3::new(attenuation, attenuation, attenuation) });
+    }
+    fn albedo(&self, _hit: &HitRecord) -> Vec3 { Vec3 { x: 1.0, y: 1.0, z: 1.0} }
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        if self.distribution.is_smooth() { return 0.0 }
+        let local = self.local(ray_in, hit);
+        let wi = local.frame.to_local(direction.unit_vector());
+        if local.wo.z <= 0.0 || wi.z == 0.0 { return 0.0 }
+
+        let (m, refracted) = RoughDielectric::half_vector(&local, wi);
+        let (cosine_o, cosine_i) = (local.wo.dot(&m), wi.dot(&m));
+        let fresnel = fresnel_dielectric(cosine_o, local.eta_t / local.eta_o);
+        // The light has to arrive at the front of the microfacet, and refract through it rather
+        // than leave from its back.
+        if cosine_o <= 0.0 || (refracted && cosine_i >= 0.0) { return 0.0 }
+        let pdf_m = self.distribution.visible_normal_pdf(local.wo, m);
+        if !refracted { return fresnel * pdf_m / (4.0 * cosine_o) }
+        let denominator = local.eta_o * cosine_o + local.eta_t * cosine_i;
+        return (1.0 - fresnel) * pdf_m * local.eta_t * local.eta_t * cosine_i.abs() / (denominator * denominator);
+    }
+    fn bsdf(&self, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        if self.distribution.is_smooth() { return black }
+        let local = self.local(ray_in, hit);
+        let wo = local.wo;
+        let wi = local.frame.to_local(direction.unit_vector());
+        if wo.z <= 0.0 || wi.z == 0.0 { return black }
+
+        let (m, refracted) = RoughDielectric::half_vector(&local, wi);
+        let (cosine_o, cosine_i) = (wo.dot(&m), wi.dot(&m));
+        let fresnel = fresnel_dielectric(cosine_o, local.eta_t / local.eta_o);
+        if cosine_o <= 0.0 || (refracted && cosine_i >= 0.0) { return black }
+        let d_g = self.distribution.d(m) * self.distribution.g2(wo, wi);
+        let value = if !refracted { fresnel * d_g / (4.0 * wo.z * wi.z) }
+        else {
+            let denominator = local.eta_o * cosine_o + local.eta_t * cosine_i;
+            let value = cosine_o * cosine_i.abs() * local.eta_t * local.eta_t * (1.0 - fresnel) * d_g;
+            value / (wo.z * wi.z.abs() * denominator * denominator)
+        };
+        return Vec3::new(value, value, value);
+    }
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Vec3 { Vec3::new(0.0, 0.0, 0.0) }
+    fn is_delta(&self) -> bool { self.distribution.is_smooth() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::sampler::bsdf_dimension;
+    use crate::stat_tests::{average_attenuation, chi_square_test, hit, integrate_bsdf, scattered_directions, SphereHistogram, SAMPLES, SIGNIFICANCE};
+
+    // The hit from hit(), but by a ray leaving the material from inside it.
+    fn hit_from_inside(material: &Material) -> (Ray, HitRecord<'_>) {
+        let (ray, hit) = hit(material);
+        (Ray { origin: Vec3::new(-1.0, 0.0, -1.0), direction: Vec3::new(1.0, 0.0, 1.0), ..ray }, hit)
+    }
+
+    #[test]
+    fn test_rough_dielectric_scattering_matches_its_pdf() {
+        let histogram = SphereHistogram { theta_bins: 40, phi_bins: 80 };
+        for roughness in [0.1, 0.5].iter() {
+            let material = Material::rough_dielectric(1.5, *roughness);
+            for (ray, hit) in [hit(&material), hit_from_inside(&material)].iter() {
+                let mut sampler = Sampler::independent(1);
+                let directions: Vec<Vec3> = (0..SAMPLES).filter_map(|i| {
+                    sampler.start_pixel_sample(0, 0, i as u32);
+                    sampler.start_dimension(bsdf_dimension(0));
+                    material.scatter(ray, hit, &mut sampler).map(|scatter| scatter.ray.direction)
+                }).collect();
+                // Both reflection and refraction happen.
+                assert!(directions.iter().any(|d| d.z > 0.0) && directions.iter().any(|d| d.z < 0.0));
+                let observed = histogram.observed(&directions);
+                let expected = histogram.expected(SAMPLES, |direction| material.pdf(ray, hit, direction));
+                if let Err(message) = chi_square_test(&observed, &expected, SIGNIFICANCE) {
+                    panic!("{:?} doesn't scatter {:?} according to its pdf: {}", material, ray.direction, message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rough_dielectric_scattering_leaves_what_its_bsdf_does() {
+        for roughness in [0.1, 0.5].iter() {
+            let material = Material::rough_dielectric(1.5, *roughness);
+            for (ray, hit) in [hit(&material), hit_from_inside(&material)].iter() {
+                let scattered = average_attenuation(&material, ray, hit);
+                let bsdf = integrate_bsdf(&material, ray, hit);
+                assert!((scattered - bsdf).length() < 0.01, "{:?} scatters {} but its bsdf leaves {}", material, scattered, bsdf);
+                // Light that would scatter between microfacets more than once is lost, which is
+                // only a little for fairly smooth glass.
+                assert!(scattered.x <= 1.0 && (*roughness > 0.1 || scattered.x > 0.9), "{:?} scatters {}", material, scattered);
+            }
+        }
+    }
+
+    #[test]
+    fn test_smooth_rough_dielectric_matches_dielectric_fresnel() {
+        let material = Material::rough_dielectric(1.5, 0.0);
+        assert!(material.is_delta());
+        let directions = scattered_directions(&material);
+        let reflected = directions.iter().filter(|d| d.z > 0.0).count() as f64;
+        let probability = fresnel_dielectric(1.0 / 2.0_f64.sqrt(), 1.5);
+        let observed = [reflected, SAMPLES as f64 - reflected];
+        let expected = [probability * SAMPLES as f64, (1.0 - probability) * SAMPLES as f64];
+        assert!(chi_square_test(&observed, &expected, SIGNIFICANCE).is_ok(), "reflected {} of {} rays", reflected, SAMPLES);
+        // Refraction obeys Snell's law.
+        let refracted = directions.iter().find(|d| d.z < 0.0).unwrap().unit_vector();
+        assert!((refracted.x * 1.5 - 1.0 / 2.0_f64.sqrt()).abs() < 1e-9);
+    }
+}
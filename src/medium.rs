@@ -0,0 +1,111 @@
+use crate::hitable::HitRecord;
+use crate::ray::Ray;
+use crate::spectrum::Wavelengths;
+use crate::vec3::Vec3;
+
+// The inside of an object that absorbs the light passing through it, such as coloured glass. Light
+// travelling a distance d through it is attenuated by exp(-absorption d), by the Beer-Lambert law.
+//
+// Integrators keep track of the medium that a path is in, which is None in the clear space between
+// objects. Passing through a surface from the side its normal faces puts the path in the medium
+// inside the object, and passing back out puts it in clear space again, so every segment travelled
+// inside an object is absorbed, whatever surface ends it. Media aren't stacked, so a path that
+// leaves an object nested inside another is taken to be in clear space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Medium {
+    // Fraction of the light absorbed per unit distance, for each colour.
+    pub absorption: Vec3,
+}
+
+impl Medium {
+    // Fraction of the light at the given wavelengths that survives travelling distance through
+    // the medium.
+    pub fn transmittance(&self, wavelengths: Wavelengths, distance: f64) -> Vec3 {
+        let absorption = wavelengths.spectrum(self.absorption);
+        return Vec3::new((-absorption.x * distance).exp(), (-absorption.y * distance).exp(), (-absorption.z * distance).exp());
+    }
+}
+
+// Fraction of the light that survives travelling distance through the medium, if there is one.
+pub fn transmittance(medium: Option<Medium>, wavelengths: Wavelengths, distance: f64) -> Vec3 {
+    return match medium {
+        Some(medium) => medium.transmittance(wavelengths, distance),
+        None => Vec3::new(1.0, 1.0, 1.0),
+    };
+}
+
+// The medium that a path leaving the hit in direction travels through, given that it arrived along
+// ray_in through the medium current. Directions back to the side the path came from stay in the same
+// medium, while those through the surface enter the material's medium or leave it for clear space.
+pub fn medium_towards(current: Option<Medium>, ray_in: &Ray, hit: &HitRecord, direction: Vec3) -> Option<Medium> {
+    let arriving = ray_in.direction.dot(&hit.normal);
+    let leaving = direction.dot(&hit.normal);
+    if arriving * leaving <= 0.0 { return current }
+    return if arriving < 0.0 { hit.material.interior_medium() } else { None };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray { origin, direction, wavelengths: Wavelengths::Rgb }
+    }
+
+    fn hit(p: Vec3, t: f64, normal: Vec3, material: &Material) -> HitRecord<'_> {
+        HitRecord { t, p, normal, shading_normal: normal, uv: (0.0, 0.0), dpdu: Vec3::new(1.0, 0.0, 0.0), dpdv: Vec3::new(0.0, 1.0, 0.0), material, material_id: 0, object_id: 0 }
+    }
+
+    #[test]
+    fn test_paths_are_absorbed_over_the_whole_distance_inside_the_glass() {
+        // A ray enters the glass at z = 0 and travels 2 to an object embedded in it, which
+        // reflects it along x, and it travels 1 further to leave through a face at x = 1. All 3
+        // units are inside the glass.
+        let absorption = Vec3::new(0.5, 0.1, 0.8);
+        let glass = Material::absorbing_dielectric(1.5, absorption);
+        let embedded = Material::metal(0.5, 0.5, 0.5, 0.0);
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        let across = Vec3::new(1.0, 0.0, 0.0);
+
+        let entering = ray(Vec3::new(0.0, 0.0, 1.0), down);
+        let medium = medium_towards(None, &entering, &hit(Vec3::new(0.0, 0.0, 0.0), 1.0, -down, &glass), down);
+        assert_eq!(medium, Some(Medium { absorption }));
+
+        let inside = ray(Vec3::new(0.0, 0.0, 0.0), down);
+        let mut surviving = transmittance(medium, Wavelengths::Rgb, 2.0);
+        let tilted = Vec3::new(1.0, 0.0, 1.0).unit_vector();
+        let medium = medium_towards(medium, &inside, &hit(Vec3::new(0.0, 0.0, -2.0), 2.0, tilted, &embedded), across);
+        assert_eq!(medium, Some(Medium { absorption }));
+
+        let reflected = ray(Vec3::new(0.0, 0.0, -2.0), across);
+        surviving = surviving * transmittance(medium, Wavelengths::Rgb, 1.0);
+        let medium = medium_towards(medium, &reflected, &hit(Vec3::new(1.0, 0.0, -2.0), 1.0, across, &glass), across);
+        assert_eq!(medium, None);
+
+        let expected = Vec3::new((-1.5_f64).exp(), (-0.3_f64).exp(), (-2.4_f64).exp());
+        assert!((surviving - expected).length() < 1e-12, "{} isn't {}", surviving, expected);
+    }
+
+    #[test]
+    fn test_reflections_stay_in_the_medium_they_arrived_through() {
+        let glass = Material::absorbing_dielectric(1.5, Vec3::new(0.5, 0.5, 0.5));
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let outside = ray(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(medium_towards(None, &outside, &hit(Vec3::new(0.0, 0.0, 0.0), 1.0, up, &glass), up), None);
+
+        // Clear glass has no medium to enter.
+        let clear = Material::dielectric(1.5);
+        assert_eq!(medium_towards(None, &outside, &hit(Vec3::new(0.0, 0.0, 0.0), 1.0, up, &clear), -up), None);
+    }
+
+    #[test]
+    fn test_thick_glass_absorbs_more_than_thin_glass() {
+        let medium = Medium { absorption: Vec3::new(0.6, 0.05, 0.6) };
+        let thin = medium.transmittance(Wavelengths::Rgb, 0.1);
+        let thick = medium.transmittance(Wavelengths::Rgb, 2.0);
+        assert!(thick.x < thin.x && thick.y < thin.y && thick.z < thin.z);
+        // Green glass stays green, however thick it is.
+        assert!(thick.y > thick.x && thick.y > thick.z);
+    }
+}
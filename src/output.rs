@@ -0,0 +1,243 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::aov::AovPixel;
+use crate::film::Film;
+use crate::random::mix_bits;
+use crate::vec3::Vec3;
+
+fn component_value(v: f64) -> u8 { (v * 255.99) as u8 }
+
+// Apply simple square root gamma correction to a linear colour. Filters with negative lobes can
+// produce slightly negative values, which are clamped to zero.
+pub fn gamma_correct(colour: Vec3) -> Vec3 {
+    Vec3::new(colour.x.max(0.0).sqrt(), colour.y.max(0.0).sqrt(), colour.z.max(0.0).sqrt())
+}
+
+// Convert a set of values in the range [0, 1], stored bottom row first, into 8 bit RGBA data.
+pub fn encode_rgba(width: usize, values: &[Vec3]) -> Vec<u8> {
+    return values.chunks(width).rev().flatten().flat_map(|value| {
+        vec!(
+            component_value(value.r()),
+            component_value(value.g()),
+            component_value(value.b()),
+            255,
+        )
+    }).collect();
+}
+
+// Convert a set of linear colours, stored bottom row first, into gamma corrected 8 bit RGBA data.
+pub fn rgba_data(width: usize, pixels: &[Vec3]) -> Vec<u8> {
+    let corrected: Vec<Vec3> = pixels.iter().map(|p| gamma_correct(*p)).collect();
+    return encode_rgba(width, &corrected);
+}
+
+// Write 8 bit RGBA data to a PNG file.
+pub fn write_rgba_png(file_name: &str, width: usize, height: usize, data: &[u8]) -> std::io::Result<()> {
+    let png_file = File::create(file_name)?;
+    let w = BufWriter::new(png_file);
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Best);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(data)?;
+
+    Ok(())
+}
+
+// Write a set of linear colours, stored bottom row first, to a gamma corrected PNG file.
+pub fn write_image(file_name: &str, width: usize, height: usize, colours: &[Vec3]) -> std::io::Result<()> {
+    write_rgba_png(file_name, width, height, &rgba_data(width, colours))
+}
+
+// Read an 8 bit PNG file written by write_image, returning its width, height and linear colours
+// stored bottom row first. Greyscale images are expanded to RGB and alpha is ignored.
+pub fn read_png(file_name: &str) -> std::io::Result<(usize, usize, Vec<Vec3>)> {
+    let decoder = png::Decoder::new(File::open(file_name)?);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let samples = info.color_type.samples();
+    // Undo the square root gamma correction applied when writing.
+    let linear = |v: u8| (v as f64 / 255.0).powi(2);
+    let colours: Vec<Vec3> = data.chunks(info.line_size).rev().flat_map(|row| {
+        row[..width * samples].chunks(samples).map(|pixel| {
+            if samples < 3 { Vec3::new(linear(pixel[0]), linear(pixel[0]), linear(pixel[0])) }
+            else { Vec3::new(linear(pixel[0]), linear(pixel[1]), linear(pixel[2])) }
+        }).collect::<Vec<Vec3>>()
+    }).collect();
+
+    return Ok((width, height, colours));
+}
+
+// Write the averaged, gamma corrected contents of the film to a PNG file.
+pub fn write_png(film: &Film, file_name: &str) -> std::io::Result<()> {
+    let colours = film.colours();
+    write_image(file_name, film.width, film.height, &colours)
+}
+
+// Map a value between zero and one onto a black, red, yellow, white heat colour ramp.
+pub fn heat_colour(value: f64) -> Vec3 {
+    let v = value.clamp(0.0, 1.0) * 3.0;
+    Vec3::new(v.min(1.0), (v - 1.0).clamp(0.0, 1.0), (v - 2.0).clamp(0.0, 1.0))
+}
+
+// Write an image showing the number of samples taken for each pixel, scaled so that the pixel
+// with the most samples is white. This is useful for checking the behaviour of adaptive sampling.
+pub fn write_sample_heatmap(film: &Film, file_name: &str) -> std::io::Result<()> {
+    let maximum = film.pixels.iter().map(|p| p.samples).max().unwrap_or(0).max(1);
+    let colours: Vec<Vec3> = film.pixels.iter().map(|p| heat_colour(p.samples as f64 / maximum as f64)).collect();
+    write_rgba_png(file_name, film.width, film.height, &encode_rgba(film.width, &colours))
+}
+
+// Write values, stored bottom row first, to a little endian Portable Float Map, which keeps them
+// as 32 bit floats rather than quantising them to 8 bits. Like the film, PFM stores the bottom row
+// first. A single channel file holds the x component of each value.
+pub fn write_pfm(file_name: &str, width: usize, height: usize, values: &[Vec3], channels: usize) -> std::io::Result<()> {
+    let mut w = BufWriter::new(File::create(file_name)?);
+    write!(w, "{}\n{} {}\n-1.0\n", if channels == 1 { "Pf" } else { "PF" }, width, height)?;
+    for value in values {
+        let components = [value.x, value.y, value.z];
+        for component in &components[..channels] {
+            w.write_all(&(*component as f32).to_le_bytes())?;
+        }
+    }
+    return w.flush();
+}
+
+// AOVs written by write_aovs as floating point images of their raw values, for compositing.
+pub const DATA_AOV_NAMES: [&str; 4] = ["depth", "position", "normal", "albedo"];
+// AOVs written by write_aovs as PNG mattes, with a distinct colour for each ID.
+pub const ID_AOV_NAMES: [&str; 2] = ["object_id", "material_id"];
+
+// File name for an AOV image, formed by adding the name of the AOV to the main output file name,
+// so image.png gives image_depth.pfm and so on.
+pub fn aov_file_name(file_name: &str, aov: &str, extension: &str) -> String {
+    let path = Path::new(file_name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    return path.with_file_name(format!("{}_{}.{}", stem, aov, extension)).to_string_lossy().into_owned();
+}
+
+// Pick a distinct, saturated colour for an ID so that neighbouring objects are easy to tell apart.
+fn id_colour(id: Option<u32>) -> Vec3 {
+    match id {
+        None => Vec3::new(0.0, 0.0, 0.0),
+        Some(id) => {
+            let h = mix_bits(id as u64 + 1);
+            let channel = |shift: u64| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+            Vec3::new(channel(0), channel(8), channel(16))
+        }
+    }
+}
+
+// The raw values of one of the AOVs named in DATA_AOV_NAMES. Depth is the distance from the camera
+// and position is in world space. Pixels where every sample saw the background have infinite depth
+// and a zero position and normal.
+pub fn aov_data(aovs: &[AovPixel], aov: &str) -> Vec<Vec3> {
+    let zero = Vec3::new(0.0, 0.0, 0.0);
+    match aov {
+        "depth" => aovs.iter().map(|p| p.depth().map_or(f64::INFINITY, |d| d) * Vec3::new(1.0, 1.0, 1.0)).collect(),
+        "position" => aovs.iter().map(|p| p.position().unwrap_or(zero)).collect(),
+        "normal" => aovs.iter().map(|p| p.normal()).collect(),
+        "albedo" => aovs.iter().map(|p| p.albedo()).collect(),
+        _ => panic!("Unknown AOV {}", aov),
+    }
+}
+
+// Colours for one of the AOVs named in ID_AOV_NAMES. Background pixels are black.
+pub fn id_image(aovs: &[AovPixel], aov: &str) -> Vec<Vec3> {
+    match aov {
+        "object_id" => aovs.iter().map(|p| id_colour(p.object_id)).collect(),
+        "material_id" => aovs.iter().map(|p| id_colour(p.material_id)).collect(),
+        _ => panic!("Unknown AOV {}", aov),
+    }
+}
+
+// Write each of the film's AOVs to a separate file alongside file_name: a single channel PFM for
+// depth, three channel PFMs for the other data and PNGs for the IDs. Does nothing if the film
+// wasn't accumulating AOVs.
+pub fn write_aovs(film: &Film, file_name: &str) -> std::io::Result<()> {
+    if let Some(ref aovs) = film.aovs {
+        for aov in DATA_AOV_NAMES.iter() {
+            let channels = if *aov == "depth" { 1 } else { 3 };
+            write_pfm(&aov_file_name(file_name, aov, "pfm"), film.width, film.height, &aov_data(aovs, aov), channels)?;
+        }
+        for aov in ID_AOV_NAMES.iter() {
+            let values = id_image(aovs, aov);
+            write_rgba_png(&aov_file_name(file_name, aov, "png"), film.width, film.height, &encode_rgba(film.width, &values))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::AovSample;
+
+    #[test]
+    fn test_read_png_returns_the_colours_written_by_write_image() {
+        let colours = vec![
+            Vec3::new(0.0, 0.25, 1.0), Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.04, 0.09, 0.16),
+        ];
+        let file_name = std::env::temp_dir().join("raytracer_test_read_png.png").to_string_lossy().into_owned();
+        write_image(&file_name, 2, 2, &colours).unwrap();
+        let (width, height, read) = read_png(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert_eq!((width, height), (2, 2));
+        assert!(read.iter().zip(colours.iter()).all(|(a, b)| (*a - *b).length() < 0.01));
+    }
+
+    #[test]
+    fn test_aov_file_name_adds_the_aov_to_the_file_stem() {
+        assert_eq!(aov_file_name("image.png", "depth", "pfm"), "image_depth.pfm");
+        assert_eq!(aov_file_name("renders/scene.png", "object_id", "png"), "renders/scene_object_id.png");
+    }
+
+    #[test]
+    fn test_aov_data_is_not_normalised() {
+        let (mut near, mut far, background) = (AovPixel::new(), AovPixel::new(), AovPixel::new());
+        let sample = |depth: f64| AovSample {
+            depth,
+            position: Vec3::new(-depth, 2.0, 0.5),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            object_id: 0,
+            material_id: 0,
+        };
+        near.add_sample(Some(&sample(0.25)), Vec3::new(1.0, 1.0, 1.0));
+        far.add_sample(Some(&sample(1000.0)), Vec3::new(1.0, 1.0, 1.0));
+        let aovs = [near, far, background];
+
+        let depths: Vec<f64> = aov_data(&aovs, "depth").iter().map(|d| d.x).collect();
+        assert_eq!(depths, vec![0.25, 1000.0, f64::INFINITY]);
+        assert_eq!(aov_data(&aovs, "position"), vec![Vec3::new(-0.25, 2.0, 0.5), Vec3::new(-1000.0, 2.0, 0.5), Vec3::new(0.0, 0.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_write_pfm_keeps_the_raw_values() {
+        let values = vec![Vec3::new(1234.5, -0.001, 0.0), Vec3::new(f64::INFINITY, 2.0, 3.0)];
+        let file_name = std::env::temp_dir().join("raytracer_test_write_pfm.pfm").to_string_lossy().into_owned();
+        write_pfm(&file_name, 2, 1, &values, 3).unwrap();
+        let data = std::fs::read(&file_name).unwrap();
+        write_pfm(&file_name, 2, 1, &values, 1).unwrap();
+        let single = std::fs::read(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+
+        let header = b"PF\n2 1\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        let floats: Vec<f32> = data[header.len()..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        assert_eq!(floats, vec![1234.5, -0.001, 0.0, f32::INFINITY, 2.0, 3.0]);
+        assert_eq!(&single[..3], b"Pf\n");
+        assert_eq!(single.len(), header.len() + 2 * 4);
+    }
+}
@@ -0,0 +1,351 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+
+use crate::hitable::HitRecord;
+use crate::hitable::sphere::Sphere;
+use crate::integrator::NEAR_ZERO;
+use crate::ray::Ray;
+use crate::spectrum::Wavelengths;
+use crate::integrator::path::max_component;
+use crate::medium::{self, medium_towards};
+use crate::sampler::{bsdf_dimension, light_path_dimension, Sampler};
+use crate::sampling::{sample_unit_disk, sample_unit_sphere};
+use crate::scene::Scene;
+use crate::stats;
+use crate::vec3::Vec3;
+
+// Photon maps store light that has been traced from the lights and the background, so that the
+// light arriving at a point can be estimated from the density of the photons around it. Only
+// caustic photons are stored: those that reach a diffuse surface after passing through glass or
+// bouncing off mirrors. That light is very hard for the path tracer to find, whereas the rest of
+// the lighting is better handled by tracing paths from the camera.
+
+// Light arriving at a point on a surface.
+#[derive(Debug, Copy, Clone)]
+pub struct Photon {
+    pub p: Vec3,
+    // The normal of the surface that the photon landed on.
+    pub normal: Vec3,
+    // The direction the photon was travelling in.
+    pub direction: Vec3,
+    // The flux carried by the photon.
+    pub power: Vec3,
+}
+
+// Photons stored in a balanced kd-tree, so that the photons near a point can be found quickly. The
+// tree is implicit: within any range of the photons, the middle one splits the rest along the axis
+// stored for it, with the photons before it on the lower side and those after it on the upper side.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+    // The objects that photons from the background were aimed at.
+    pub background_targets: Vec<u32>,
+}
+
+fn coordinate(v: &Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+// Arrange the photons into a kd-tree, splitting each range along the axis where it is widest.
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 { return }
+    let (mut lower, mut upper) = (photons[0].p, photons[0].p);
+    for photon in photons.iter() {
+        lower = Vec3::new(lower.x.min(photon.p.x), lower.y.min(photon.p.y), lower.z.min(photon.p.z));
+        upper = Vec3::new(upper.x.max(photon.p.x), upper.y.max(photon.p.y), upper.z.max(photon.p.z));
+    }
+    let extent = upper - lower;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| coordinate(&a.p, axis).total_cmp(&coordinate(&b.p, axis)));
+    axes[middle] = axis;
+    let (photons_below, photons_above) = photons.split_at_mut(middle);
+    let (axes_below, axes_above) = axes.split_at_mut(middle);
+    build(photons_below, axes_below);
+    build(&mut photons_above[1..], &mut axes_above[1..]);
+}
+
+// A photon found by a nearest neighbour search, ordered by its distance so that the furthest of
+// those found so far is at the top of the heap.
+struct Neighbour {
+    distance_squared: f64,
+    index: usize,
+}
+
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance_squared == other.distance_squared
+    }
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_squared.total_cmp(&other.distance_squared)
+    }
+}
+
+impl PhotonMap {
+
+    pub fn new(mut photons: Vec<Photon>, background_targets: Vec<u32>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        return PhotonMap { photons, axes, background_targets };
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    // Call f with each photon within the radius of p.
+    pub fn within<F>(&self, p: Vec3, radius: f64, mut f: F) where F: FnMut(&Photon) {
+        self.search_within(0, self.photons.len(), p, radius * radius, &mut f);
+    }
+
+    fn search_within<F>(&self, start: usize, end: usize, p: Vec3, radius_squared: f64, f: &mut F) where F: FnMut(&Photon) {
+        if start >= end { return }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        let offset = coordinate(&p, self.axes[middle]) - coordinate(&photon.p, self.axes[middle]);
+        let (near, far) = if offset < 0.0 { ((start, middle), (middle + 1, end)) } else { ((middle + 1, end), (start, middle)) };
+
+        self.search_within(near.0, near.1, p, radius_squared, f);
+        if (photon.p - p).squared_length() <= radius_squared {
+            f(photon);
+        }
+        if offset * offset <= radius_squared {
+            self.search_within(far.0, far.1, p, radius_squared, f);
+        }
+    }
+
+    // The k photons nearest to p that are within the radius of it, along with the squared distance
+    // to the furthest of them. If fewer than k photons are within the radius, the squared radius
+    // is returned instead.
+    pub fn nearest(&self, p: Vec3, k: usize, radius: f64) -> (Vec<&Photon>, f64) {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        let mut radius_squared = radius * radius;
+        if k > 0 {
+            self.search_nearest(0, self.photons.len(), p, k, &mut radius_squared, &mut heap);
+        }
+        let photons = heap.into_iter().map(|neighbour| &self.photons[neighbour.index]).collect();
+        return (photons, radius_squared);
+    }
+
+    fn search_nearest(&self, start: usize, end: usize, p: Vec3, k: usize, radius_squared: &mut f64, heap: &mut BinaryHeap<Neighbour>) {
+        if start >= end { return }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        let offset = coordinate(&p, self.axes[middle]) - coordinate(&photon.p, self.axes[middle]);
+        let (near, far) = if offset < 0.0 { ((start, middle), (middle + 1, end)) } else { ((middle + 1, end), (start, middle)) };
+
+        self.search_nearest(near.0, near.1, p, k, radius_squared, heap);
+        let distance_squared = (photon.p - p).squared_length();
+        if distance_squared <= *radius_squared {
+            heap.push(Neighbour { distance_squared, index: middle });
+            if heap.len() > k {
+                heap.pop();
+            }
+            // Once k photons have been found, only closer ones are of interest.
+            if heap.len() == k {
+                *radius_squared = heap.peek().unwrap().distance_squared;
+            }
+        }
+        if offset * offset <= *radius_squared {
+            self.search_nearest(far.0, far.1, p, k, radius_squared, heap);
+        }
+    }
+}
+
+// Where photons are emitted from. Photons from the background are aimed at one of the mirror or
+// glass spheres, as those from any other direction can't form a caustic.
+struct PhotonSources<'a> {
+    // Chance of emitting each photon from the scene's lights rather than the background.
+    light_probability: f64,
+    // The spheres that photons from the background are aimed at, with their object ids.
+    targets: Vec<(&'a Sphere, u32)>,
+    // Sum of the squared radii of the targets, which are chosen in proportion to their area.
+    target_area: f64,
+}
+
+// Trace photons from the scene's lights and background, keeping the caustic photons. The photons
+// traced depend only on the scene and the pass, so each pass of a progressive render gets a
+// different set.
+pub fn trace_photons(scene: &Scene, count: usize, pass: u64, max_depth: u32) -> PhotonMap {
+    let targets = scene.delta_spheres();
+    let target_area: f64 = targets.iter().map(|(sphere, _)| sphere.radius * sphere.radius).sum();
+
+    // Share the photons between the lights and the background in proportion to the power they
+    // send into the scene, so that all of the photons carry similar amounts of light. The power
+    // from the background is estimated from its colour along the axes.
+    let light_power: f64 = scene.lights.iter().map(|light| max_component(light.emission) * PI * light.area()).sum();
+    let axes = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+    let background_radiance: f64 = axes.iter()
+        .flat_map(|axis| [*axis, -*axis])
+        .map(|direction| max_component(scene.background.colour(&Ray { origin: Vec3::new(0.0, 0.0, 0.0), direction, wavelengths: Wavelengths::Rgb })))
+        .sum::<f64>() / 6.0;
+    let background_power = background_radiance * 4.0 * PI * PI * target_area;
+    let light_probability = if light_power + background_power > 0.0 { light_power / (light_power + background_power) } else { 0.0 };
+    let sources = PhotonSources { light_probability, targets, target_area };
+    let background_targets = if background_power > 0.0 { sources.targets.iter().map(|(_, object_id)| *object_id).collect() } else { vec![] };
+    if light_power + background_power == 0.0 {
+        return PhotonMap::new(vec![], background_targets);
+    }
+
+    let photons = (0..count).into_par_iter().filter_map(|index| {
+        let mut sampler = Sampler::independent(pass);
+        sampler.start_pixel_sample(index as u32, 0, 0);
+        let (r, hit, power) = emit_photon(scene, &sources, &mut sampler)?;
+        trace_photon(scene, r, hit, power / count as f64, max_depth, &mut sampler)
+    }).collect();
+    return PhotonMap::new(photons, background_targets);
+}
+
+// Choose where a photon starts and find what it hits first, returning the ray it follows, the hit
+// and the power it carries.
+fn emit_photon<'a>(scene: &'a Scene, sources: &PhotonSources, sampler: &mut Sampler) -> Option<(Ray, HitRecord<'a>, Vec3)> {
+    sampler.start_dimension(light_path_dimension(0));
+    let u = sampler.get_1d();
+    if u < sources.light_probability {
+        let light = scene.choose_light(u / sources.light_probability)?;
+        let point = light.sample_point(sampler.get_2d());
+        let direction = point.normal + sample_unit_sphere(sampler.get_2d());
+        if point.normal.dot(&direction) <= 0.0 { return None }
+
+        // Directions are chosen in proportion to the cosine, so the power is the light's total
+        // flux divided by the chance of choosing it.
+        let power = light.emission * PI * light.area() / (sources.light_probability * scene.light_selection_pdf());
+        let r = Ray { origin: point.p, direction, wavelengths: Wavelengths::Rgb };
+        stats::record_secondary_ray();
+        let hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX)?;
+        return Some((r, hit, power));
+    }
+
+    // Choose a target in proportion to its area as seen from any direction, then a direction and a
+    // point on the disk facing that direction through the target's centre.
+    let mut choice = (u - sources.light_probability) / (1.0 - sources.light_probability) * sources.target_area;
+    let (sphere, object_id) = *sources.targets.iter()
+        .find(|(sphere, _)| {
+            choice -= sphere.radius * sphere.radius;
+            choice < 0.0
+        })
+        .unwrap_or(sources.targets.last()?);
+    let direction = sample_unit_sphere(sampler.get_2d());
+    let disk = sample_unit_disk(sampler.get_2d());
+    let a = if direction.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let b = direction.cross(&a).unit_vector();
+    let a = b.cross(&direction);
+    let offset = sphere.radius * (disk.x * a + disk.y * b);
+    let entry = sphere.centre + offset - (sphere.radius * sphere.radius - offset.squared_length()).max(0.0).sqrt() * direction;
+
+    // The photon only arrives if nothing else blocks the background from the target.
+    stats::record_secondary_ray();
+    if scene.world.hit(&Ray { origin: entry, direction: -direction, wavelengths: Wavelengths::Rgb }, NEAR_ZERO, f64::MAX).is_some() {
+        return None;
+    }
+    let r = Ray { origin: entry - direction, direction, wavelengths: Wavelengths::Rgb };
+    stats::record_secondary_ray();
+    let hit = scene.world.hit(&r, 0.0, f64::MAX).filter(|hit| hit.object_id == object_id)?;
+
+    // The chance of choosing a point on the disk is 1 / (pi r^2), that of the direction
+    // 1 / (4 pi) and that of the target r^2 / target_area.
+    let pdf = (1.0 - sources.light_probability) / (4.0 * PI * PI * sources.target_area);
+    let power = scene.background.colour(&Ray { origin: entry, direction: -direction, wavelengths: Wavelengths::Rgb }) / pdf;
+    return Some((r, hit, power));
+}
+
+// Follow a photon through mirrors and glass, returning it where it lands on the first other
+// surface. Photons that don't pass through any mirrors or glass aren't caustics, so they're
+// discarded, as are those that leave the scene. Photons start in clear space, and are absorbed along
+// the way through coloured glass.
+fn trace_photon(scene: &Scene, mut r: Ray, first_hit: HitRecord, mut power: Vec3, max_depth: u32, sampler: &mut Sampler) -> Option<Photon> {
+    let mut hit = first_hit;
+    let mut medium = None;
+    for bounce in 0..max_depth {
+        if !hit.material.is_delta() {
+            if bounce == 0 || hit.material.is_emissive() { return None }
+            return Some(Photon { p: hit.p, normal: hit.normal, direction: r.direction.unit_vector(), power });
+        }
+        sampler.start_dimension(light_path_dimension(bsdf_dimension(bounce)));
+        let scatter = hit.material.scatter(&r, &hit, sampler)?;
+        power = power * scatter.attenuation;
+        medium = medium_towards(medium, &r, &hit, scatter.ray.direction);
+        r = scatter.ray;
+        stats::record_secondary_ray();
+        hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX)?;
+        power = power * medium::transmittance(medium, r.wavelengths, hit.t * r.direction.length());
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    fn random_photons(count: usize) -> Vec<Photon> {
+        let mut rng = Rng::new(7);
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        (0..count).map(|i| Photon {
+            p: Vec3::new(rng.next_f64(), rng.next_f64(), 0.1 * rng.next_f64()),
+            normal: zero,
+            direction: zero,
+            power: Vec3::new(i as f64, 0.0, 0.0),
+        }).collect()
+    }
+
+    #[test]
+    fn test_photons_within_a_radius_match_a_brute_force_search() {
+        let photons = random_photons(1000);
+        let map = PhotonMap::new(photons.clone(), vec![]);
+        for p in [Vec3::new(0.5, 0.5, 0.05), Vec3::new(0.0, 1.0, 0.0), Vec3::new(2.0, 2.0, 2.0)] {
+            let mut found = vec![];
+            map.within(p, 0.2, |photon| found.push(photon.power.x as usize));
+            found.sort();
+            let expected: Vec<usize> = photons.iter().enumerate()
+                .filter(|(_, photon)| (photon.p - p).length() <= 0.2)
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_nearest_photons_match_a_brute_force_search() {
+        let photons = random_photons(1000);
+        let map = PhotonMap::new(photons.clone(), vec![]);
+        let p = Vec3::new(0.3, 0.6, 0.0);
+        let mut distances: Vec<f64> = photons.iter().map(|photon| (photon.p - p).squared_length()).collect();
+        distances.sort_by(|a, b| a.total_cmp(b));
+
+        let (nearest, radius_squared) = map.nearest(p, 10, 1.0);
+        let mut found: Vec<f64> = nearest.iter().map(|photon| (photon.p - p).squared_length()).collect();
+        found.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(found, distances[..10].to_vec());
+        assert_eq!(radius_squared, distances[9]);
+
+        // With a small radius, fewer photons are found and the radius is kept.
+        let radius = 0.5 * (distances[3].sqrt() + distances[4].sqrt());
+        let (nearest, radius_squared) = map.nearest(p, 10, radius);
+        assert_eq!(nearest.len(), 4);
+        assert_eq!(radius_squared, radius * radius);
+    }
+}
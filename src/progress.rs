@@ -0,0 +1,261 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::film::Bounds;
+use crate::render::PassSummary;
+
+// Reporting of render progress. The renderer calls a ProgressReporter as passes start and finish
+// and as each tile is completed, which lets applications embedding the renderer display progress
+// however they like. Tiles are rendered in parallel, so tile_completed may be called from any of
+// the worker threads.
+
+// How far through the current pass a render is.
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    pub pass: u64,
+    pub tiles_completed: usize,
+    pub total_tiles: usize,
+    // Time since the pass started.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+
+    pub fn fraction(&self) -> f64 {
+        return if self.total_tiles == 0 { 1.0 }
+        else { self.tiles_completed as f64 / self.total_tiles as f64 };
+    }
+
+    // Estimate the time left in the pass by assuming the remaining tiles take as long on average
+    // as those completed so far. None until the first tile is complete.
+    pub fn estimated_remaining(&self) -> Option<Duration> {
+        if self.tiles_completed == 0 { return None }
+        let remaining_tiles = (self.total_tiles - self.tiles_completed) as f64;
+        return Some(self.elapsed.mul_f64(remaining_tiles / self.tiles_completed as f64));
+    }
+}
+
+pub trait ProgressReporter: Sync {
+    fn pass_started(&self, _pass: u64, _total_tiles: usize) {}
+
+    fn tile_completed(&self, _bounds: &Bounds, _progress: &Progress) {}
+
+    // Only called by progressive renders, once the pass has been merged into the film.
+    fn pass_completed(&self, _summary: &PassSummary) {}
+
+    // Checked before each pixel is rendered. Once this returns true the remaining pixels are
+    // skipped and the render stops after the current pass, leaving the samples taken so far in
+    // the film.
+    fn is_cancelled(&self) -> bool { false }
+}
+
+// A flag shared between threads that is used to cancel a render, for example from a signal
+// handler. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+
+    pub fn new() -> CancelToken {
+        CancelToken { cancelled: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// Forwards progress to another reporter, and cancels the render once the token is cancelled.
+pub struct CancellableProgress<'a> {
+    reporter: &'a dyn ProgressReporter,
+    token: CancelToken,
+}
+
+impl<'a> CancellableProgress<'a> {
+    pub fn new(reporter: &'a dyn ProgressReporter, token: CancelToken) -> CancellableProgress<'a> {
+        CancellableProgress { reporter, token }
+    }
+}
+
+impl<'a> ProgressReporter for CancellableProgress<'a> {
+
+    fn pass_started(&self, pass: u64, total_tiles: usize) {
+        self.reporter.pass_started(pass, total_tiles);
+    }
+
+    fn tile_completed(&self, bounds: &Bounds, progress: &Progress) {
+        self.reporter.tile_completed(bounds, progress);
+    }
+
+    fn pass_completed(&self, summary: &PassSummary) {
+        self.reporter.pass_completed(summary);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled() || self.reporter.is_cancelled()
+    }
+}
+
+// Reports nothing.
+pub struct QuietProgress;
+
+impl ProgressReporter for QuietProgress {}
+
+// Format a duration as minutes and seconds, or hours, minutes and seconds.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    return if seconds >= 3600 { format!("{}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60) }
+    else { format!("{}:{:02}", seconds / 60, seconds % 60) };
+}
+
+const BAR_WIDTH: usize = 30;
+
+// Draws a progress bar with an estimate of the time remaining on standard error, redrawing it in
+// place as tiles complete.
+pub struct TerminalProgress {
+    // The last percentage drawn, so that the bar is only redrawn when it changes.
+    last_percent: Mutex<Option<u64>>,
+}
+
+impl TerminalProgress {
+    pub fn new() -> TerminalProgress {
+        TerminalProgress { last_percent: Mutex::new(None) }
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self { TerminalProgress::new() }
+}
+
+impl ProgressReporter for TerminalProgress {
+
+    fn pass_started(&self, _pass: u64, _total_tiles: usize) {
+        *self.last_percent.lock().unwrap() = None;
+    }
+
+    fn tile_completed(&self, _bounds: &Bounds, progress: &Progress) {
+        let percent = (progress.fraction() * 100.0).floor() as u64;
+        let mut last_percent = self.last_percent.lock().unwrap();
+        if *last_percent == Some(percent) { return }
+        *last_percent = Some(percent);
+
+        let filled = (progress.fraction() * BAR_WIDTH as f64).round() as usize;
+        let eta = progress.estimated_remaining().map_or(String::from("--:--"), format_duration);
+        let mut stderr = std::io::stderr();
+        // Ignore errors, as failing to draw the progress bar shouldn't stop the render.
+        let _ = write!(
+            stderr, "\rPass {} [{}{}] {:>3}% {}/{} tiles, ETA {} ",
+            progress.pass, "#".repeat(filled), " ".repeat(BAR_WIDTH - filled), percent,
+            progress.tiles_completed, progress.total_tiles, eta
+        );
+        if progress.tiles_completed == progress.total_tiles {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+
+    fn pass_completed(&self, summary: &PassSummary) {
+        eprintln!(
+            "Pass {} complete: {} samples per pixel, noise {:.4}, {:.1}s elapsed",
+            summary.pass, summary.samples_per_pixel, summary.noise, summary.elapsed.as_secs_f64()
+        );
+    }
+}
+
+// Writes each event as a line of JSON, which suits logs and other programs monitoring a render.
+pub struct JsonLinesProgress<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesProgress<W> {
+
+    pub fn new(writer: W) -> JsonLinesProgress<W> {
+        JsonLinesProgress { writer: Mutex::new(writer) }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+
+    fn write_line(&self, line: String) {
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", line);
+        let _ = writer.flush();
+    }
+}
+
+impl<W: Write + Send> ProgressReporter for JsonLinesProgress<W> {
+
+    fn pass_started(&self, pass: u64, total_tiles: usize) {
+        self.write_line(format!(r#"{{"event":"pass_started","pass":{},"total_tiles":{}}}"#, pass, total_tiles));
+    }
+
+    fn tile_completed(&self, bounds: &Bounds, progress: &Progress) {
+        let eta = progress.estimated_remaining().map_or(String::from("null"), |eta| format!("{:.3}", eta.as_secs_f64()));
+        self.write_line(format!(
+            r#"{{"event":"tile_completed","pass":{},"x0":{},"y0":{},"x1":{},"y1":{},"tiles_completed":{},"total_tiles":{},"elapsed":{:.3},"eta":{}}}"#,
+            progress.pass, bounds.x0, bounds.y0, bounds.x1, bounds.y1,
+            progress.tiles_completed, progress.total_tiles, progress.elapsed.as_secs_f64(), eta
+        ));
+    }
+
+    fn pass_completed(&self, summary: &PassSummary) {
+        self.write_line(format!(
+            r#"{{"event":"pass_completed","pass":{},"samples_per_pixel":{},"noise":{:.6},"elapsed":{:.3}}}"#,
+            summary.pass, summary.samples_per_pixel, summary.noise, summary.elapsed.as_secs_f64()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(tiles_completed: usize) -> Progress {
+        Progress { pass: 1, tiles_completed, total_tiles: 10, elapsed: Duration::from_secs(4) }
+    }
+
+    #[test]
+    fn test_estimated_remaining_assumes_tiles_take_the_same_time() {
+        assert_eq!(progress(0).estimated_remaining(), None);
+        assert_eq!(progress(2).estimated_remaining(), Some(Duration::from_secs(16)));
+        assert_eq!(progress(10).estimated_remaining(), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_cancellable_progress_is_cancelled_by_its_token() {
+        let token = CancelToken::new();
+        let progress = CancellableProgress::new(&QuietProgress, token.clone());
+        assert!(!progress.is_cancelled());
+        token.cancel();
+        assert!(progress.is_cancelled());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(75)), "1:15");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+
+    #[test]
+    fn test_json_lines_progress_writes_one_line_per_event() {
+        let reporter = JsonLinesProgress::new(vec![]);
+        reporter.pass_started(1, 10);
+        reporter.tile_completed(&Bounds { x0: 0, y0: 0, x1: 16, y1: 16 }, &progress(1));
+        let output = String::from_utf8(reporter.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"event":"pass_started","pass":1,"total_tiles":10}"#);
+        assert!(lines[1].starts_with(r#"{"event":"tile_completed","pass":1,"x0":0,"y0":0,"x1":16,"y1":16,"#));
+        assert!(lines[1].ends_with(r#""eta":36.000}"#));
+    }
+}
@@ -0,0 +1,125 @@
+// Small, seedable random number generator and hashing utilities.
+// Renders need to be reproducible given a seed, regardless of how work is scheduled across
+// threads, so rather than relying on thread local generators each pixel sample seeds its own
+// generator from a hash of the pixel coordinates and sample index.
+
+// The PCG32 generator described by O'Neill in "PCG: A Family of Simple Fast Space-Efficient
+// Statistically Good Algorithms for Random Number Generation".
+#[derive(Debug, Copy, Clone)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+const PCG_DEFAULT_INCREMENT: u64 = 1442695040888963407;
+
+impl Rng {
+
+    pub fn new(seed: u64) -> Rng {
+        let mut rng = Rng { state: 0, increment: PCG_DEFAULT_INCREMENT };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        return rng;
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rotation = (old_state >> 59) as u32;
+        return xor_shifted.rotate_right(rotation);
+    }
+
+    // Uniformly distributed value in [0, 1) with 53 bits of precision.
+    pub fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 21) ^ (self.next_u32() as u64 >> 11);
+        return bits as f64 / (1u64 << 53) as f64;
+    }
+}
+
+// Finalisation step of the SplitMix64 generator, used to scramble the bits of a value.
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    return v;
+}
+
+// Combine a number of values into a single well mixed hash.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, v| mix_bits(h ^ mix_bits(*v)))
+}
+
+// Return the element at index i of a random permutation of 0..length, where the permutation is
+// chosen by the seed. From Kensler, "Correlated Multi-Jittered Sampling".
+pub fn permutation_element(i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let p = seed;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length { break; }
+    }
+
+    return i.wrapping_add(p) % length;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_deterministic_for_a_given_seed() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let first: Vec<u32> = (0..8).map(|_| a.next_u32()).collect();
+        assert_eq!(first, (0..8).map(|_| b.next_u32()).collect::<Vec<u32>>());
+        assert_ne!(first, (0..8).map(|_| c.next_u32()).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_next_f64_is_in_the_unit_interval() {
+        let mut rng = Rng::new(7);
+        let values: Vec<f64> = (0..10000).map(|_| rng.next_f64()).collect();
+        assert!(values.iter().all(|v| *v >= 0.0 && *v < 1.0));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_permutation_element_produces_a_permutation() {
+        for length in [1, 2, 7, 16, 100].iter() {
+            let mut elements: Vec<u32> = (0..*length).map(|i| permutation_element(i, *length, 1234)).collect();
+            elements.sort();
+            assert_eq!(elements, (0..*length).collect::<Vec<u32>>());
+        }
+    }
+}
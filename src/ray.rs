@@ -1,3 +1,4 @@
+use crate::spectrum::Wavelengths;
 use crate::vec3::Vec3;
 
 // Class representing a ray from a given origin that travels in a given direction.
@@ -5,6 +6,8 @@ use crate::vec3::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // The wavelengths of the light that the ray carries, which rays scattered from it share.
+    pub wavelengths: Wavelengths,
 }
 
 impl Ray {
@@ -22,7 +25,7 @@ mod tests {
     fn test_point_at_parameter() {
         let origin = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
         let direction = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
-        let ray = Ray { origin, direction };
+        let ray = Ray { origin, direction, wavelengths: Wavelengths::Rgb };
         assert_eq!(ray.point_at_parameter(2.0), Vec3 { x: 2.0, y: 4.0, z: 6.0 })
     }
 }
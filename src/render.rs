@@ -0,0 +1,707 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::aov::AovSample;
+use crate::denoise::Denoiser;
+use crate::film::{Bounds, Film, FilmTile, Pixel};
+use crate::filter::Filter;
+use crate::hitable::HitRecord;
+use crate::progress::{Progress, ProgressReporter};
+use crate::ray::Ray;
+use crate::integrator::metropolis::{Bootstrap, Metropolis};
+use crate::integrator::{Integrator, PassState, Splat, NEAR_ZERO};
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::stats::{self, RenderStats};
+use crate::vec3::Vec3;
+
+// The result of tracing a path from the camera.
+pub struct PathSample<'a> {
+    pub colour: Vec3,
+    // The first surface hit by the camera ray, if any.
+    pub first_hit: Option<HitRecord<'a>>,
+    // Light contributed to other points of the image.
+    pub splats: Vec<Splat>,
+}
+
+// Trace a ray from the camera with the given integrator, keeping the first hit so that it can be
+// used for AOVs.
+pub fn trace<'a>(r: Ray, scene: &'a Scene, integrator: &Integrator, pass: &PassState, sampler: &mut Sampler) -> PathSample<'a> {
+    stats::record_primary_ray();
+    let first_hit = scene.world.hit(&r, NEAR_ZERO, f64::MAX);
+    let mut splats = vec![];
+    let colour = integrator.radiance(r, first_hit, scene, pass, sampler, &mut splats);
+    return PathSample { colour, first_hit, splats };
+}
+
+// Compute a linear blend between white and blue depending on the value of the y coordinate.
+pub fn background_colour(ray: &Ray) -> Vec3 {
+    let unit_direction = ray.direction.unit_vector();
+    let t = 0.5 * (unit_direction.y + 1.0);
+    (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+}
+
+// The light seen by rays that don't hit anything in the world.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Background {
+    // The blend from white to blue given by background_colour.
+    Sky,
+    // The same colour in every direction. A uniform white background is useful for testing that
+    // materials conserve energy, since a white object should then be invisible.
+    Uniform(Vec3),
+}
+
+impl Background {
+    // The light seen along the ray, at the wavelengths it carries.
+    pub fn colour(&self, ray: &Ray) -> Vec3 {
+        let colour = match *self {
+            Background::Sky => background_colour(ray),
+            Background::Uniform(colour) => colour,
+        };
+        return ray.wavelengths.spectrum(colour);
+    }
+}
+
+// Settings for adaptive sampling. Every pixel receives at least min_samples, after which pixels
+// whose estimated error (see Pixel::error) is below the threshold stop receiving samples. No pixel
+// receives more than max_samples.
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveSettings {
+    pub min_samples: u64,
+    pub max_samples: u64,
+    pub threshold: f64,
+}
+
+impl AdaptiveSettings {
+    pub fn is_converged(&self, pixel: &Pixel) -> bool {
+        pixel.samples >= self.max_samples ||
+            (pixel.samples >= self.min_samples && pixel.error() <= self.threshold)
+    }
+}
+
+// Settings that apply to every pass of a render.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    // Generates the random numbers used for each sample. Each tile is rendered with a copy of this.
+    pub sampler: Sampler,
+    // Reconstruction filter used to weight samples when accumulating them into pixels.
+    pub filter: Filter,
+    // When set, pixels that have converged stop receiving samples.
+    pub adaptive: Option<AdaptiveSettings>,
+    // When set, the final image is denoised. This needs a film with AOVs.
+    pub denoiser: Option<Denoiser>,
+    // Computes the colour seen along each camera ray.
+    pub integrator: Integrator,
+    // Whether to trace light at sampled wavelengths rather than as RGB. See spectrum.rs.
+    pub spectral: bool,
+}
+
+impl RenderSettings {
+    // The colour of each pixel of the film, denoised if a denoiser has been chosen.
+    pub fn image(&self, film: &Film) -> Vec<Vec3> {
+        match self.denoiser {
+            Some(denoiser) => denoiser.denoise(film),
+            None => film.colours(),
+        }
+    }
+}
+
+// Width and height of the tiles that a pass is split into.
+const TILE_SIZE: usize = 16;
+
+// Render the pixels of a single tile, returning the tile and the statistics for rendering it. If
+// the render is cancelled part way through, the pixels rendered so far are returned.
+fn render_tile(scene: &Scene, film: &Film, settings: &RenderSettings, pass: &PassState, samples: u64, bounds: Bounds, progress: &dyn ProgressReporter) -> (FilmTile, RenderStats) {
+    let start = Instant::now();
+    // Discard anything recorded on this thread outside of rendering a tile.
+    stats::take_counters();
+
+    let mut tile = film.tile(bounds, settings.filter);
+    let mut sampler = settings.sampler.clone();
+
+    'pixels: for j in bounds.y0..bounds.y1 {
+        for i in bounds.x0..bounds.x1 {
+            if progress.is_cancelled() {
+                break 'pixels;
+            }
+            let pixel = film.pixel(i, j);
+            let pixel_samples = match settings.adaptive {
+                Some(adaptive) if adaptive.is_converged(pixel) => 0,
+                Some(adaptive) => samples.min(adaptive.max_samples - pixel.samples),
+                None => samples,
+            };
+            for index in pixel.samples..pixel.samples + pixel_samples {
+                // Continue the pixel's sample sequence from where the previous pass left off.
+                sampler.start_pixel_sample(i as u32, j as u32, index as u32);
+                let camera_sample = sampler.camera_sample();
+                let x = i as f64 + camera_sample.pixel.0;
+                let y = j as f64 + camera_sample.pixel.1;
+                let wavelengths = if settings.spectral { sampler.wavelengths() } else { Wavelengths::Rgb };
+                let r = Ray { wavelengths, ..scene.camera.get_ray(x / film.width as f64, y / film.height as f64, camera_sample.lens) };
+                let sample = trace(r, scene, &settings.integrator, pass, &mut sampler);
+                tile.add_sample(i, j, x, y, wavelengths.to_rgb(sample.colour));
+                for splat in sample.splats.iter() {
+                    tile.add_splat(splat.s * film.width as f64, splat.t * film.height as f64, wavelengths.to_rgb(splat.colour));
+                }
+                if tile.has_aovs() {
+                    let aov = sample.first_hit.map(|hit| AovSample::from_hit(&hit, hit.t * r.direction.length()));
+                    tile.add_aov_sample(i, j, aov.as_ref(), scene.background.colour(&Ray { wavelengths: Wavelengths::Rgb, ..r }));
+                }
+            }
+        }
+    }
+
+    let mut tile_stats = RenderStats { counters: stats::take_counters(), ..RenderStats::default() };
+    tile_stats.add_thread_time(rayon::current_thread_index().unwrap_or(0), start.elapsed());
+    return (tile, tile_stats);
+}
+
+// Run one of the Markov chains of a Metropolis light transport pass for a number of mutations,
+// returning a tile holding its splats and the statistics for running it.
+fn render_chain(scene: &Scene, film: &Film, metropolis: &Metropolis, bootstrap: &Bootstrap, chain: u32, mutations: u64, progress: &dyn ProgressReporter) -> (FilmTile, RenderStats) {
+    let start = Instant::now();
+    stats::take_counters();
+
+    let mut tile = film.tile(Bounds { x0: 0, y0: 0, x1: 0, y1: 0 }, Filter::box_filter(0.5));
+    match metropolis.start_chain(scene, bootstrap, chain) {
+        Some(mut markov_chain) => {
+            let mut splats = vec![];
+            for _ in 0..mutations {
+                if progress.is_cancelled() {
+                    break;
+                }
+                metropolis.mutate(scene, bootstrap, &mut markov_chain, &mut splats);
+                for splat in splats.drain(..) {
+                    tile.add_splat(splat.s * film.width as f64, splat.t * film.height as f64, splat.colour);
+                }
+                tile.add_splat_samples(1);
+            }
+        }
+        // Every path is black, as is the image.
+        None => tile.add_splat_samples(mutations),
+    }
+
+    let mut chain_stats = RenderStats { counters: stats::take_counters(), ..RenderStats::default() };
+    chain_stats.add_thread_time(rayon::current_thread_index().unwrap_or(0), start.elapsed());
+    return (tile, chain_stats);
+}
+
+// Render a pass of Metropolis light transport, sharing samples mutations per pixel between the
+// chains, which are reported as the pass's tiles. Chains are run a batch at a time, so that only
+// the splats of one batch are held at once, and merged in order so that the image doesn't depend
+// on the number of threads.
+fn render_chains(scene: &Scene, film: &mut Film, settings: &RenderSettings, metropolis: &Metropolis, samples: u64, pass: u64, progress: &dyn ProgressReporter) -> RenderStats {
+    let start = Instant::now();
+    let chains = metropolis.metropolis.chains.max(1);
+    let completed = AtomicUsize::new(0);
+    progress.pass_started(pass, chains as usize);
+
+    let bootstrap = metropolis.bootstrap(scene, settings.sampler.clone().seed(), pass, settings.spectral);
+    let mutations = samples * film.pixels.len() as u64;
+    let whole_film = Bounds { x0: 0, y0: 0, x1: film.width, y1: film.height };
+    let mut pass_stats = RenderStats::default();
+    let indices: Vec<u32> = (0..chains).collect();
+    for batch in indices.chunks(rayon::current_num_threads()) {
+        let rendered: Vec<Option<(FilmTile, RenderStats)>> = batch.par_iter().map(|chain| {
+            if progress.is_cancelled() {
+                return None;
+            }
+            let chain_mutations = mutations / chains as u64 + if (*chain as u64) < mutations % chains as u64 { 1 } else { 0 };
+            let result = render_chain(scene, film, metropolis, &bootstrap, *chain, chain_mutations, progress);
+            let tiles_completed = completed.fetch_add(1, Ordering::Relaxed) + 1;
+            progress.tile_completed(&whole_film, &Progress { pass, tiles_completed, total_tiles: chains as usize, elapsed: start.elapsed() });
+            Some(result)
+        }).collect();
+
+        for (tile, tile_stats) in rendered.iter().flatten() {
+            film.merge_tile(tile);
+            pass_stats.merge(tile_stats);
+        }
+    }
+    pass_stats.elapsed = start.elapsed();
+    return pass_stats;
+}
+
+// Render the tiles of a pass in parallel, reporting progress as each one finishes. Metropolis
+// light transport renders the whole film at once, so it runs its chains instead.
+fn render_tiles(scene: &Scene, film: &mut Film, settings: &RenderSettings, samples: u64, pass: u64, progress: &dyn ProgressReporter) -> RenderStats {
+    if let Integrator::Metropolis(ref metropolis) = settings.integrator {
+        return render_chains(scene, film, settings, metropolis, samples, pass, progress);
+    }
+    let start = Instant::now();
+    let tiles = film.tiles(TILE_SIZE);
+    let completed = AtomicUsize::new(0);
+    progress.pass_started(pass, tiles.len());
+    let pass_state = settings.integrator.start_pass(scene, pass);
+
+    let rendered: Vec<Option<(FilmTile, RenderStats)>> = tiles.par_iter().map(|bounds| {
+        if progress.is_cancelled() {
+            return None;
+        }
+        let result = render_tile(scene, film, settings, &pass_state, samples, *bounds, progress);
+        let tiles_completed = completed.fetch_add(1, Ordering::Relaxed) + 1;
+        progress.tile_completed(bounds, &Progress { pass, tiles_completed, total_tiles: tiles.len(), elapsed: start.elapsed() });
+        Some(result)
+    }).collect();
+
+    let mut pass_stats = RenderStats::default();
+    for (tile, tile_stats) in rendered.iter().flatten() {
+        film.merge_tile(tile);
+        pass_stats.merge(tile_stats);
+    }
+    pass_stats.elapsed = start.elapsed();
+    return pass_stats;
+}
+
+// Add a number of samples to every pixel in the film. Each sample is taken with a random offset
+// within the pixel so that the accumulated image is antialiased.
+// The film is split into tiles that are rendered in parallel, with progress reported as each one
+// finishes. If the reporter cancels the render, the remaining pixels are skipped, and the film
+// holds the samples taken so far, with the number of samples in each pixel. Returns the statistics
+// for the pass, where the number of primary rays is the number of samples taken.
+pub fn render_pass(scene: &Scene, film: &mut Film, settings: &RenderSettings, samples: u64, progress: &dyn ProgressReporter) -> RenderStats {
+    render_tiles(scene, film, settings, samples, 1, progress)
+}
+
+// Controls when a progressive render stops. Whichever limit is reached first ends the render.
+#[derive(Debug, Copy, Clone)]
+pub struct ProgressiveSettings {
+    // Wall clock time to spend rendering. No further passes are started once this has elapsed
+    // or if the next pass is expected to overrun it.
+    pub time_budget: Option<Duration>,
+    // Maximum number of samples per pixel.
+    pub target_samples: u64,
+    // Stop once the average pixel error reported by Film::noise drops below this value.
+    pub noise_threshold: Option<f64>,
+    pub samples_per_pass: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    TimeBudget,
+    TargetSamples,
+    NoiseThreshold,
+    // Every pixel has converged under adaptive sampling.
+    Converged,
+    // The progress reporter cancelled the render.
+    Cancelled,
+}
+
+// Summary of the state of a progressive render, reported after each pass.
+#[derive(Debug, Clone)]
+pub struct PassSummary {
+    pub pass: u64,
+    // Samples per pixel taken by the passes that rendered every pixel.
+    pub samples_per_pixel: u64,
+    pub elapsed: Duration,
+    pub noise: f64,
+    // Statistics for all of the passes so far.
+    pub stats: RenderStats,
+}
+
+// Render successive passes over the whole image until one of the limits in settings is reached.
+// on_pass is called with the accumulated film after each pass, which allows callers to write out
+// intermediate images.
+pub fn render_progressive<F>(scene: &Scene, film: &mut Film, settings: &RenderSettings, progressive: &ProgressiveSettings, progress: &dyn ProgressReporter, mut on_pass: F) -> StopReason
+    where F: FnMut(&Film, &PassSummary)
+{
+    let start = Instant::now();
+    let mut samples_per_pixel = 0;
+    let mut pass = 0;
+    let mut total_stats = RenderStats::default();
+
+    loop {
+        let pass_start = Instant::now();
+        let samples = progressive.samples_per_pass.min(progressive.target_samples - samples_per_pixel);
+
+        pass += 1;
+        let pass_stats = render_tiles(scene, film, settings, samples, pass, progress);
+        total_stats.merge(&pass_stats);
+
+        // A cancelled pass skips some of the pixels, so its samples are only counted once they've
+        // been taken everywhere.
+        if !progress.is_cancelled() {
+            samples_per_pixel += samples;
+        }
+
+        let summary = PassSummary { pass, samples_per_pixel, elapsed: start.elapsed(), noise: film.noise(), stats: total_stats.clone() };
+        progress.pass_completed(&summary);
+        on_pass(film, &summary);
+
+        if progress.is_cancelled() {
+            return StopReason::Cancelled;
+        }
+
+        if pass_stats.counters.primary_rays == 0 {
+            return StopReason::Converged;
+        }
+        if samples_per_pixel >= progressive.target_samples {
+            return StopReason::TargetSamples;
+        }
+        if progressive.noise_threshold.is_some_and(|threshold| summary.noise <= threshold) {
+            return StopReason::NoiseThreshold;
+        }
+        if let Some(budget) = progressive.time_budget {
+            if summary.elapsed + pass_start.elapsed() > budget {
+                return StopReason::TimeBudget;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hitable::Hitable;
+    use crate::progress::{CancelToken, CancellableProgress, QuietProgress};
+    use std::sync::Mutex;
+
+    // Records the progress reported, optionally cancelling the render after a number of tiles.
+    struct RecordingProgress {
+        cancel_after_tiles: usize,
+        tiles: AtomicUsize,
+        passes: Mutex<Vec<(u64, usize)>>,
+    }
+
+    impl RecordingProgress {
+        fn new(cancel_after_tiles: usize) -> RecordingProgress {
+            RecordingProgress { cancel_after_tiles, tiles: AtomicUsize::new(0), passes: Mutex::new(vec![]) }
+        }
+    }
+
+    impl ProgressReporter for RecordingProgress {
+        fn pass_started(&self, pass: u64, total_tiles: usize) {
+            self.passes.lock().unwrap().push((pass, total_tiles));
+        }
+        fn tile_completed(&self, _bounds: &Bounds, progress: &Progress) {
+            assert!(progress.tiles_completed <= progress.total_tiles);
+            self.tiles.fetch_add(1, Ordering::SeqCst);
+        }
+        fn is_cancelled(&self) -> bool {
+            self.tiles.load(Ordering::SeqCst) >= self.cancel_after_tiles
+        }
+    }
+    use crate::integrator::path::PathSettings;
+    use crate::material::Material;
+
+    fn test_scene() -> Scene {
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::lambertian(0.5, 0.5, 0.5)),
+        ]);
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0
+        );
+        Scene::new(world, camera)
+    }
+
+    fn render_settings() -> RenderSettings {
+        RenderSettings { sampler: Sampler::independent(0), filter: Filter::box_filter(0.5), adaptive: None, denoiser: None, integrator: Integrator::path(PathSettings::default()), spectral: false }
+    }
+
+    fn progressive_settings() -> ProgressiveSettings {
+        ProgressiveSettings { time_budget: None, target_samples: 8, noise_threshold: None, samples_per_pass: 3 }
+    }
+
+    #[test]
+    fn test_render_progressive_stops_at_the_target_sample_count() {
+        let scene = test_scene();
+        let mut film = Film::new(4, 4);
+        let mut passes = vec![];
+        let reason = render_progressive(&scene, &mut film, &render_settings(), &progressive_settings(), &QuietProgress, |_, summary| passes.push(summary.samples_per_pixel));
+
+        assert_eq!(reason, StopReason::TargetSamples);
+        assert_eq!(passes, vec![3, 6, 8]);
+        assert!(film.pixels.iter().all(|p| p.samples == 8));
+    }
+
+    #[test]
+    fn test_render_progressive_stops_at_the_noise_threshold() {
+        let scene = test_scene();
+        let mut film = Film::new(4, 4);
+        let progressive = ProgressiveSettings { noise_threshold: Some(f64::INFINITY), ..progressive_settings() };
+        let reason = render_progressive(&scene, &mut film, &render_settings(), &progressive, &QuietProgress, |_, _| {});
+
+        assert_eq!(reason, StopReason::NoiseThreshold);
+        assert_eq!(film.total_samples(), 3 * 16);
+    }
+
+    #[test]
+    fn test_render_progressive_stops_when_the_time_budget_is_exhausted() {
+        let scene = test_scene();
+        let mut film = Film::new(4, 4);
+        let progressive = ProgressiveSettings { time_budget: Some(Duration::from_secs(0)), ..progressive_settings() };
+        let reason = render_progressive(&scene, &mut film, &render_settings(), &progressive, &QuietProgress, |_, _| {});
+
+        assert_eq!(reason, StopReason::TimeBudget);
+        assert_eq!(film.total_samples(), 3 * 16);
+    }
+
+    #[test]
+    fn test_adaptive_sampling_stops_sampling_converged_pixels() {
+        let scene = test_scene();
+        let mut film = Film::new(8, 8);
+        let adaptive = AdaptiveSettings { min_samples: 4, max_samples: 64, threshold: 0.05 };
+        let settings = RenderSettings { adaptive: Some(adaptive), ..render_settings() };
+        let progressive = ProgressiveSettings { target_samples: 64, samples_per_pass: 4, ..progressive_settings() };
+        let reason = render_progressive(&scene, &mut film, &settings, &progressive, &QuietProgress, |_, _| {});
+
+        assert_eq!(reason, StopReason::Converged);
+        assert!(film.pixels.iter().all(|p| adaptive.is_converged(p)));
+        // Pixels that only see the sky converge immediately, while those on the edge of the
+        // diffuse sphere need more samples.
+        let samples: Vec<u64> = film.pixels.iter().map(|p| p.samples).collect();
+        assert_eq!(samples.iter().min(), Some(&4));
+        assert!(samples.iter().max().unwrap() > &4);
+    }
+
+    #[test]
+    fn test_render_pass_is_deterministic_for_a_given_sampler_seed() {
+        let scene = test_scene();
+        let render = |seed| {
+            let mut film = Film::new(4, 4);
+            let settings = RenderSettings { sampler: Sampler::sobol(seed), ..render_settings() };
+            render_pass(&scene, &mut film, &settings, 4, &QuietProgress);
+            film.pixels.iter().map(|p| p.colour()).collect::<Vec<Vec3>>()
+        };
+        assert!(render(1) == render(1));
+        assert!(render(1) != render(2));
+    }
+
+    #[test]
+    fn test_spectral_render_agrees_with_rgb_render() {
+        let scene = test_scene();
+        let render = |spectral| {
+            let mut film = Film::new(4, 4);
+            let settings = RenderSettings { spectral, ..render_settings() };
+            render_pass(&scene, &mut film, &settings, 1024, &QuietProgress);
+            film.colours()
+        };
+        let (rgb, spectral) = (render(false), render(true));
+        for (c, s) in rgb.iter().zip(spectral.iter()) {
+            assert!((*s - *c).length() < 0.05 * c.length(), "{} in the spectral render, {} in the RGB render", s, c);
+        }
+    }
+
+    #[test]
+    fn test_render_pass_with_a_box_filter_averages_the_samples_in_each_pixel() {
+        let scene = test_scene();
+        let mut film = Film::new(20, 20);
+        render_pass(&scene, &mut film, &render_settings(), 4, &QuietProgress);
+        assert!(film.pixels.iter().all(|p| (p.colour() - p.average()).length() < 1e-12));
+    }
+
+    #[test]
+    fn test_render_pass_accumulates_aovs_for_films_that_have_them() {
+        let scene = test_scene();
+        let mut film = Film::with_aovs(8, 8);
+        render_pass(&scene, &mut film, &render_settings(), 2, &QuietProgress);
+        let aovs = film.aovs.as_ref().unwrap();
+
+        // The centre of the image looks straight at the sphere, whose nearest point is at a
+        // distance of 0.5, while the corners only see the sky.
+        let centre = &aovs[4 * 8 + 4];
+        assert!((centre.depth().unwrap() - 0.5).abs() < 0.05);
+        assert!(centre.normal().z > 0.9);
+        assert_eq!(centre.object_id, Some(0));
+        assert_eq!(aovs[0].depth(), None);
+        assert!(aovs.iter().all(|p| p.samples == 2));
+    }
+
+    // Render a sphere made of the given material in a uniform white environment, returning the
+    // colour of the pixels that see the sphere.
+    fn furnace(material: Material, samples: u64) -> Vec<Vec3> {
+        let world = Hitable::hitable_list(vec![Hitable::sphere(Vec3::new(0.0, 0.0, -1.0), 0.5, material)]);
+        let scene = Scene { background: Background::Uniform(Vec3::new(1.0, 1.0, 1.0)), ..Scene::new(world, test_scene().camera) };
+        let mut film = Film::with_aovs(16, 16);
+        render_pass(&scene, &mut film, &render_settings(), samples, &QuietProgress);
+
+        let aovs = film.aovs.as_ref().unwrap();
+        return film.pixels.iter().zip(aovs.iter()).filter(|(_, aov)| aov.hits == aov.samples).map(|(p, _)| p.colour()).collect();
+    }
+
+    // Trace paths from the origin through a sphere made of the given material in a uniform white
+    // environment, returning the average light they carry back. Each bounce is weighted by
+    // bsdf * |cos| / pdf, worked out from the material's BSDF and density rather than taken from the
+    // attenuation it scatters with, so a BSDF that doesn't match its sampling shows up as light
+    // gained or lost. Delta materials have no density, so their bounces keep the attenuation.
+    fn furnace_through_bsdf(material: Material, paths: u32) -> Vec3 {
+        let world = Hitable::hitable_list(vec![Hitable::sphere(Vec3::new(0.0, 0.0, -1.0), 0.5, material)]);
+        let scene = Scene { background: Background::Uniform(Vec3::new(1.0, 1.0, 1.0)), ..Scene::new(world, test_scene().camera) };
+        let mut sampler = Sampler::independent(1);
+        let mut total = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..paths {
+            sampler.start_pixel_sample(0, 0, i);
+            // Directions within this square all hit the sphere, which subtends 30 degrees.
+            let (u, v) = sampler.get_2d();
+            let mut r = Ray { origin: Vec3::new(0.0, 0.0, 0.0), direction: Vec3::new(0.6 * u - 0.3, 0.6 * v - 0.3, -1.0), wavelengths: Wavelengths::Rgb };
+            let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+            for bounce in 0..50 {
+                let hit = match scene.world.hit(&r, NEAR_ZERO, f64::MAX) {
+                    Some(hit) => hit,
+                    None => {
+                        total = total + throughput * scene.background.colour(&r);
+                        break;
+                    }
+                };
+                sampler.start_dimension(crate::sampler::bsdf_dimension(bounce));
+                let scatter = match hit.material.scatter(&r, &hit, &mut sampler) {
+                    Some(scatter) => scatter,
+                    None => break,
+                };
+                let weight = if hit.material.is_delta() { scatter.attenuation }
+                else {
+                    let direction = scatter.ray.direction;
+                    let pdf = hit.material.pdf(&r, &hit, direction);
+                    let cosine = direction.unit_vector().dot(&hit.normal).abs();
+                    if pdf > 0.0 { hit.material.bsdf(&r, &hit, direction) * cosine / pdf } else { Vec3::new(0.0, 0.0, 0.0) }
+                };
+                throughput = throughput * weight;
+                r = scatter.ray;
+            }
+        }
+        return total / paths as f64;
+    }
+
+    #[test]
+    fn test_white_objects_are_invisible_in_a_white_furnace() {
+        // A rough dielectric only models light scattering once off its microfacets, so it loses the
+        // little that would bounce between them. It must never gain light, though.
+        let materials = [
+            (Material::lambertian(1.0, 1.0, 1.0), 1e-3),
+            (Material::metal(1.0, 1.0, 1.0, 0.0), 1e-3),
+            (Material::metal(1.0, 1.0, 1.0, 0.5), 1e-3),
+            (Material::metal(1.0, 1.0, 1.0, 1.0), 1e-3),
+            (Material::dielectric(1.5), 1e-3),
+            (Material::rough_dielectric(1.5, 0.05), 0.02),
+        ];
+        for (material, tolerance) in materials.iter() {
+            let colour = furnace_through_bsdf(material.clone(), 4096);
+            for c in [colour.x, colour.y, colour.z] {
+                assert!(c < 1.0 + 1e-3 && c > 1.0 - tolerance, "{:?} rendered as {}", material, colour);
+            }
+        }
+    }
+
+    #[test]
+    fn test_a_convex_diffuse_object_reflects_its_albedo_in_a_white_furnace() {
+        // Light scattered by a convex object can't hit it again, so exactly the albedo is reflected.
+        for colour in furnace(Material::lambertian(0.5, 0.25, 0.75), 16) {
+            assert!((colour - Vec3::new(0.5, 0.25, 0.75)).length() < 1e-3, "rendered as {}", colour);
+        }
+    }
+
+    // Render a grey sphere resting on a grey ground in a uniform white environment, where light
+    // bounces between the two surfaces, returning the average colour and the statistics.
+    fn render_with_path_settings(path: PathSettings) -> (Vec3, RenderStats) {
+        let grey = Material::lambertian(0.5, 0.5, 0.5);
+        let world = Hitable::hitable_list(vec![
+            Hitable::sphere(Vec3::new(0.0, 0.0, -1.0), 0.5, grey.clone()),
+            Hitable::sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, grey),
+        ]);
+        let scene = Scene { background: Background::Uniform(Vec3::new(1.0, 1.0, 1.0)), ..Scene::new(world, test_scene().camera) };
+        let settings = RenderSettings { integrator: Integrator::path(path), ..render_settings() };
+        let mut film = Film::new(16, 16);
+        let stats = render_pass(&scene, &mut film, &settings, 256, &QuietProgress);
+        let total = film.pixels.iter().fold(Vec3::new(0.0, 0.0, 0.0), |total, p| total + p.colour());
+        return (total / film.pixels.len() as f64, stats);
+    }
+
+    #[test]
+    fn test_russian_roulette_shortens_paths_without_changing_the_image() {
+        let (without, without_stats) = render_with_path_settings(PathSettings { min_bounces: 50, ..PathSettings::default() });
+        let (with, with_stats) = render_with_path_settings(PathSettings { min_bounces: 0, ..PathSettings::default() });
+
+        assert_eq!(without_stats.counters.absorbed, 0);
+        assert!(with_stats.counters.absorbed > 0);
+        assert!(with_stats.average_path_length() < without_stats.average_path_length());
+        assert!((with - without).length() < 0.01 * without.length(), "{} with Russian roulette, {} without", with, without);
+    }
+
+    #[test]
+    fn test_paths_stop_at_the_depth_limit_for_each_kind_of_bounce() {
+        let (_, stats) = render_with_path_settings(PathSettings { max_diffuse_depth: 1, ..PathSettings::default() });
+        // Only a single diffuse bounce is allowed, so each path traces at most two rays.
+        assert!(stats.counters.max_depth > 0);
+        assert!(stats.average_path_length() <= 2.0);
+
+        let (_, stats) = render_with_path_settings(PathSettings { max_specular_depth: 0, max_transmission_depth: 0, ..PathSettings::default() });
+        assert_eq!(stats.counters.max_depth, 0);
+    }
+
+    #[test]
+    fn test_render_pass_reports_statistics() {
+        let scene = test_scene();
+        let mut film = Film::new(4, 4);
+        let stats = render_pass(&scene, &mut film, &render_settings(), 2, &QuietProgress);
+        let counters = stats.counters;
+
+        assert_eq!(counters.primary_rays, 32);
+        // Every path ends in exactly one way.
+        assert_eq!(counters.escaped + counters.max_depth + counters.absorbed, 32);
+        // Each ray is tested against the single sphere.
+        assert_eq!(counters.intersection_tests, stats.total_rays());
+        assert!(stats.average_path_length() >= 1.0);
+        assert!(!stats.thread_times.is_empty());
+    }
+
+    #[test]
+    fn test_render_progressive_reports_each_pass_and_tile() {
+        let scene = test_scene();
+        let mut film = Film::new(40, 20);
+        let progress = RecordingProgress::new(usize::MAX);
+        render_progressive(&scene, &mut film, &render_settings(), &progressive_settings(), &progress, |_, _| {});
+
+        // The film is split into 3 by 2 tiles.
+        assert_eq!(*progress.passes.lock().unwrap(), vec![(1, 6), (2, 6), (3, 6)]);
+        assert_eq!(progress.tiles.load(Ordering::SeqCst), 18);
+    }
+
+    #[test]
+    fn test_cancelling_a_render_skips_the_remaining_tiles() {
+        let scene = test_scene();
+        let mut film = Film::new(64, 64);
+        let progress = RecordingProgress::new(3);
+        // How many tiles are already being rendered when the render is cancelled depends on the
+        // number of threads, so the render runs on two of them whatever machine runs the test.
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let mut reported = vec![];
+        let reason = pool.install(|| render_progressive(&scene, &mut film, &render_settings(), &progressive_settings(), &progress, |_, summary| {
+            reported.push(summary.samples_per_pixel);
+        }));
+
+        assert_eq!(reason, StopReason::Cancelled);
+        // The pass didn't reach every pixel, so none of its samples are counted.
+        assert_eq!(reported, vec![0]);
+        // The tile that the other thread is rendering when the third one finishes may finish too.
+        let tiles = progress.tiles.load(Ordering::SeqCst);
+        assert!((3..=4).contains(&tiles));
+        // Tiles that were being rendered when the render was cancelled may be incomplete, but
+        // every pixel either has all of the pass's samples or none of them.
+        let counts = film.sample_counts();
+        assert!(counts.iter().all(|n| *n == 0 || *n == 3));
+        assert!(counts.iter().filter(|n| **n == 3).count() >= 3 * 16 * 16);
+        assert!(counts.contains(&0));
+    }
+
+    #[test]
+    fn test_a_render_cancelled_before_it_starts_takes_no_samples() {
+        let scene = test_scene();
+        let mut film = Film::new(8, 8);
+        let token = CancelToken::new();
+        token.cancel();
+        let stats = render_pass(&scene, &mut film, &render_settings(), 4, &CancellableProgress::new(&QuietProgress, token));
+
+        assert_eq!(stats.counters.primary_rays, 0);
+        assert_eq!(film.total_samples(), 0);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::random::{mix_bits, permutation_element};
+use crate::sampler::{_Sampler, SampleState};
+
+// Bases used for each dimension of the Halton sequence. Dimensions beyond the end of the table
+// fall back to independent random samples.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+const MINIMUM_DIGIT_WEIGHT: f64 = 1.0 / (1u64 << 40) as f64;
+
+// Largest double less than one.
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Compute the radical inverse of index in the given base, randomly permuting each digit with a
+// permutation that depends on the digits that precede it. This is equivalent to Owen scrambling
+// and removes the correlation between dimensions with large bases.
+fn owen_scrambled_radical_inverse(base: u32, index: u64, seed: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_power = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let mut remaining = index;
+
+    // Generate enough digits for around 40 bits of precision, which is plenty for sampling and
+    // keeps the reversed digits well within range of a u64 for every base in the table.
+    while inverse_base_power > MINIMUM_DIGIT_WEIGHT {
+        let next = remaining / base as u64;
+        let digit = (remaining - next * base as u64) as u32;
+        let digit_seed = mix_bits(seed ^ reversed_digits) as u32;
+        let permuted_digit = permutation_element(digit, base, digit_seed);
+        reversed_digits = reversed_digits * base as u64 + permuted_digit as u64;
+        inverse_base_power *= inverse_base;
+        remaining = next;
+    }
+
+    return (inverse_base_power * reversed_digits as f64).min(ONE_MINUS_EPSILON);
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HaltonSampler {
+    pub(super) state: SampleState,
+}
+
+impl _Sampler for HaltonSampler {
+    fn state(&mut self) -> &mut SampleState { &mut self.state }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next_dimensions(1) as usize;
+        return if dimension < PRIMES.len() {
+            owen_scrambled_radical_inverse(PRIMES[dimension], self.state.index as u64, self.state.dimension_seed(dimension as u32))
+        }
+        else { self.state.rng(dimension as u32).next_f64() };
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radical_inverse_points_are_stratified() {
+        // The first nine points in base three should fall into distinct ninths of the interval.
+        let mut strata: Vec<u32> = (0..9).map(|i| (owen_scrambled_radical_inverse(3, i, 99) * 9.0) as u32).collect();
+        strata.sort();
+        assert_eq!(strata, (0..9).collect::<Vec<u32>>());
+    }
+}
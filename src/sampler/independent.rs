@@ -0,0 +1,21 @@
+use crate::sampler::{_Sampler, SampleState};
+
+#[derive(Debug, Copy, Clone)]
+pub struct IndependentSampler {
+    pub(super) state: SampleState,
+}
+
+impl _Sampler for IndependentSampler {
+    fn state(&mut self) -> &mut SampleState { &mut self.state }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next_dimensions(1);
+        return self.state.rng(dimension).next_f64();
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next_dimensions(2);
+        let mut rng = self.state.rng(dimension);
+        return (rng.next_f64(), rng.next_f64());
+    }
+}
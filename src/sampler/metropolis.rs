@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::random::Rng;
+use crate::sampler::{_Sampler, SampleState};
+
+// The largest f64 below 1, keeping wrapped values in [0, 1).
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// A coordinate of the current point in primary sample space, along with the value it had before
+// the current iteration so that rejected mutations can be undone.
+#[derive(Debug, Copy, Clone)]
+struct PrimarySample {
+    value: f64,
+    // The iteration in which the value was last changed.
+    last_modified: u64,
+    backup_value: f64,
+    backup_last_modified: u64,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.backup_value = self.value;
+        self.backup_last_modified = self.last_modified;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.backup_value;
+        self.last_modified = self.backup_last_modified;
+    }
+}
+
+// Samples for Kelemen et al.'s primary sample space Metropolis light transport. Rather than
+// generating new values for each sample, the sampler keeps a point in primary sample space, the
+// vector of all of the random numbers a path consumes, and each iteration mutates it. A large step
+// replaces every value with a new uniform random number, while a small step perturbs each one by a
+// normally distributed offset. Values are only mutated when they're used, catching up on the
+// mutations they missed, so paths only pay for the dimensions they use. Values are kept by
+// dimension in a map, as the dimensions used are spread far apart in the layout.
+#[derive(Debug, Clone)]
+pub struct MetropolisSampler {
+    pub(super) state: SampleState,
+    rng: Rng,
+    // Standard deviation of the offsets of small steps.
+    sigma: f64,
+    large_step_probability: f64,
+    samples: HashMap<u32, PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+}
+
+impl MetropolisSampler {
+
+    pub(super) fn new(seed: u64, sigma: f64, large_step_probability: f64) -> MetropolisSampler {
+        // The first iteration chooses the starting point of the chain, so it is a large step.
+        MetropolisSampler {
+            state: SampleState::new(seed),
+            rng: Rng::new(seed),
+            sigma,
+            large_step_probability,
+            samples: HashMap::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+        }
+    }
+
+    // Propose a new point by mutating the current one.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_f64() < self.large_step_probability;
+        self.state.dimension = 0;
+    }
+
+    // Make the proposed point the current one.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    // Return to the point before the proposal.
+    pub fn reject(&mut self) {
+        let iteration = self.iteration;
+        for sample in self.samples.values_mut().filter(|sample| sample.last_modified == iteration) {
+            sample.restore();
+        }
+        self.iteration -= 1;
+    }
+
+    fn value(&mut self, dimension: u32) -> f64 {
+        let (rng, iteration) = (&mut self.rng, self.iteration);
+        let sample = self.samples.entry(dimension).or_insert_with(|| {
+            // Dimensions that haven't been used before start from a uniform random value, as if
+            // they had been set by the previous iteration.
+            let value = rng.next_f64();
+            let last_modified = iteration.saturating_sub(1);
+            PrimarySample { value, last_modified, backup_value: value, backup_last_modified: last_modified }
+        });
+        if sample.last_modified == self.iteration {
+            return sample.value;
+        }
+
+        // A large step since the value was last used would have replaced it.
+        if sample.last_modified < self.last_large_step {
+            sample.value = self.rng.next_f64();
+            sample.last_modified = self.last_large_step;
+        }
+        sample.backup();
+        if self.large_step {
+            sample.value = self.rng.next_f64();
+        }
+        else {
+            // The sum of the small steps missed since the value was last used is normally
+            // distributed, with the variance of a single step times the number of steps.
+            let steps = (self.iteration - sample.last_modified) as f64;
+            let normal = (-2.0 * (1.0 - self.rng.next_f64()).ln()).sqrt() * (2.0 * PI * self.rng.next_f64()).cos();
+            let value = sample.value + normal * self.sigma * steps.sqrt();
+            sample.value = (value - value.floor()).min(ONE_MINUS_EPSILON);
+        }
+        sample.last_modified = self.iteration;
+        return sample.value;
+    }
+}
+
+impl _Sampler for MetropolisSampler {
+    fn state(&mut self) -> &mut SampleState { &mut self.state }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next_dimensions(1);
+        return self.value(dimension);
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next_dimensions(2);
+        return (self.value(dimension), self.value(dimension + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(sampler: &mut MetropolisSampler, count: u32) -> Vec<f64> {
+        sampler.state.dimension = 0;
+        (0..count).map(|_| sampler.get_1d()).collect()
+    }
+
+    // Distance between two values in [0, 1), treating the interval as wrapping around.
+    fn wrapped_distance(a: f64, b: f64) -> f64 {
+        let d = (a - b).abs();
+        d.min(1.0 - d)
+    }
+
+    #[test]
+    fn test_small_steps_stay_near_the_current_point() {
+        let mut sampler = MetropolisSampler::new(3, 0.01, 0.0);
+        let start = values(&mut sampler, 16);
+        sampler.start_iteration();
+        let mutated = values(&mut sampler, 16);
+        assert!(start.iter().zip(mutated.iter()).all(|(a, b)| a != b && wrapped_distance(*a, *b) < 0.1));
+        assert!(mutated.iter().all(|v| (0.0..1.0).contains(v)));
+    }
+
+    #[test]
+    fn test_rejected_mutations_return_to_the_current_point() {
+        let mut sampler = MetropolisSampler::new(5, 0.01, 0.5);
+        let start = values(&mut sampler, 8);
+        for _ in 0..20 {
+            sampler.start_iteration();
+            let _ = values(&mut sampler, 12);
+            sampler.reject();
+            assert_eq!((0..8).map(|d| sampler.samples[&d].value).collect::<Vec<f64>>(), start);
+        }
+    }
+
+    #[test]
+    fn test_accepted_large_steps_replace_values_that_were_not_used() {
+        let mut sampler = MetropolisSampler::new(7, 0.01, 1.0);
+        let start = values(&mut sampler, 4);
+        sampler.start_iteration();
+        let _ = values(&mut sampler, 2);
+        sampler.accept();
+        // The next small step starts from new values for the dimensions that the large step skipped.
+        sampler.large_step_probability = 0.0;
+        sampler.start_iteration();
+        let next = values(&mut sampler, 4);
+        assert!(wrapped_distance(next[3], start[3]) > 1e-3 || wrapped_distance(next[2], start[2]) > 1e-3);
+    }
+}
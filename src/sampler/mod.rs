@@ -0,0 +1,300 @@
+use crate::random::{hash, Rng};
+use crate::sampler::halton::HaltonSampler;
+use crate::sampler::independent::IndependentSampler;
+use crate::sampler::metropolis::MetropolisSampler;
+use crate::sampler::sobol::SobolSampler;
+use crate::sampler::stratified::StratifiedSampler;
+use crate::spectrum::Wavelengths;
+
+pub mod independent;
+pub mod stratified;
+pub mod halton;
+pub mod sobol;
+pub mod metropolis;
+
+// Samplers generate the random numbers used while rendering. Each sample of a pixel consumes a
+// sequence of dimensions, and every sampler uses the same layout so that a given dimension is
+// always used for the same purpose. This keeps the values in each dimension well distributed
+// across the samples of a pixel, and is what allows stratified and low discrepancy samplers to
+// reduce noise.
+//
+// Dimension layout:
+//   0-1  position within the pixel
+//   2-3  position on the camera lens
+//   4    time within the shutter interval
+//   then DIMENSIONS_PER_BOUNCE dimensions for each bounce of a path: LIGHT_DIMENSIONS for light
+//   sampling, BSDF_DIMENSIONS for sampling the material's BSDF and the last for deciding whether
+//   Russian roulette ends the path.
+//
+// Bidirectional integrators also trace a path from a light for each sample. Its dimensions start
+// at LIGHT_PATH_DIMENSION, far beyond those of any camera path, with the first five choosing the
+// light, the point on it and the direction the path leaves in. Its bounces use the same layout as
+// camera paths, offset by LIGHT_PATH_DIMENSION. Photon mapping traces its photons with the same
+// layout.
+//
+// Spectral renders choose the wavelengths of each sample with WAVELENGTH_DIMENSION, which is set
+// apart from the rest of the layout so that RGB renders use the same samples as they would without
+// it.
+pub const PIXEL_DIMENSION: u32 = 0;
+pub const LENS_DIMENSION: u32 = 2;
+pub const TIME_DIMENSION: u32 = 4;
+const FIRST_BOUNCE_DIMENSION: u32 = 5;
+const DIMENSIONS_PER_BOUNCE: u32 = 10;
+const LIGHT_DIMENSIONS: u32 = 3;
+const BSDF_DIMENSIONS: u32 = 6;
+
+pub const LIGHT_PATH_DIMENSION: u32 = 1 << 20;
+pub const WAVELENGTH_DIMENSION: u32 = LIGHT_PATH_DIMENSION - 1;
+
+// First dimension used for light sampling at the given bounce.
+pub fn light_dimension(bounce: u32) -> u32 {
+    FIRST_BOUNCE_DIMENSION + bounce * DIMENSIONS_PER_BOUNCE
+}
+
+// First dimension used for BSDF sampling at the given bounce.
+pub fn bsdf_dimension(bounce: u32) -> u32 {
+    light_dimension(bounce) + LIGHT_DIMENSIONS
+}
+
+// Dimension used for Russian roulette at the given bounce.
+pub fn russian_roulette_dimension(bounce: u32) -> u32 {
+    bsdf_dimension(bounce) + BSDF_DIMENSIONS
+}
+
+// The dimension of a light path that corresponds to the given dimension of a camera path.
+pub fn light_path_dimension(dimension: u32) -> u32 {
+    LIGHT_PATH_DIMENSION + dimension
+}
+
+// The samples needed to generate a camera ray.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraSample {
+    pub pixel: (f64, f64),
+    pub lens: (f64, f64),
+    pub time: f64,
+}
+
+// State shared by all of the samplers, tracking which pixel sample and dimension are current.
+// Any random numbers a sampler needs come from a generator seeded from the pixel, sample index
+// and dimension, so renders are reproducible no matter which thread renders a pixel and the
+// values for a dimension don't depend on how many dimensions were used before it.
+#[derive(Debug, Copy, Clone)]
+struct SampleState {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+
+    fn new(seed: u64) -> SampleState {
+        SampleState { seed, pixel_seed: seed, index: 0, dimension: 0 }
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // Seed that is unique to the current pixel and the given dimension.
+    fn dimension_seed(&self, dimension: u32) -> u64 {
+        hash(&[self.pixel_seed, dimension as u64])
+    }
+
+    // Random number generator for the given dimension of the current pixel sample.
+    fn rng(&self, dimension: u32) -> Rng {
+        Rng::new(hash(&[self.pixel_seed, self.index as u64, dimension as u64]))
+    }
+
+    // Claim the next count dimensions, returning the first.
+    fn next_dimensions(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        return dimension;
+    }
+}
+
+// Internal trait that defines the API for the underlying samplers.
+// Note that the Sampler enum forms the public API for samplers and wraps these private types.
+trait _Sampler {
+    fn state(&mut self) -> &mut SampleState;
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Debug, Clone)]
+pub enum Sampler {
+    Independent(IndependentSampler),
+    Stratified(StratifiedSampler),
+    Halton(HaltonSampler),
+    Sobol(SobolSampler),
+    Metropolis(MetropolisSampler),
+}
+
+impl Sampler {
+
+    // Independent uniform random samples.
+    pub fn independent(seed: u64) -> Sampler {
+        Sampler::Independent(IndependentSampler { state: SampleState::new(seed) })
+    }
+
+    // Jittered samples, with each dimension divided into one stratum per sample.
+    pub fn stratified(samples_per_pixel: u32, seed: u64) -> Sampler {
+        Sampler::Stratified(StratifiedSampler { samples_per_pixel: samples_per_pixel.max(1), state: SampleState::new(seed) })
+    }
+
+    // The Halton sequence, with Owen scrambling applied separately to each pixel.
+    pub fn halton(seed: u64) -> Sampler {
+        Sampler::Halton(HaltonSampler { state: SampleState::new(seed) })
+    }
+
+    // Owen scrambled Sobol points, padded and shuffled between pairs of dimensions.
+    pub fn sobol(seed: u64) -> Sampler {
+        Sampler::Sobol(SobolSampler { state: SampleState::new(seed) })
+    }
+
+    // Primary sample space samples for a Markov chain of Metropolis light transport, where small
+    // steps perturb values by offsets with standard deviation sigma. See MetropolisSampler.
+    pub fn metropolis(seed: u64, sigma: f64, large_step_probability: f64) -> Sampler {
+        Sampler::Metropolis(MetropolisSampler::new(seed, sigma, large_step_probability))
+    }
+
+    // The seed the sampler was created with.
+    pub fn seed(&mut self) -> u64 {
+        self.sampler().state().seed
+    }
+
+    fn sampler(&mut self) -> &mut dyn _Sampler {
+        match *self {
+            Sampler::Independent(ref mut sampler) => sampler,
+            Sampler::Stratified(ref mut sampler) => sampler,
+            Sampler::Halton(ref mut sampler) => sampler,
+            Sampler::Sobol(ref mut sampler) => sampler,
+            Sampler::Metropolis(ref mut sampler) => sampler,
+        }
+    }
+
+    // Prepare to generate the sample with the given index for a pixel.
+    pub fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.sampler().state().start_pixel_sample(x, y, index);
+    }
+
+    // Move to the given dimension of the current sample. See the layout described above.
+    pub fn start_dimension(&mut self, dimension: u32) {
+        self.sampler().state().dimension = dimension;
+    }
+
+    pub fn get_1d(&mut self) -> f64 {
+        self.sampler().get_1d()
+    }
+
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        self.sampler().get_2d()
+    }
+
+    pub fn camera_sample(&mut self) -> CameraSample {
+        self.start_dimension(PIXEL_DIMENSION);
+        let pixel = self.get_2d();
+        self.start_dimension(LENS_DIMENSION);
+        let lens = self.get_2d();
+        self.start_dimension(TIME_DIMENSION);
+        let time = self.get_1d();
+        return CameraSample { pixel, lens, time };
+    }
+
+    // Choose the wavelengths carried by the current sample of a spectral render.
+    pub fn wavelengths(&mut self) -> Wavelengths {
+        self.start_dimension(WAVELENGTH_DIMENSION);
+        return Wavelengths::sample(self.get_1d());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_samplers(samples_per_pixel: u32) -> Vec<Sampler> {
+        vec![
+            Sampler::independent(1),
+            Sampler::stratified(samples_per_pixel, 1),
+            Sampler::halton(1),
+            Sampler::sobol(1),
+        ]
+    }
+
+    fn pixel_samples(sampler: &mut Sampler, x: u32, y: u32, count: u32, dimension: u32) -> Vec<(f64, f64)> {
+        (0..count).map(|i| {
+            sampler.start_pixel_sample(x, y, i);
+            sampler.start_dimension(dimension);
+            sampler.get_2d()
+        }).collect()
+    }
+
+    #[test]
+    fn test_samples_are_in_the_unit_interval() {
+        for mut sampler in all_samplers(64) {
+            for dimension in 0..100 {
+                assert!(pixel_samples(&mut sampler, 3, 5, 64, dimension).iter().all(|(u, v)| {
+                    *u >= 0.0 && *u < 1.0 && *v >= 0.0 && *v < 1.0
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn test_samples_are_deterministic() {
+        for mut sampler in all_samplers(16) {
+            let first = pixel_samples(&mut sampler, 1, 2, 16, bsdf_dimension(2));
+            let _ = pixel_samples(&mut sampler, 7, 9, 16, 0);
+            assert_eq!(first, pixel_samples(&mut sampler, 1, 2, 16, bsdf_dimension(2)));
+        }
+    }
+
+    #[test]
+    fn test_camera_sample_matches_the_dimension_layout() {
+        for mut sampler in all_samplers(16) {
+            sampler.start_pixel_sample(4, 4, 3);
+            let camera_sample = sampler.camera_sample();
+            sampler.start_pixel_sample(4, 4, 3);
+            sampler.start_dimension(LENS_DIMENSION);
+            assert_eq!(camera_sample.lens, sampler.get_2d());
+        }
+    }
+
+    // A power of two number of stratified, Halton (for the first dimension pair) or Sobol samples
+    // should place exactly one sample in each cell of a grid with that many cells.
+    #[test]
+    fn test_well_distributed_samplers_place_one_sample_in_each_stratum() {
+        let samplers = vec![Sampler::stratified(16, 1), Sampler::sobol(1)];
+        for mut sampler in samplers {
+            for dimension in [PIXEL_DIMENSION, LENS_DIMENSION, bsdf_dimension(3)].iter() {
+                let mut cells: Vec<usize> = pixel_samples(&mut sampler, 2, 8, 16, *dimension).iter()
+                    .map(|(u, v)| (u * 4.0) as usize + 4 * (v * 4.0) as usize)
+                    .collect();
+                cells.sort();
+                assert_eq!(cells, (0..16).collect::<Vec<usize>>());
+            }
+        }
+    }
+
+    // Estimate the integral of a smooth function over the unit square using each sampler and check
+    // that the well distributed samplers have a lower error than independent samples.
+    #[test]
+    fn test_well_distributed_samplers_reduce_integration_error() {
+        let error = |sampler: &mut Sampler| -> f64 {
+            let pixels = 256;
+            (0..pixels).map(|p| {
+                let samples = pixel_samples(sampler, p, 0, 16, LENS_DIMENSION);
+                let estimate = samples.iter().map(|(u, v)| u * v).sum::<f64>() / 16.0;
+                (estimate - 0.25).powi(2)
+            }).sum::<f64>() / pixels as f64
+        };
+
+        let independent_error = error(&mut Sampler::independent(1));
+        assert!(error(&mut Sampler::stratified(16, 1)) < independent_error / 2.0);
+        assert!(error(&mut Sampler::halton(1)) < independent_error / 2.0);
+        assert!(error(&mut Sampler::sobol(1)) < independent_error / 2.0);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::random::hash;
+use crate::sampler::{_Sampler, SampleState};
+
+// Owen scrambled Sobol sampler, following Burley's "Practical Hash-based Owen Scrambling".
+// Only the first two dimensions of the Sobol sequence are used, which together form a (0, 2)
+// sequence. Higher dimensions are produced by padding: each pair of dimensions uses an independently
+// scrambled and shuffled copy of the 2D sequence, which avoids the poor projections of higher
+// Sobol dimensions and means no large table of direction numbers is needed.
+
+fn sobol_first_dimension(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_second_dimension(index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    let mut remaining = index;
+    while remaining != 0 {
+        if remaining & 1 != 0 {
+            result ^= direction;
+        }
+        remaining >>= 1;
+        direction ^= direction >> 1;
+    }
+    return result;
+}
+
+fn laine_karras_permutation(x: u32, seed: u32) -> u32 {
+    let mut x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    return x;
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn to_unit_interval(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SobolSampler {
+    pub(super) state: SampleState,
+}
+
+impl SobolSampler {
+    // Shuffle the sample index for a dimension so that padded dimensions aren't correlated.
+    fn shuffled_index(&self, seed: u64) -> u32 {
+        nested_uniform_scramble(self.state.index, seed as u32)
+    }
+}
+
+impl _Sampler for SobolSampler {
+    fn state(&mut self) -> &mut SampleState { &mut self.state }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next_dimensions(1);
+        let seed = self.state.dimension_seed(dimension);
+        let index = self.shuffled_index(seed);
+        return to_unit_interval(nested_uniform_scramble(sobol_first_dimension(index), hash(&[seed, 1]) as u32));
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next_dimensions(2);
+        let seed = self.state.dimension_seed(dimension);
+        let index = self.shuffled_index(seed);
+        return (
+            to_unit_interval(nested_uniform_scramble(sobol_first_dimension(index), hash(&[seed, 1]) as u32)),
+            to_unit_interval(nested_uniform_scramble(sobol_second_dimension(index), hash(&[seed, 2]) as u32)),
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use crate::random::{hash, permutation_element};
+use crate::sampler::{_Sampler, SampleState};
+
+// Divides each dimension into one stratum per sample and places a single jittered sample in each
+// stratum, with the strata visited in a different random order for each dimension. Pairs of
+// dimensions are stratified over a grid that is as close to square as possible.
+// Sample indices beyond samples_per_pixel start another round of strata, so the sampler still
+// produces valid samples during progressive renders that take more samples than planned.
+#[derive(Debug, Copy, Clone)]
+pub struct StratifiedSampler {
+    pub samples_per_pixel: u32,
+    pub(super) state: SampleState,
+}
+
+impl StratifiedSampler {
+    fn stratum(&self, dimension: u32, strata: u32) -> u32 {
+        let round = self.state.index / self.samples_per_pixel;
+        let seed = hash(&[self.state.dimension_seed(dimension), round as u64]) as u32;
+        return permutation_element(self.state.index % self.samples_per_pixel, strata, seed);
+    }
+}
+
+impl _Sampler for StratifiedSampler {
+    fn state(&mut self) -> &mut SampleState { &mut self.state }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next_dimensions(1);
+        let strata = self.samples_per_pixel;
+        let stratum = self.stratum(dimension, strata);
+        return (stratum as f64 + self.state.rng(dimension).next_f64()) / strata as f64;
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next_dimensions(2);
+        let x_strata = (self.samples_per_pixel as f64).sqrt().floor() as u32;
+        let y_strata = self.samples_per_pixel.div_ceil(x_strata);
+        let stratum = self.stratum(dimension, x_strata * y_strata);
+        let mut rng = self.state.rng(dimension);
+        return (
+            ((stratum % x_strata) as f64 + rng.next_f64()) / x_strata as f64,
+            ((stratum / x_strata) as f64 + rng.next_f64()) / y_strata as f64,
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+// Functions that warp uniformly distributed samples in [0, 1)^2 onto other domains. Using these
+// rather than rejection sampling means that the well distributed samples generated by a Sampler
+// stay well distributed after the mapping.
+
+// Map a sample onto the unit disk in the xy plane using Shirley and Chiu's concentric mapping,
+// which preserves the relative areas and stratification of the input samples.
+pub fn sample_unit_disk(u: (f64, f64)) -> Vec3 {
+    let offset_x = 2.0 * u.0 - 1.0;
+    let offset_y = 2.0 * u.1 - 1.0;
+
+    if offset_x == 0.0 && offset_y == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    let (r, theta) = if offset_x.abs() > offset_y.abs() {
+        (offset_x, (PI / 4.0) * (offset_y / offset_x))
+    }
+    else {
+        (offset_y, (PI / 2.0) - (PI / 4.0) * (offset_x / offset_y))
+    };
+
+    return Vec3::new(r * theta.cos(), r * theta.sin(), 0.0);
+}
+
+// Map a sample onto a uniformly distributed direction on the surface of the unit sphere.
+pub fn sample_unit_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    return Vec3::new(r * phi.cos(), r * phi.sin(), z);
+}
+
+// Map a 2D sample and a 1D sample onto a point uniformly distributed within the unit ball.
+pub fn sample_unit_ball(u: (f64, f64), radius_sample: f64) -> Vec3 {
+    radius_sample.cbrt() * sample_unit_sphere(u)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::stat_tests::{chi_square_test, SphereHistogram};
+
+    const SAMPLES: usize = 100000;
+    const SIGNIFICANCE: f64 = 1e-3;
+
+    fn grid(n: usize) -> Vec<(f64, f64)> {
+        (0..n * n).map(|i| (((i % n) as f64 + 0.5) / n as f64, ((i / n) as f64 + 0.5) / n as f64)).collect()
+    }
+
+    #[test]
+    fn test_sample_unit_disk_returns_points_within_the_disk() {
+        assert!(grid(32).into_iter().map(sample_unit_disk).all(|p| p.length() <= 1.0 && p.z == 0.0));
+    }
+
+    #[test]
+    fn test_sample_unit_sphere_returns_unit_vectors() {
+        assert!(grid(32).into_iter().map(sample_unit_sphere).all(|p| (p.length() - 1.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_sample_unit_ball_returns_points_within_the_ball() {
+        assert!(grid(32).into_iter().map(|u| sample_unit_ball(u, u.0)).all(|p| p.length() <= 1.0));
+    }
+
+    fn random_samples() -> Vec<(f64, f64, f64)> {
+        let mut rng = Rng::new(3);
+        (0..SAMPLES).map(|_| (rng.next_f64(), rng.next_f64(), rng.next_f64())).collect()
+    }
+
+    #[test]
+    fn test_sample_unit_disk_is_uniform() {
+        // Rings of equal area, each split into equal sectors.
+        let (rings, sectors) = (10, 20);
+        let mut observed = vec![0.0; rings * sectors];
+        for (u, v, _) in random_samples() {
+            let p = sample_unit_disk((u, v));
+            let ring = ((p.squared_length() * rings as f64) as usize).min(rings - 1);
+            let sector = (((p.y.atan2(p.x) + PI) / (2.0 * PI) * sectors as f64) as usize).min(sectors - 1);
+            observed[ring * sectors + sector] += 1.0;
+        }
+        let expected = vec![SAMPLES as f64 / (rings * sectors) as f64; rings * sectors];
+        assert_eq!(chi_square_test(&observed, &expected, SIGNIFICANCE), Ok(()));
+    }
+
+    #[test]
+    fn test_sample_unit_sphere_is_uniform() {
+        let histogram = SphereHistogram { theta_bins: 20, phi_bins: 40 };
+        let directions: Vec<Vec3> = random_samples().into_iter().map(|(u, v, _)| sample_unit_sphere((u, v))).collect();
+        let expected = histogram.expected(SAMPLES, |_| 1.0 / (4.0 * PI));
+        assert_eq!(chi_square_test(&histogram.observed(&directions), &expected, SIGNIFICANCE), Ok(()));
+    }
+
+    #[test]
+    fn test_sample_unit_ball_is_uniform() {
+        // Shells of equal volume, each split into bins of equal solid angle.
+        let shells = 10;
+        let histogram = SphereHistogram { theta_bins: 5, phi_bins: 10 };
+        let mut observed = vec![0.0; shells * histogram.bin_count()];
+        for (u, v, w) in random_samples() {
+            let p = sample_unit_ball((u, v), w);
+            let shell = ((p.length().powi(3) * shells as f64) as usize).min(shells - 1);
+            observed[shell * histogram.bin_count() + histogram.bin(p)] += 1.0;
+        }
+        let expected = vec![SAMPLES as f64 / observed.len() as f64; observed.len()];
+        assert_eq!(chi_square_test(&observed, &expected, SIGNIFICANCE), Ok(()));
+    }
+}
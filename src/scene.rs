@@ -1,19 +1,125 @@
-use crate::hitable::Hitable;
-use rand::random;
+use crate::camera::Camera;
+use crate::hitable::{HitRecord, Hitable};
+use crate::hitable::sphere::Sphere;
+use crate::light::Light;
+use crate::random::Rng;
+use crate::render::Background;
 use crate::vec3::Vec3;
 use crate::material::Material;
+use crate::scene_file;
+
+// A world to render along with the camera to view it from.
+pub struct Scene {
+    pub world: Hitable,
+    pub camera: Camera,
+    // The emissive objects in the world.
+    pub lights: Vec<Light>,
+    // The light seen by rays that leave the world.
+    pub background: Background,
+}
+
+impl Scene {
+
+    // Create a scene lit by the sky, finding the lights among the objects in the world.
+    pub fn new(mut world: Hitable, camera: Camera) -> Scene {
+        world.assign_material_ids();
+        let lights = find_lights(&world);
+        return Scene { world, camera, lights, background: Background::Sky };
+    }
+
+    // The light that a hit is on, if the object hit is one of the scene's lights.
+    pub fn light(&self, hit: &HitRecord) -> Option<&Light> {
+        if !hit.material.is_emissive() { return None }
+        return self.lights.iter().find(|light| light.object_id == hit.object_id);
+    }
+
+    // Probability of choosing any one light when a light is chosen at random.
+    pub fn light_selection_pdf(&self) -> f64 {
+        return if self.lights.is_empty() { 0.0 } else { 1.0 / self.lights.len() as f64 };
+    }
+
+    // Choose one of the lights uniformly at random.
+    pub fn choose_light(&self, u: f64) -> Option<&Light> {
+        if self.lights.is_empty() { return None }
+        let index = ((u * self.lights.len() as f64) as usize).min(self.lights.len() - 1);
+        return Some(&self.lights[index]);
+    }
+
+    // The mirrors and glass among the spheres that lights can be found for, with their object
+    // ids. Light reaching the rest of the scene through these forms caustics.
+    pub fn delta_spheres(&self) -> Vec<(&Sphere, u32)> {
+        return top_level_spheres(&self.world).into_iter().filter(|(sphere, _)| sphere.material.is_delta()).collect();
+    }
+}
+
+// The spheres that are on their own or in the outermost list of objects, with their object ids.
+fn top_level_spheres(world: &Hitable) -> Vec<(&Sphere, u32)> {
+    match *world {
+        Hitable::Sphere(ref sphere) => vec![(sphere, 0)],
+        Hitable::HitableList(ref list) => list.hitables.iter().enumerate().filter_map(|(i, h)| match *h {
+            Hitable::Sphere(ref sphere) => Some((sphere, i as u32)),
+            _ => None,
+        }).collect(),
+        Hitable::Mesh(_) => vec![],
+    }
+}
+
+// Emissive spheres in the world, which may be on their own or in the outermost list of objects.
+fn find_lights(world: &Hitable) -> Vec<Light> {
+    return top_level_spheres(world).into_iter().filter_map(|(sphere, object_id)| match sphere.material {
+        Material::Emissive(ref emissive) => Some(Light::sphere(sphere.centre, sphere.radius, emissive.emission, object_id)),
+        _ => None,
+    }).collect();
+}
+
+// Names of the built-in scenes that can be passed to scene.
+pub const SCENE_NAMES: [&str; 4] = ["final", "simple", "caustic", "materials"];
+
+// Build one of the built-in scenes by name. The seed chooses the layout of randomly generated
+// scenes, so that renders are reproducible.
+pub fn scene(name: &str, aspect_ratio: f64, seed: u64) -> Option<Scene> {
+    match name {
+        "final" => Some(final_scene(aspect_ratio, seed)),
+        "simple" => Some(simple_scene(aspect_ratio)),
+        "caustic" => Some(caustic_scene(aspect_ratio)),
+        "materials" => Some(materials_scene(aspect_ratio)),
+        _ => None,
+    }
+}
+
+// The scene from the cover of Ray Tracing in One Weekend.
+pub fn final_scene(aspect_ratio: f64, seed: u64) -> Scene {
+    let look_from = Vec3::new(13.0, 2.0, 3.0);
+    let look_at = Vec3::new(0.0, 0.0, 0.0);
+    let focus_distance = 10.0;
+
+    let camera = Camera::new(
+        look_from,
+        look_at,
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        20.0,
+        aspect_ratio,
+        0.1,
+        focus_distance
+    );
+
+    return Scene::new(Hitable::hitable_list(final_scene_spheres(seed)), camera);
+}
+
+fn final_scene_spheres(seed: u64) -> Vec<Hitable> {
+    let mut rng = Rng::new(seed);
+    let mut random = || rng.next_f64();
 
-pub fn final_scene() -> Vec<Hitable> {
     // Randomly generate a number of small spheres.
     let mut small_spheres: Vec<Hitable> = vec![];
     let radius = 0.2;
     for a in -11..11 {
         for b in -11..11 {
-            let choose_material = random::<f64>();
+            let choose_material = random();
             let centre = Vec3::new(
-                a as f64 + 0.9 * random::<f64>(),
+                a as f64 + 0.9 * random(),
                 radius,
-                b as f64 + 0.9 * random::<f64>()
+                b as f64 + 0.9 * random()
             );
             if (centre - Vec3::new(4.0, 0.2, 0.0 )).length() > 0.9 {
                 if choose_material < 0.8 {
@@ -22,9 +128,9 @@ pub fn final_scene() -> Vec<Hitable> {
                         centre,
                         radius,
                         Material::lambertian(
-                            random::<f64>() * random::<f64>(),
-                            random::<f64>() * random::<f64>(),
-                            random::<f64>() * random::<f64>(),
+                            random() * random(),
+                            random() * random(),
+                            random() * random(),
                         )
                     ))
                 }
@@ -34,9 +140,9 @@ pub fn final_scene() -> Vec<Hitable> {
                         centre,
                         radius,
                         Material::metal(
-                            0.5 * (1.0 + random::<f64>()),
-                            0.5 * (1.0 + random::<f64>()),
-                            0.5 * (1.0 + random::<f64>()),
+                            0.5 * (1.0 + random()),
+                            0.5 * (1.0 + random()),
+                            0.5 * (1.0 + random()),
                             0.5,
                         )
                     ));
@@ -50,7 +156,6 @@ pub fn final_scene() -> Vec<Hitable> {
                     ))
                 }
             }
-            else {  }
         }
     };
 
@@ -66,4 +171,107 @@ pub fn final_scene() -> Vec<Hitable> {
     ].into_iter().flatten().collect();
 
     return all_spheres;
-}
\ No newline at end of file
+}
+
+// A diffuse, a metal and a hollow glass sphere sitting on a large diffuse sphere.
+pub fn simple_scene(aspect_ratio: f64) -> Scene {
+    let world = Hitable::hitable_list(vec![
+        Hitable::sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::lambertian(0.8, 0.8, 0.0)),
+        Hitable::sphere(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::lambertian(0.1, 0.2, 0.5)),
+        Hitable::sphere(Vec3::new(1.0, 0.0, -1.0), 0.5, Material::metal(0.8, 0.6, 0.2, 0.3)),
+        Hitable::sphere(Vec3::new(-1.0, 0.0, -1.0), 0.5, Material::dielectric(1.5)),
+        Hitable::sphere(Vec3::new(-1.0, 0.0, -1.0), -0.45, Material::dielectric(1.5)),
+    ]);
+
+    let camera = Camera::new(
+        Vec3::new(-2.0, 2.0, 1.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        aspect_ratio,
+        0.0,
+        1.0
+    );
+
+    return Scene::new(world, camera);
+}
+
+// A glass sphere lit by a small light in an otherwise dark scene, which focuses the light into a
+// caustic on the ground. Caustics like this are hard for the path tracer, as paths from the ground
+// must pass through the glass and then happen to hit the light.
+pub fn caustic_scene(aspect_ratio: f64) -> Scene {
+    let world = Hitable::hitable_list(vec![
+        Hitable::sphere(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::lambertian(0.7, 0.7, 0.7)),
+        Hitable::sphere(Vec3::new(0.0, 1.0, 0.0), 1.0, Material::dielectric(1.5)),
+        Hitable::sphere(Vec3::new(-2.4, 0.7, -0.5), 0.7, Material::lambertian(0.2, 0.4, 0.8)),
+        Hitable::sphere(Vec3::new(2.2, 0.5, 0.5), 0.5, Material::metal(0.8, 0.7, 0.6, 0.2)),
+        Hitable::sphere(Vec3::new(-1.0, 4.5, -1.5), 0.3, Material::emissive(40.0, 36.0, 30.0)),
+    ]);
+
+    let camera = Camera::new(
+        Vec3::new(0.0, 3.0, 9.0),
+        Vec3::new(0.0, 0.6, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        35.0,
+        aspect_ratio,
+        0.0,
+        1.0
+    );
+
+    return Scene { background: Background::Uniform(Vec3::new(0.01, 0.01, 0.01)), ..Scene::new(world, camera) };
+}
+
+// A row of spheres showing off the principled material, described in scenes/materials.scene.
+pub fn materials_scene(aspect_ratio: f64) -> Scene {
+    return scene_file::parse(include_str!("../scenes/materials.scene"), aspect_ratio)
+        .expect("the materials scene is valid");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::NEAR_ZERO;
+    use crate::light::LightShape;
+    use crate::ray::Ray;
+    use crate::spectrum::Wavelengths;
+    use crate::texture::{ImageTexture, Texture};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_emissive_spheres_are_found_as_lights() {
+        let scene = caustic_scene(1.5);
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.light_selection_pdf(), 1.0);
+
+        // Hits on the light's sphere are matched up with it, but hits on the ground aren't.
+        let light = scene.choose_light(0.5).unwrap();
+        let centre = match light.shape { LightShape::Sphere(ref sphere) => sphere.centre };
+        let up = Ray { origin: Vec3::new(centre.x, 0.5, centre.z), direction: Vec3::new(0.0, 1.0, 0.0), wavelengths: Wavelengths::Rgb };
+        let down = Ray { origin: Vec3::new(centre.x, 0.5, centre.z), direction: Vec3::new(0.0, -1.0, 0.0), wavelengths: Wavelengths::Rgb };
+        assert!(scene.light(&scene.world.hit(&up, NEAR_ZERO, f64::MAX).unwrap()).is_some());
+        assert!(scene.light(&scene.world.hit(&down, NEAR_ZERO, f64::MAX).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_objects_with_identical_materials_share_a_material_id() {
+        // Two images with the same name and size, but different texels.
+        let image = |texel: Vec3| Texture::Image(Arc::new(ImageTexture { name: "wood.png".to_string(), width: 1, height: 1, texels: vec![texel] }));
+        let (light, dark) = (image(Vec3::new(0.9, 0.9, 0.9)), image(Vec3::new(0.1, 0.1, 0.1)));
+        let materials = vec![
+            Material::lambertian(0.5, 0.5, 0.5),
+            Material::metal(0.5, 0.5, 0.5, 0.0),
+            Material::lambertian(0.5, 0.5, 0.5),
+            Material::bump_mapped(Material::lambertian(0.5, 0.5, 0.5), light.clone(), 1.0),
+            Material::bump_mapped(Material::lambertian(0.5, 0.5, 0.5), dark, 1.0),
+            Material::bump_mapped(Material::lambertian(0.5, 0.5, 0.5), light, 1.0),
+        ];
+        let spheres = materials.into_iter().enumerate().map(|(i, material)| Hitable::sphere(Vec3::new(3.0 * i as f64, 0.0, 0.0), 1.0, material)).collect();
+        let scene = Scene::new(Hitable::hitable_list(spheres), simple_scene(1.0).camera);
+
+        let ids: Vec<u32> = (0..6).map(|i| {
+            let ray = Ray { origin: Vec3::new(3.0 * i as f64, 5.0, 0.0), direction: Vec3::new(0.0, -1.0, 0.0), wavelengths: Wavelengths::Rgb };
+            scene.world.hit(&ray, NEAR_ZERO, f64::MAX).unwrap().material_id
+        }).collect();
+        assert_eq!(ids, vec![0, 1, 0, 2, 3, 2]);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::camera::Camera;
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::material::principled::Principled;
+use crate::render::Background;
+use crate::scene::Scene;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+
+// Scenes can be described in a text file rather than built in code. Each line is a statement of
+// whitespace separated words, and anything after a # is a comment:
+//
+//   camera <from x y z> <at x y z> <vertical fov>
+//   background sky | background <r g b>
+//   sphere <centre x y z> <radius> <material>
+//
+// where the material is one of
+//
+//   lambertian <r g b>
+//   metal <r g b> <fuzziness>
+//   dielectric <refractive index>
+//   emissive <r g b>
+//   principled [<parameter>=<texture> ...]
+//
+// The parameters of a principled material are the fields of Principled, and any that aren't given
+// keep their defaults. A texture is a single number, an r,g,b colour, checker:<even>:<odd>:<scale>
+// with r,g,b colours, or image:<file> for a PNG decoded as colours or data:<file> for one decoded
+// as raw values.
+
+// Read a scene from a file, rendered at the given aspect ratio.
+pub fn load(file_name: &str, aspect_ratio: f64) -> Result<Scene, String> {
+    let text = std::fs::read_to_string(file_name).map_err(|e| format!("Unable to read scene {}: {}", file_name, e))?;
+    return parse(&text, aspect_ratio).map_err(|e| format!("{}: {}", file_name, e));
+}
+
+pub fn parse(text: &str, aspect_ratio: f64) -> Result<Scene, String> {
+    let mut camera = None;
+    let mut background = Background::Sky;
+    let mut objects = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        let statement = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => continue,
+        };
+        let result = match statement {
+            ("camera", args) => parse_camera(args, aspect_ratio).map(|c| camera = Some(c)),
+            ("background", ["sky"]) => {
+                background = Background::Sky;
+                Ok(())
+            }
+            ("background", args) => colour(args).map(|c| background = Background::Uniform(c)),
+            ("sphere", args) => parse_sphere(args).map(|s| objects.push(s)),
+            (name, _) => Err(format!("unknown statement {}", name)),
+        };
+        result.map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+
+    let camera = camera.ok_or_else(|| String::from("the scene has no camera"))?;
+    return Ok(Scene { background, ..Scene::new(Hitable::hitable_list(objects), camera) });
+}
+
+fn parse_camera(args: &[&str], aspect_ratio: f64) -> Result<Camera, String> {
+    if args.len() != 7 {
+        return Err(String::from("camera takes a position, a point to look at and a field of view"));
+    }
+    let fov = number(args[6])?;
+    return Ok(Camera::new(vector(&args[0..3])?, vector(&args[3..6])?, Vec3::new(0.0, 1.0, 0.0), fov, aspect_ratio, 0.0, 1.0));
+}
+
+fn parse_sphere(args: &[&str]) -> Result<Hitable, String> {
+    if args.len() < 5 {
+        return Err(String::from("sphere takes a centre, a radius and a material"));
+    }
+    return Ok(Hitable::sphere(vector(&args[0..3])?, number(args[3])?, parse_material(&args[4..])?));
+}
+
+fn parse_material(args: &[&str]) -> Result<Material, String> {
+    let material = match args {
+        ["lambertian", r, g, b] => Material::lambertian(number(r)?, number(g)?, number(b)?),
+        ["metal", r, g, b, fuzziness] => Material::metal(number(r)?, number(g)?, number(b)?, number(fuzziness)?),
+        ["dielectric", refractive_index] => Material::dielectric(number(refractive_index)?),
+        ["emissive", r, g, b] => Material::emissive(number(r)?, number(g)?, number(b)?),
+        ["principled", parameters @ ..] => Material::principled(parse_principled(parameters)?),
+        _ => return Err(format!("unknown material {}", args.join(" "))),
+    };
+    return Ok(material);
+}
+
+pub fn parse_principled(parameters: &[&str]) -> Result<Principled, String> {
+    let mut principled = Principled::default();
+    for parameter in parameters {
+        let (name, value) = parameter.split_once('=').ok_or_else(|| format!("expected <parameter>=<texture>, not {}", parameter))?;
+        let texture = texture(value)?;
+        match name {
+            "base_colour" => principled.base_colour = texture,
+            "metallic" => principled.metallic = texture,
+            "roughness" => principled.roughness = texture,
+            "specular" => principled.specular = texture,
+            "specular_tint" => principled.specular_tint = texture,
+            "sheen" => principled.sheen = texture,
+            "clearcoat" => principled.clearcoat = texture,
+            "transmission" => principled.transmission = texture,
+            "ior" => principled.ior = texture,
+            "anisotropy" => principled.anisotropy = texture,
+            _ => return Err(format!("unknown principled parameter {}", name)),
+        }
+    }
+    return Ok(principled);
+}
+
+fn texture(value: &str) -> Result<Texture, String> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let texture = match parts.as_slice() {
+        ["checker", even, odd, scale] => Texture::checker(components(even)?, components(odd)?, number(scale)?),
+        ["image", file_name] => Texture::image(file_name).map_err(|e| format!("unable to read {}: {}", file_name, e))?,
+        ["data", file_name] => Texture::data_image(file_name).map_err(|e| format!("unable to read {}: {}", file_name, e))?,
+        [value] => Texture::Constant(components(value)?),
+        _ => return Err(format!("unknown texture {}", value)),
+    };
+    return Ok(texture);
+}
+
+// A single number for a grey, or r,g,b.
+fn components(value: &str) -> Result<Vec3, String> {
+    let values = value.split(',').map(number).collect::<Result<Vec<f64>, String>>()?;
+    return match values.as_slice() {
+        [v] => Ok(Vec3::new(*v, *v, *v)),
+        [r, g, b] => Ok(Vec3::new(*r, *g, *b)),
+        _ => Err(format!("expected a number or r,g,b, not {}", value)),
+    };
+}
+
+fn colour(args: &[&str]) -> Result<Vec3, String> {
+    return if args.len() == 3 { vector(args) } else { Err(String::from("expected sky or a colour")) };
+}
+
+fn vector(args: &[&str]) -> Result<Vec3, String> {
+    return Ok(Vec3::new(number(args[0])?, number(args[1])?, number(args[2])?));
+}
+
+fn number(value: &str) -> Result<f64, String> {
+    return value.parse().map_err(|_| format!("expected a number, not {}", value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_principled_parameters_take_numbers_colours_and_checkers() {
+        let principled = parse_principled(&["base_colour=0.8,0.1,0.1", "metallic=1", "roughness=checker:0.7:0.2:200"]).unwrap();
+        assert_eq!(principled.base_colour, Texture::constant(0.8, 0.1, 0.1));
+        assert_eq!(principled.metallic, Texture::scalar(1.0));
+        assert_eq!(principled.roughness, Texture::checker(Vec3::new(0.7, 0.7, 0.7), Vec3::new(0.2, 0.2, 0.2), 200.0));
+        // Parameters that aren't given keep their defaults.
+        assert_eq!(principled.ior, Principled::default().ior);
+    }
+
+    #[test]
+    fn test_a_scene_file_describes_the_camera_background_and_spheres() {
+        let text = "
+            # A lit principled sphere on a matte floor.
+            camera 0 1 5  0 0 0  45
+            background 0.1 0.1 0.1
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5
+            sphere 0 1 0 1 principled base_colour=0.9,0.5,0.1 clearcoat=1   # coated orange
+            sphere 0 5 0 0.5 emissive 4 4 4
+        ";
+        let scene = parse(text, 1.5).unwrap();
+        assert_eq!(scene.background, Background::Uniform(Vec3::new(0.1, 0.1, 0.1)));
+        assert_eq!(scene.lights.len(), 1);
+        let Hitable::HitableList(ref list) = scene.world else { panic!("expected a list of objects") };
+        assert_eq!(list.hitables.len(), 3);
+        let Hitable::Sphere(ref sphere) = list.hitables[1] else { panic!("expected a sphere") };
+        let expected = Principled { base_colour: Texture::constant(0.9, 0.5, 0.1), clearcoat: Texture::scalar(1.0), ..Principled::default() };
+        assert_eq!(sphere.material, Material::principled(expected));
+    }
+
+    #[test]
+    fn test_mistakes_are_reported_with_their_line() {
+        assert_eq!(parse("camera 0 0 0 0 0 -1 90\nsphere 0 0 -1 0.5 plastic", 1.0).err(), Some(String::from("line 2: unknown material plastic")));
+        assert_eq!(parse("sphere 0 0 -1 0.5 principled roughness=x", 1.0).err(), Some(String::from("line 1: expected a number, not x")));
+        assert_eq!(parse("", 1.0).err(), Some(String::from("the scene has no camera")));
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::OnceLock;
+
+use crate::vec3::Vec3;
+
+// Spectral rendering traces light at individual wavelengths rather than as red, green and blue, so
+// that effects which depend on the wavelength can be represented. Each camera sample carries light
+// at three wavelengths, following the hero wavelength sampling of Wilkie et al.: the hero is
+// chosen uniformly and the other two are spaced evenly after it, wrapping around the range. The
+// values at the three wavelengths are stored in the components of a Vec3, so the integrators
+// handle them exactly like RGB colours.
+//
+// Colours in scenes are given as RGB, and are turned into spectra using Smits' method, which
+// builds a smooth spectrum from a few basis spectra. Samples are converted to CIE XYZ using the
+// analytic fit to the colour matching functions by Wyman, Sloan and Shirley, then into linear
+// sRGB, white balanced so that a constant spectrum gives white.
+
+// Range of wavelengths traced, in nanometres, which is the range covered by Smits' spectra.
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+// Number of wavelengths carried by each sample.
+const WAVELENGTHS: usize = 3;
+
+// Smits' basis spectra, sampled in 10 equal bins over the range of wavelengths.
+const SMITS_BINS: usize = 10;
+const WHITE: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN: [f64; SMITS_BINS] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW: [f64; SMITS_BINS] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f64; SMITS_BINS] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f64; SMITS_BINS] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// The wavelengths that a ray carries light at. Rays in RGB mode carry red, green and blue instead,
+// and every conversion leaves their colours unchanged.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wavelengths {
+    Rgb,
+    Spectral([f64; WAVELENGTHS]),
+}
+
+impl Wavelengths {
+
+    // The wavelengths for a sample, with the hero wavelength chosen by u.
+    pub fn sample(u: f64) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = u * range;
+        let mut lambda = [0.0; WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (hero + i as f64 * range / WAVELENGTHS as f64) % range;
+            *l = LAMBDA_MIN + offset;
+        }
+        return Wavelengths::Spectral(lambda);
+    }
+
+    // The hero wavelength, which effects that can only follow a single wavelength use.
+    pub fn hero(&self) -> Option<f64> {
+        match *self {
+            Wavelengths::Rgb => None,
+            Wavelengths::Spectral(lambda) => Some(lambda[0]),
+        }
+    }
+
+    // The values at these wavelengths of the spectrum for an RGB colour, such as an albedo or an
+    // emission.
+    pub fn spectrum(self, rgb: Vec3) -> Vec3 {
+        match self {
+            Wavelengths::Rgb => rgb,
+            Wavelengths::Spectral(lambda) => Vec3::new(smits(rgb, lambda[0]), smits(rgb, lambda[1]), smits(rgb, lambda[2])),
+        }
+    }
+
+    // Convert the light carried at these wavelengths to linear sRGB. Each wavelength is an
+    // independent estimate of the colour, so they're averaged.
+    pub fn to_rgb(self, values: Vec3) -> Vec3 {
+        match self {
+            Wavelengths::Rgb => values,
+            Wavelengths::Spectral(lambda) => {
+                let values = [values.x, values.y, values.z];
+                let xyz = lambda.iter().zip(values.iter())
+                    .fold(Vec3::new(0.0, 0.0, 0.0), |total, (l, v)| total + *v * colour_matching(*l));
+                // Dividing by the density of each wavelength, and normalising so that a constant
+                // spectrum of 1 has a luminance of 1.
+                let xyz = xyz * (LAMBDA_MAX - LAMBDA_MIN) / (WAVELENGTHS as f64 * y_integral());
+                return xyz_to_rgb(xyz) / white_point();
+            }
+        }
+    }
+}
+
+// The value at the wavelength lambda of Smits' spectrum for an RGB colour. The colour is made up
+// of white, plus one of cyan, magenta or yellow, plus one of red, green or blue, according to
+// which of its components is smallest and largest.
+fn smits(rgb: Vec3, lambda: f64) -> f64 {
+    let bin = (((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * SMITS_BINS as f64) as usize).min(SMITS_BINS - 1);
+    let (r, g, b) = (rgb.r(), rgb.g(), rgb.b());
+    if r <= g && r <= b {
+        return r * WHITE[bin] + if g <= b { (g - r) * CYAN[bin] + (b - g) * BLUE[bin] } else { (b - r) * CYAN[bin] + (g - b) * GREEN[bin] };
+    }
+    if g <= r && g <= b {
+        return g * WHITE[bin] + if r <= b { (r - g) * MAGENTA[bin] + (b - r) * BLUE[bin] } else { (b - g) * MAGENTA[bin] + (r - b) * RED[bin] };
+    }
+    return b * WHITE[bin] + if r <= g { (r - b) * YELLOW[bin] + (g - r) * GREEN[bin] } else { (g - b) * YELLOW[bin] + (r - g) * RED[bin] };
+}
+
+// A Gaussian with different widths either side of its peak, as used by the fit below.
+fn piecewise_gaussian(x: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma_below } else { sigma_above };
+    return (-0.5 * t * t).exp();
+}
+
+// The CIE 1931 colour matching functions at the wavelength lambda, using the multi-lobe fit of
+// Wyman, Sloan and Shirley.
+pub fn colour_matching(lambda: f64) -> Vec3 {
+    let x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+    return Vec3::new(x, y, z);
+}
+
+fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+// Integral of the colour matching functions over the range of wavelengths, found numerically.
+fn integral() -> Vec3 {
+    static INTEGRAL: OnceLock<Vec3> = OnceLock::new();
+    return *INTEGRAL.get_or_init(|| {
+        let steps = 10_000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        (0..steps).fold(Vec3::new(0.0, 0.0, 0.0), |total, i| total + step * colour_matching(LAMBDA_MIN + (i as f64 + 0.5) * step))
+    });
+}
+
+fn y_integral() -> f64 {
+    integral().y
+}
+
+// The linear sRGB colour of a constant spectrum of 1, which is divided out so that it gives white.
+fn white_point() -> Vec3 {
+    xyz_to_rgb(integral() / y_integral())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    // Average the RGB estimates of a spectrum over many sets of wavelengths.
+    fn average_rgb(spectrum: impl Fn(Wavelengths) -> Vec3) -> Vec3 {
+        let samples = 10_000;
+        let mut rng = Rng::new(1);
+        return (0..samples).fold(Vec3::new(0.0, 0.0, 0.0), |total, _| {
+            let wavelengths = Wavelengths::sample(rng.next_f64());
+            total + wavelengths.to_rgb(spectrum(wavelengths))
+        }) / samples as f64;
+    }
+
+    #[test]
+    fn test_wavelengths_are_spread_over_the_range() {
+        for u in [0.0, 0.3, 0.999].iter() {
+            match Wavelengths::sample(*u) {
+                Wavelengths::Spectral(lambda) => {
+                    assert!(lambda.iter().all(|l| (LAMBDA_MIN..LAMBDA_MAX).contains(l)), "{:?}", lambda);
+                    let mut sorted = lambda;
+                    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    assert!((sorted[1] - sorted[0] - (LAMBDA_MAX - LAMBDA_MIN) / 3.0).abs() < 1e-9);
+                    assert!((sorted[2] - sorted[1] - (LAMBDA_MAX - LAMBDA_MIN) / 3.0).abs() < 1e-9);
+                }
+                Wavelengths::Rgb => panic!("sampled wavelengths aren't spectral"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgb_wavelengths_leave_colours_unchanged() {
+        let colour = Vec3::new(0.2, 0.5, 0.9);
+        assert_eq!(Wavelengths::Rgb.spectrum(colour), colour);
+        assert_eq!(Wavelengths::Rgb.to_rgb(colour), colour);
+    }
+
+    #[test]
+    fn test_constant_spectrum_is_white() {
+        let white = average_rgb(|_| Vec3::new(1.0, 1.0, 1.0));
+        assert!((white - Vec3::new(1.0, 1.0, 1.0)).length() < 0.01, "{}", white);
+    }
+
+    #[test]
+    fn test_upsampled_colours_convert_back_to_roughly_the_same_colour() {
+        for colour in [Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.8, 0.3, 0.3), Vec3::new(0.2, 0.6, 0.2), Vec3::new(0.1, 0.2, 0.5), Vec3::new(4.0, 2.0, 1.0)].iter() {
+            let round_trip = average_rgb(|wavelengths| wavelengths.spectrum(*colour));
+            assert!((round_trip - *colour).length() < 0.1 * colour.length(), "{} became {}", colour, round_trip);
+        }
+    }
+
+    #[test]
+    fn test_colour_matching_functions_peak_at_the_expected_wavelengths() {
+        let peak = |component: fn(Vec3) -> f64, from: f64, to: f64| {
+            (0..1000).map(|i| from + (to - from) * i as f64 / 1000.0)
+                .max_by(|a, b| component(colour_matching(*a)).partial_cmp(&component(colour_matching(*b))).unwrap()).unwrap()
+        };
+        assert!((peak(|c| c.x, 500.0, 700.0) - 599.0).abs() < 5.0);
+        assert!((peak(|c| c.y, 450.0, 700.0) - 555.0).abs() < 5.0);
+        assert!((peak(|c| c.z, 380.0, 550.0) - 445.0).abs() < 5.0);
+    }
+}
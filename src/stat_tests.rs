@@ -0,0 +1,200 @@
+use crate::hitable::HitRecord;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::{bsdf_dimension, Sampler};
+use crate::spectrum::Wavelengths;
+use crate::vec3::Vec3;
+
+// Statistical helpers for tests that check sampling routines produce the distribution they claim.
+
+// Bins with fewer expected samples than this are pooled, as the chi-square approximation is poor
+// for them.
+const MINIMUM_EXPECTED_FREQUENCY: f64 = 5.0;
+
+// Pearson's chi-square goodness of fit test of observed bin counts against expected counts.
+// Returns an error describing the mismatch if the observed counts are unlikely, at the given
+// significance level, to have come from the expected distribution.
+pub fn chi_square_test(observed: &[f64], expected: &[f64], significance: f64) -> Result<(), String> {
+    let mut statistic = 0.0;
+    let mut degrees_of_freedom = 0;
+    let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+
+    for (o, e) in observed.iter().zip(expected.iter()) {
+        if *e < MINIMUM_EXPECTED_FREQUENCY {
+            if *e == 0.0 && *o > 0.0 {
+                return Err(format!("{} samples fell in a bin where none were expected", o));
+            }
+            pooled_observed += o;
+            pooled_expected += e;
+        }
+        else {
+            statistic += (o - e).powi(2) / e;
+            degrees_of_freedom += 1;
+        }
+    }
+    if pooled_expected > 0.0 {
+        statistic += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+        degrees_of_freedom += 1;
+    }
+    // One degree of freedom is lost because the total count is fixed.
+    degrees_of_freedom -= 1;
+
+    let p_value = chi_square_p_value(statistic, degrees_of_freedom as f64);
+    return if p_value < significance {
+        Err(format!("chi-square statistic {:.1} with {} degrees of freedom has p-value {:.2e}", statistic, degrees_of_freedom, p_value))
+    }
+    else { Ok(()) };
+}
+
+// Probability of a chi-square distributed variable with k degrees of freedom exceeding x, using
+// the Wilson-Hilferty approximation, which is accurate for the large numbers of bins used in tests.
+pub fn chi_square_p_value(x: f64, k: f64) -> f64 {
+    let variance = 2.0 / (9.0 * k);
+    let z = ((x / k).cbrt() - (1.0 - variance)) / variance.sqrt();
+    return 0.5 * erfc(z / std::f64::consts::SQRT_2);
+}
+
+// Complementary error function, from Numerical Recipes, with a relative error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 + t * (-0.18628806
+        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    return if x >= 0.0 { r } else { 2.0 - r };
+}
+
+// Bins directions on the unit sphere by cos(theta) and phi, which gives bins of equal solid angle.
+pub struct SphereHistogram {
+    pub theta_bins: usize,
+    pub phi_bins: usize,
+}
+
+impl SphereHistogram {
+
+    pub fn bin_count(&self) -> usize {
+        self.theta_bins * self.phi_bins
+    }
+
+    pub fn bin(&self, direction: Vec3) -> usize {
+        let d = direction.unit_vector();
+        let z_bin = (((d.z + 1.0) / 2.0 * self.theta_bins as f64) as usize).min(self.theta_bins - 1);
+        let phi = d.y.atan2(d.x) + std::f64::consts::PI;
+        let phi_bin = ((phi / (2.0 * std::f64::consts::PI) * self.phi_bins as f64) as usize).min(self.phi_bins - 1);
+        return z_bin * self.phi_bins + phi_bin;
+    }
+
+    // Count the number of directions falling in each bin.
+    pub fn observed(&self, directions: &[Vec3]) -> Vec<f64> {
+        let mut counts = vec![0.0; self.bin_count()];
+        for direction in directions {
+            counts[self.bin(*direction)] += 1.0;
+        }
+        return counts;
+    }
+
+    // Expected number of samples in each bin, found by integrating a solid angle density over
+    // each bin with the midpoint rule.
+    pub fn expected<F>(&self, samples: usize, pdf: F) -> Vec<f64>
+        where F: Fn(Vec3) -> f64
+    {
+        const RESOLUTION: usize = 16;
+        let bin_height = 2.0 / self.theta_bins as f64;
+        let bin_width = 2.0 * std::f64::consts::PI / self.phi_bins as f64;
+        let cell_area = bin_height * bin_width / (RESOLUTION * RESOLUTION) as f64;
+
+        let mut expected = vec![0.0; self.bin_count()];
+        for z_bin in 0..self.theta_bins {
+            for phi_bin in 0..self.phi_bins {
+                let mut integral = 0.0;
+                for j in 0..RESOLUTION {
+                    for i in 0..RESOLUTION {
+                        let z = -1.0 + bin_height * (z_bin as f64 + (j as f64 + 0.5) / RESOLUTION as f64);
+                        let phi = bin_width * (phi_bin as f64 + (i as f64 + 0.5) / RESOLUTION as f64) - std::f64::consts::PI;
+                        let r = (1.0 - z * z).max(0.0).sqrt();
+                        integral += pdf(Vec3::new(r * phi.cos(), r * phi.sin(), z)) * cell_area;
+                    }
+                }
+                expected[z_bin * self.phi_bins + phi_bin] = integral * samples as f64;
+            }
+        }
+        return expected;
+    }
+}
+
+// Number of rays scattered by the material tests. The tests are deterministic, so a low
+// significance level only guards against an unlucky seed.
+pub const SAMPLES: usize = 100000;
+pub const SIGNIFICANCE: f64 = 1e-3;
+
+// A hit on a surface facing up the z axis, by a ray arriving at 45 degrees.
+pub fn hit(material: &Material) -> (Ray, HitRecord<'_>) {
+    let ray = Ray { origin: Vec3::new(-1.0, 0.0, 1.0), direction: Vec3::new(1.0, 0.0, -1.0), wavelengths: Wavelengths::Rgb };
+    let hit = HitRecord { t: 1.0, p: Vec3::new(0.0, 0.0, 0.0), normal: Vec3::new(0.0, 0.0, 1.0), shading_normal: Vec3::new(0.0, 0.0, 1.0), uv: (0.5, 0.5), dpdu: Vec3::new(1.0, 0.0, 0.0), dpdv: Vec3::new(0.0, 1.0, 0.0), material, material_id: 0, object_id: 0 };
+    (ray, hit)
+}
+
+// Directions of the rays the material scatters from hit(), leaving out those it absorbs.
+pub fn scattered_directions(material: &Material) -> Vec<Vec3> {
+    let (ray, hit) = hit(material);
+    let mut sampler = Sampler::independent(1);
+    (0..SAMPLES).filter_map(|i| {
+        sampler.start_pixel_sample(0, 0, i as u32);
+        sampler.start_dimension(bsdf_dimension(0));
+        material.scatter(&ray, &hit, &mut sampler).map(|scatter| scatter.ray.direction)
+    }).collect()
+}
+
+// Check with a chi-square test that the directions the material scatters follow its pdf.
+pub fn check_scattering_matches_pdf(material: &Material, histogram: SphereHistogram) {
+    let (ray, hit) = hit(material);
+    let observed = histogram.observed(&scattered_directions(material));
+    let expected = histogram.expected(SAMPLES, |direction| material.pdf(&ray, &hit, direction));
+    if let Err(message) = chi_square_test(&observed, &expected, SIGNIFICANCE) {
+        panic!("{:?} doesn't scatter according to its pdf: {}", material, message);
+    }
+}
+
+// Average of the light left by scatter, counting absorbed rays as leaving none.
+pub fn average_attenuation(material: &Material, ray: &Ray, hit: &HitRecord) -> Vec3 {
+    let mut sampler = Sampler::independent(1);
+    (0..SAMPLES).fold(Vec3::new(0.0, 0.0, 0.0), |total, i| {
+        sampler.start_pixel_sample(0, 0, i as u32);
+        sampler.start_dimension(bsdf_dimension(0));
+        total + material.scatter(ray, hit, &mut sampler).map_or(Vec3::new(0.0, 0.0, 0.0), |scatter| scatter.attenuation)
+    }) / SAMPLES as f64
+}
+
+// Integral of bsdf * |cos| over the sphere, with the midpoint rule in cells of equal solid
+// angle, which is the light that scatter should leave on average.
+pub fn integrate_bsdf(material: &Material, ray: &Ray, hit: &HitRecord) -> Vec3 {
+    let (z_steps, phi_steps) = (800, 800);
+    let cell = 4.0 * std::f64::consts::PI / (z_steps * phi_steps) as f64;
+    (0..z_steps * phi_steps).fold(Vec3::new(0.0, 0.0, 0.0), |total, i| {
+        let z = -1.0 + 2.0 * ((i / phi_steps) as f64 + 0.5) / z_steps as f64;
+        let phi = 2.0 * std::f64::consts::PI * ((i % phi_steps) as f64 + 0.5) / phi_steps as f64;
+        let r = (1.0 - z * z).sqrt();
+        total + material.bsdf(ray, hit, Vec3::new(r * phi.cos(), r * phi.sin(), z)) * z.abs() * cell
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chi_square_p_value_matches_tabulated_values() {
+        // The 5% critical values for 10 and 100 degrees of freedom.
+        assert!((chi_square_p_value(18.307, 10.0) - 0.05).abs() < 0.002);
+        assert!((chi_square_p_value(124.342, 100.0) - 0.05).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_chi_square_test_rejects_a_skewed_distribution() {
+        let expected = vec![100.0; 20];
+        let mut observed = expected.clone();
+        assert!(chi_square_test(&observed, &expected, 0.01).is_ok());
+        observed[0] += 60.0;
+        observed[1] -= 60.0;
+        assert!(chi_square_test(&observed, &expected, 0.01).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::cell::Cell;
+use std::fmt::{Display, Error, Formatter};
+use std::time::Duration;
+
+// Statistics gathered while rendering. Counters are kept per thread, so that the code deep inside
+// ray tracing can record events without locking or passing a context around. Each tile takes the
+// counters of the thread that rendered it once it is complete, and the results are combined into
+// a RenderStats for the whole pass.
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Counters {
+    pub primary_rays: u64,
+    pub secondary_rays: u64,
+    // How paths ended.
+    pub escaped: u64,
+    pub max_depth: u64,
+    pub absorbed: u64,
+    pub intersection_tests: u64,
+    pub bvh_nodes_visited: u64,
+}
+
+impl Counters {
+    pub fn merge(&mut self, other: &Counters) {
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.escaped += other.escaped;
+        self.max_depth += other.max_depth;
+        self.absorbed += other.absorbed;
+        self.intersection_tests += other.intersection_tests;
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+    }
+}
+
+// Why a path stopped being traced.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Termination {
+    // The path left the scene and picked up the background.
+    Escaped,
+    // The path reached the maximum number of bounces.
+    MaxDepth,
+    // The path was absorbed by a surface.
+    Absorbed,
+}
+
+// The counters for a single thread. Each counter is a separate cell so that recording an event
+// only touches the value being updated, which matters for frequent events like intersection tests.
+struct ThreadCounters {
+    primary_rays: Cell<u64>,
+    secondary_rays: Cell<u64>,
+    escaped: Cell<u64>,
+    max_depth: Cell<u64>,
+    absorbed: Cell<u64>,
+    intersection_tests: Cell<u64>,
+    bvh_nodes_visited: Cell<u64>,
+}
+
+thread_local! {
+    static COUNTERS: ThreadCounters = const {
+        ThreadCounters {
+            primary_rays: Cell::new(0),
+            secondary_rays: Cell::new(0),
+            escaped: Cell::new(0),
+            max_depth: Cell::new(0),
+            absorbed: Cell::new(0),
+            intersection_tests: Cell::new(0),
+            bvh_nodes_visited: Cell::new(0),
+        }
+    };
+}
+
+fn add<F>(counter: F, n: u64) where F: Fn(&ThreadCounters) -> &Cell<u64> {
+    COUNTERS.with(|counters| {
+        let cell = counter(counters);
+        cell.set(cell.get() + n);
+    });
+}
+
+fn increment<F>(counter: F) where F: Fn(&ThreadCounters) -> &Cell<u64> {
+    add(counter, 1);
+}
+
+pub fn record_primary_ray() { increment(|c| &c.primary_rays) }
+
+pub fn record_secondary_ray() { increment(|c| &c.secondary_rays) }
+
+pub fn record_termination(termination: Termination) {
+    match termination {
+        Termination::Escaped => increment(|c| &c.escaped),
+        Termination::MaxDepth => increment(|c| &c.max_depth),
+        Termination::Absorbed => increment(|c| &c.absorbed),
+    }
+}
+
+// Intersection tests are frequent enough that recording them one at a time is noticeably slow, so
+// aggregates record the number of objects they test in one go.
+pub fn record_intersection_tests(n: u64) { add(|c| &c.intersection_tests, n) }
+
+pub fn record_bvh_node_visit() { increment(|c| &c.bvh_nodes_visited) }
+
+// Return the counters recorded on the current thread, resetting them to zero.
+pub fn take_counters() -> Counters {
+    COUNTERS.with(|c| Counters {
+        primary_rays: c.primary_rays.take(),
+        secondary_rays: c.secondary_rays.take(),
+        escaped: c.escaped.take(),
+        max_depth: c.max_depth.take(),
+        absorbed: c.absorbed.take(),
+        intersection_tests: c.intersection_tests.take(),
+        bvh_nodes_visited: c.bvh_nodes_visited.take(),
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderStats {
+    pub counters: Counters,
+    // Wall clock time spent rendering.
+    pub elapsed: Duration,
+    // Time each worker thread spent rendering tiles, indexed by the thread's index in the pool.
+    pub thread_times: Vec<Duration>,
+}
+
+impl RenderStats {
+
+    pub fn merge(&mut self, other: &RenderStats) {
+        self.counters.merge(&other.counters);
+        self.elapsed += other.elapsed;
+        self.add_thread_times(&other.thread_times);
+    }
+
+    pub fn add_thread_time(&mut self, thread: usize, time: Duration) {
+        if self.thread_times.len() <= thread {
+            self.thread_times.resize(thread + 1, Duration::default());
+        }
+        self.thread_times[thread] += time;
+    }
+
+    fn add_thread_times(&mut self, times: &[Duration]) {
+        for (thread, time) in times.iter().enumerate() {
+            self.add_thread_time(thread, *time);
+        }
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.counters.primary_rays + self.counters.secondary_rays
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        return if seconds > 0.0 { self.total_rays() as f64 / seconds } else { 0.0 };
+    }
+
+    // Average number of rays traced per path, including the camera ray.
+    pub fn average_path_length(&self) -> f64 {
+        return if self.counters.primary_rays == 0 { 0.0 }
+        else { self.total_rays() as f64 / self.counters.primary_rays as f64 };
+    }
+
+    pub fn intersection_tests_per_ray(&self) -> f64 {
+        return if self.total_rays() == 0 { 0.0 }
+        else { self.counters.intersection_tests as f64 / self.total_rays() as f64 };
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let c = &self.counters;
+        let paths = (c.escaped + c.max_depth + c.absorbed).max(1) as f64;
+        let percent = |n: u64| 100.0 * n as f64 / paths;
+
+        writeln!(f, "Render statistics")?;
+        writeln!(f, "  Time:                     {:.2}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "  Primary rays:             {}", c.primary_rays)?;
+        writeln!(f, "  Secondary rays:           {}", c.secondary_rays)?;
+        writeln!(f, "  Rays per second:          {:.0}", self.rays_per_second())?;
+        writeln!(f, "  Average path length:      {:.2}", self.average_path_length())?;
+        writeln!(f, "  Paths escaped:            {} ({:.1}%)", c.escaped, percent(c.escaped))?;
+        writeln!(f, "  Paths at maximum depth:   {} ({:.1}%)", c.max_depth, percent(c.max_depth))?;
+        writeln!(f, "  Paths absorbed:           {} ({:.1}%)", c.absorbed, percent(c.absorbed))?;
+        writeln!(f, "  Intersection tests/ray:   {:.1}", self.intersection_tests_per_ray())?;
+        // Only meshes have a BVH, so scenes without them don't visit any nodes.
+        if c.bvh_nodes_visited > 0 {
+            writeln!(f, "  Mesh BVH nodes visited:   {}", c.bvh_nodes_visited)?;
+        }
+        if let Some(peak) = peak_memory() {
+            writeln!(f, "  Peak memory:              {:.1} MiB", peak as f64 / (1024.0 * 1024.0))?;
+        }
+        for (thread, time) in self.thread_times.iter().enumerate() {
+            writeln!(f, "  Thread {:<3} time:          {:.2}s", thread, time.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+// Peak resident memory of the process in bytes, where the platform reports it.
+pub fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    return Some(kilobytes * 1024);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_counters_returns_and_resets_the_thread_counters() {
+        take_counters();
+        record_primary_ray();
+        record_secondary_ray();
+        record_secondary_ray();
+        record_termination(Termination::Escaped);
+        record_intersection_tests(3);
+
+        let counters = take_counters();
+        assert_eq!(counters.primary_rays, 1);
+        assert_eq!(counters.secondary_rays, 2);
+        assert_eq!(counters.escaped, 1);
+        assert_eq!(counters.intersection_tests, 3);
+        assert_eq!(take_counters(), Counters::default());
+    }
+
+    #[test]
+    fn test_merge_adds_thread_times_by_index() {
+        let mut stats = RenderStats::default();
+        stats.add_thread_time(1, Duration::from_secs(2));
+        let mut other = RenderStats::default();
+        other.add_thread_time(0, Duration::from_secs(1));
+        other.add_thread_time(1, Duration::from_secs(1));
+        stats.merge(&other);
+        assert_eq!(stats.thread_times, vec![Duration::from_secs(1), Duration::from_secs(3)]);
+    }
+
+    #[test]
+    fn test_bvh_nodes_are_only_reported_when_some_were_visited() {
+        let mut stats = RenderStats::default();
+        assert!(!stats.to_string().contains("BVH"));
+        stats.counters.bvh_nodes_visited = 12;
+        assert!(stats.to_string().contains("Mesh BVH nodes visited:   12"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt::{Debug, Error, Formatter};
+use std::sync::Arc;
+
+use crate::hitable::HitRecord;
+use crate::output::read_png;
+use crate::vec3::Vec3;
+
+// A value that varies over a surface, looked up by the uv coordinates of a hit. Colours and scalar
+// parameters of materials are both given by textures, with scalars taken from the average of the
+// three components.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Texture {
+    Constant(Vec3),
+    // Squares alternating between two values, with scale squares to each unit of u and v.
+    Checker { even: Vec3, odd: Vec3, scale: f64 },
+    Image(Arc<ImageTexture>),
+}
+
+impl Texture {
+
+    pub fn constant(r: f64, g: f64, b: f64) -> Texture {
+        Texture::Constant(Vec3::new(r, g, b))
+    }
+
+    pub fn scalar(value: f64) -> Texture {
+        Texture::Constant(Vec3::new(value, value, value))
+    }
+
+    pub fn checker(even: Vec3, odd: Vec3, scale: f64) -> Texture {
+        Texture::Checker { even, odd, scale }
+    }
+
+    // An image read from a PNG file, which is decoded with the same gamma that renders are
+    // written with.
+    pub fn image(file_name: &str) -> std::io::Result<Texture> {
+        let (width, height, texels) = read_png(file_name)?;
+        return Ok(Texture::Image(Arc::new(ImageTexture { name: file_name.to_string(), width, height, texels })));
+    }
+
+    // An image holding data rather than colours, such as a normal or height map, whose values are
+    // read as they're stored rather than gamma decoded.
+    pub fn data_image(file_name: &str) -> std::io::Result<Texture> {
+        let (width, height, texels) = read_png(file_name)?;
+        let texels = texels.into_iter().map(|t| Vec3::new(t.x.sqrt(), t.y.sqrt(), t.z.sqrt())).collect();
+        return Ok(Texture::Image(Arc::new(ImageTexture { name: file_name.to_string(), width, height, texels })));
+    }
+
+    pub fn value(&self, hit: &HitRecord) -> Vec3 {
+        self.value_at(hit.uv)
+    }
+
+    pub fn scalar_value(&self, hit: &HitRecord) -> f64 {
+        self.scalar_value_at(hit.uv)
+    }
+
+    // The value at the given uv coordinates, for looking textures up away from a hit, such as at
+    // the vertices of a mesh.
+    pub fn value_at(&self, uv: (f64, f64)) -> Vec3 {
+        match *self {
+            Texture::Constant(value) => value,
+            Texture::Checker { even, odd, scale } => {
+                let (u, v) = uv;
+                let square = (u * scale).floor() as i64 + (v * scale).floor() as i64;
+                if square.rem_euclid(2) == 0 { even } else { odd }
+            }
+            Texture::Image(ref image) => image.value(uv),
+        }
+    }
+
+    pub fn scalar_value_at(&self, uv: (f64, f64)) -> f64 {
+        let value = self.value_at(uv);
+        return average(value);
+    }
+}
+
+fn average(value: Vec3) -> f64 {
+    (value.x + value.y + value.z) / 3.0
+}
+
+// Linear colours stored bottom row first, so that v increases up the image.
+#[derive(PartialEq)]
+pub struct ImageTexture {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Vec3>,
+}
+
+impl ImageTexture {
+
+    // Bilinearly interpolate the texels around uv, wrapping around the edges so that images tile.
+    pub fn value(&self, uv: (f64, f64)) -> Vec3 {
+        let x = uv.0 * self.width as f64 - 0.5;
+        let y = uv.1 * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |x: f64, y: f64| {
+            let column = (x as i64).rem_euclid(self.width as i64) as usize;
+            let row = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.texels[row * self.width + column]
+        };
+        let bottom = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1.0, y0);
+        let top = (1.0 - fx) * texel(x0, y0 + 1.0) + fx * texel(x0 + 1.0, y0 + 1.0);
+        return (1.0 - fy) * bottom + fy * top;
+    }
+}
+
+// Images are described by name rather than by their texels, which keeps the descriptions of
+// materials short.
+impl Debug for ImageTexture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        return write!(f, "ImageTexture({}, {}x{})", self.name, self.width, self.height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::output::write_image;
+
+    fn hit_at(material: &Material, uv: (f64, f64)) -> HitRecord<'_> {
+        HitRecord { t: 1.0, p: Vec3::new(0.0, 0.0, 0.0), normal: Vec3::new(0.0, 0.0, 1.0), shading_normal: Vec3::new(0.0, 0.0, 1.0), uv, dpdu: Vec3::new(1.0, 0.0, 0.0), dpdv: Vec3::new(0.0, 1.0, 0.0), material, material_id: 0, object_id: 0 }
+    }
+
+    #[test]
+    fn test_checker_alternates_between_squares() {
+        let material = Material::lambertian(1.0, 1.0, 1.0);
+        let (even, odd) = (Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0));
+        let checker = Texture::checker(even, odd, 4.0);
+        assert_eq!(checker.value(&hit_at(&material, (0.1, 0.1))), even);
+        assert_eq!(checker.value(&hit_at(&material, (0.3, 0.1))), odd);
+        assert_eq!(checker.value(&hit_at(&material, (0.3, 0.3))), even);
+        assert_eq!(checker.scalar_value(&hit_at(&material, (0.3, 0.1))), 0.0);
+    }
+
+    #[test]
+    fn test_image_texture_interpolates_between_texels() {
+        let texels = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        let image = ImageTexture { name: "test".to_string(), width: 2, height: 2, texels };
+        // Texel centres return the texels, with the first row at the bottom.
+        assert_eq!(image.value((0.25, 0.25)), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(image.value((0.75, 0.75)), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(image.value((0.5, 0.25)), Vec3::new(0.5, 0.0, 0.0));
+        // The edges wrap around.
+        assert_eq!(image.value((0.0, 0.25)), Vec3::new(0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_image_texture_reads_png_files() {
+        let colours = vec![Vec3::new(0.25, 0.25, 0.25), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        let file_name = std::env::temp_dir().join("raytracer_test_image_texture.png").to_string_lossy().into_owned();
+        write_image(&file_name, 2, 2, &colours).unwrap();
+        let texture = Texture::image(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+
+        let material = Material::lambertian(1.0, 1.0, 1.0);
+        assert!((texture.value(&hit_at(&material, (0.75, 0.25))) - Vec3::new(1.0, 0.0, 0.0)).length() < 0.01);
+        assert!(format!("{:?}", texture).contains("2x2"));
+    }
+}
@@ -160,14 +160,14 @@ mod tests {
     fn test_equality_returns_true_for_two_equal_vectors() {
         let v1 = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
         let v2 = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
-        assert_eq!(v1 == v2, true)
+        assert!(v1 == v2)
     }
 
     #[test]
     fn test_equality_returns_false_for_two_unequal_vectors() {
         let v1 = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
         let v2 = Vec3 { x: 2.0, y: 3.0, z: 4.0 };
-        assert_eq!(v1 == v2, false)
+        assert!(v1 != v2)
     }
 
     #[test]
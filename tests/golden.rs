@@ -0,0 +1,93 @@
+// Render the built-in scenes at a low resolution and compare them against reference images checked
+// in under tests/golden, so that changes which alter the rendered output are caught.
+//
+// When a change is expected to alter the output, regenerate the references by running
+//
+//     UPDATE_GOLDEN=1 cargo test --test golden
+//
+// and check the new images before committing them.
+
+#![allow(clippy::needless_return)]
+
+use std::path::PathBuf;
+
+use raytracer::compare::compare;
+use raytracer::film::Film;
+use raytracer::filter::Filter;
+use raytracer::output::{read_png, write_image};
+use raytracer::progress::QuietProgress;
+use raytracer::integrator::path::PathSettings;
+use raytracer::integrator::Integrator;
+use raytracer::render::{render_pass, RenderSettings};
+use raytracer::sampler::Sampler;
+use raytracer::scene::scene;
+use raytracer::vec3::Vec3;
+
+const WIDTH: usize = 96;
+const HEIGHT: usize = 64;
+const SAMPLES: u64 = 16;
+const SEED: u64 = 0;
+
+// Renders are deterministic, so these only need to allow for the quantisation of the references
+// and for floating point differences between platforms.
+const MINIMUM_PSNR: f64 = 40.0;
+const MINIMUM_SSIM: f64 = 0.99;
+
+fn golden_file_name(golden_name: &str) -> String {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", &format!("{}.png", golden_name)].iter().collect();
+    return path.to_string_lossy().into_owned();
+}
+
+fn render(scene_name: &str, integrator: Integrator) -> Vec<Vec3> {
+    let scene = scene(scene_name, WIDTH as f64 / HEIGHT as f64, SEED).unwrap();
+    let settings = RenderSettings {
+        sampler: Sampler::sobol(SEED),
+        filter: Filter::box_filter(0.5),
+        adaptive: None,
+        denoiser: None,
+        integrator,
+        spectral: false,
+    };
+    let mut film = Film::new(WIDTH, HEIGHT);
+    render_pass(&scene, &mut film, &settings, SAMPLES, &QuietProgress);
+
+    // The references can only store values in [0, 1].
+    let clamp = |v: f64| v.clamp(0.0, 1.0);
+    return film.colours().into_iter().map(|c| Vec3::new(clamp(c.x), clamp(c.y), clamp(c.z))).collect();
+}
+
+// Compare a render of the scene against the reference with the given name.
+fn check_against_golden_image(golden_name: &str, scene_name: &str, integrator: Integrator) {
+    let image = render(scene_name, integrator);
+    let file_name = golden_file_name(golden_name);
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        write_image(&file_name, WIDTH, HEIGHT, &image).unwrap();
+        return;
+    }
+
+    let (width, height, reference) = read_png(&file_name)
+        .unwrap_or_else(|e| panic!("Unable to read {}, set UPDATE_GOLDEN=1 to create it: {}", file_name, e));
+    assert_eq!((width, height), (WIDTH, HEIGHT), "{} has the wrong size", file_name);
+
+    let comparison = compare(&image, &reference, WIDTH, HEIGHT);
+    assert!(
+        comparison.psnr >= MINIMUM_PSNR && comparison.ssim >= MINIMUM_SSIM,
+        "{} scene differs from {}: {:?}", scene_name, file_name, comparison
+    );
+}
+
+#[test]
+fn test_final_scene_matches_golden_image() {
+    check_against_golden_image("final", "final", Integrator::path(PathSettings::default()));
+}
+
+#[test]
+fn test_simple_scene_matches_golden_image() {
+    check_against_golden_image("simple", "simple", Integrator::path(PathSettings::default()));
+}
+
+#[test]
+fn test_caustic_scene_matches_golden_image_with_bidirectional_path_tracing() {
+    check_against_golden_image("caustic_bdpt", "caustic", Integrator::bidirectional(PathSettings::default()));
+}